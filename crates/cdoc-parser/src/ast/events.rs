@@ -0,0 +1,176 @@
+//! Fuses the markdown events from pulldown-cmark with the special elements (shortcodes, math,
+//! code) found by the raw parser, so the AST can be built from a single stream of events.
+
+use crate::common::Span;
+use crate::raw::{Child, Element, ElementInfo, Special};
+use pulldown_cmark::{CowStr, Event, OffsetIter, Options, Parser};
+use std::collections::VecDeque;
+use std::iter::Peekable;
+use std::ops::Range;
+use std::vec;
+
+/// Stands in for special elements in the text given to the markdown parser. Every byte of an
+/// element is replaced by it, so offsets into the masked text are offsets into the source. The
+/// markdown parser treats it like any other word character, which keeps it intact in headings,
+/// table cells, link text and emphasis.
+pub(crate) const MASK: char = '\u{1A}';
+
+/// The markdown of a sequence of raw elements with the special elements masked out.
+pub(crate) struct MaskedSource {
+    pub(crate) text: String,
+    /// Offset of the text in the source.
+    pub(crate) offset: usize,
+    pub(crate) children: Vec<Child>,
+}
+
+impl From<Vec<ElementInfo>> for MaskedSource {
+    fn from(value: Vec<ElementInfo>) -> Self {
+        let offset = value.first().map_or(0, |e| e.span.range.start);
+        let mut text = String::new();
+        let mut children = Vec::new();
+
+        let mut code_idx = 0;
+        let mut command_idx = 0;
+        let mut math_idx = 0;
+
+        for elem in value {
+            match elem.element {
+                Element::Markdown(s) => text.push_str(&s),
+                Element::Special(label, inner) => {
+                    let identifier = match inner {
+                        Special::Math { .. } => {
+                            math_idx += 1;
+                            math_idx - 1
+                        }
+                        Special::CodeBlock { .. } => {
+                            code_idx += 1;
+                            code_idx - 1
+                        }
+                        Special::Command { .. } => {
+                            command_idx += 1;
+                            command_idx - 1
+                        }
+                        Special::Verbatim { .. }
+                        | Special::CodeInline { .. }
+                        | Special::Raw { .. }
+                        | Special::Span { .. }
+                        | Special::Attributes { .. } => 0,
+                    };
+
+                    for _ in elem.span.range.clone() {
+                        text.push(MASK);
+                    }
+
+                    children.push(Child {
+                        elem: inner,
+                        span: elem.span,
+                        label,
+                        identifier,
+                    });
+                }
+            }
+        }
+
+        MaskedSource {
+            text,
+            offset,
+            children,
+        }
+    }
+}
+
+pub(crate) enum DocEvent<'a> {
+    Markdown(Event<'a>, Span),
+    Special(Child),
+}
+
+/// Markdown events with the masked parts of text and html events replaced by the special
+/// elements they stand for.
+pub(crate) struct FusedEvents<'a> {
+    parser: OffsetIter<'a, 'a>,
+    offset: usize,
+    children: Peekable<vec::IntoIter<Child>>,
+    pending: VecDeque<DocEvent<'a>>,
+    /// Spans of the elements that were not part of any text (e.g. in a link destination or
+    /// title), which the events therefore leave out.
+    pub(crate) unplaced: Vec<Span>,
+}
+
+impl<'a> FusedEvents<'a> {
+    pub(crate) fn new(text: &'a str, offset: usize, children: Vec<Child>) -> Self {
+        FusedEvents {
+            parser: Parser::new_ext(text, Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES)
+                .into_offset_iter(),
+            offset,
+            children: children.into_iter().peekable(),
+            pending: VecDeque::new(),
+            unplaced: Vec::new(),
+        }
+    }
+
+    fn split(&mut self, text: &str, range: Range<usize>, kind: fn(CowStr<'a>) -> Event<'a>) {
+        while let Some(child) = self.children.next_if(|c| c.span.range.start < range.start) {
+            self.unplaced.push(child.span);
+        }
+
+        let mut rest = text;
+        let mut pos = range.start;
+        loop {
+            let mask_idx = rest.find(MASK);
+            let (piece, tail) = rest.split_at(mask_idx.unwrap_or(rest.len()));
+
+            if !piece.is_empty() {
+                let end = match mask_idx {
+                    Some(_) => self
+                        .children
+                        .peek()
+                        .map_or(range.end, |c| c.span.range.start),
+                    None => range.end,
+                };
+                self.pending.push_back(DocEvent::Markdown(
+                    kind(piece.to_string().into()),
+                    Span::new(pos, end),
+                ));
+            }
+
+            if mask_idx.is_none() {
+                break;
+            }
+
+            // A run of masks may stand for several adjacent elements.
+            let run = tail.len() - tail.trim_start_matches(MASK).len();
+            let mut covered = 0;
+            while covered < run {
+                match self.children.next_if(|c| c.span.range.start < range.end) {
+                    Some(child) => {
+                        covered += child.span.range.len();
+                        pos = child.span.range.end;
+                        self.pending.push_back(DocEvent::Special(child));
+                    }
+                    None => break,
+                }
+            }
+            rest = &tail[run..];
+        }
+    }
+}
+
+impl<'a> Iterator for FusedEvents<'a> {
+    type Item = DocEvent<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            let Some((event, range)) = self.parser.next() else {
+                self.unplaced.extend(self.children.by_ref().map(|c| c.span));
+                return None;
+            };
+            let range = range.start + self.offset..range.end + self.offset;
+            match event {
+                Event::Text(s) if s.contains(MASK) => self.split(&s, range, Event::Text),
+                Event::Html(s) if s.contains(MASK) => self.split(&s, range, Event::Html),
+                event => return Some(DocEvent::Markdown(event, Span::new(range.start, range.end))),
+            }
+        }
+        self.pending.pop_front()
+    }
+}
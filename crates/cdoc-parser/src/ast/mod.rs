@@ -1,10 +1,14 @@
+mod events;
 pub mod parser;
+pub mod printer;
+pub mod query;
 pub mod visitor;
 
 use crate::code_ast::types::CodeContent;
 use crate::common::Span;
+use crate::raw::Attributes;
 use cowstr::CowStr;
-use pulldown_cmark::LinkType;
+use pulldown_cmark::{Alignment, LinkType};
 use serde::{Deserialize, Serialize};
 
 use linked_hash_map::LinkedHashMap;
@@ -31,8 +35,8 @@ pub struct CodeBlock {
     pub label: Option<CowStr>,
     /// Code source
     pub source: CodeContent,
-    /// Code tags
-    pub attributes: Vec<CowStr>,
+    /// Attributes given after the opening fence
+    pub attributes: Attributes,
     /// Display the block as a cell or listing (only used for notebooks)
     pub display_cell: bool,
     pub global_idx: usize,
@@ -47,30 +51,90 @@ pub struct Math {
     pub span: Span,
 }
 
+/// Content that is passed through as is, but only to the output format it targets.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RawContent {
+    /// Name of the target format or template prefix, e.g. `html` or `latex`.
+    pub format: CowStr,
+    pub source: CowStr,
+    pub display_block: bool,
+    pub span: Span,
+}
+
+/// Inline elements. Every variant carries the [Span] of its source, either directly or in its
+/// inner struct.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Inline {
     /// Plain text
-    Text(CowStr),
-    Styled(Vec<Inline>, Style),
+    Text(CowStr, Span),
+    Styled(Vec<Inline>, Style, Span),
     /// Inline code
-    Code(CowStr),
+    Code(CowStr, Span),
     /// A code block. May originate from markdown fenced code blocks or notebook code cells.
     CodeBlock(CodeBlock),
-    SoftBreak,
-    HardBreak,
+    SoftBreak(Span),
+    HardBreak(Span),
     /// Horizontal rule
-    Rule,
+    Rule(Span),
     /// An inline image (usually originates from a markdown image spec)
-    Image(LinkType, CowStr, CowStr, Vec<Inline>),
+    Image(LinkType, CowStr, CowStr, Vec<Inline>, Span),
     /// An inline link (usually originates from a markdown link spec)
-    Link(LinkType, CowStr, CowStr, Vec<Inline>),
+    Link(LinkType, CowStr, CowStr, Vec<Inline>, Span),
     /// Unescaped html.
-    Html(CowStr),
+    Html(CowStr, Span),
+    /// Raw content for a specific output format.
+    Raw(RawContent),
+    /// A bracketed span, i.e. inline content with attributes such as `[text]{.class #id}`.
+    Span(Vec<Inline>, Attributes, Span),
     /// Math element (may be inline or display)
     /// The trailing space element is necessary due to the way parsing currently works with
     /// pulldown_cmark.
     Math(Math),
     Command(Command),
+    /// A reference to a footnote definition with the given label.
+    FootnoteReference(CowStr, Span),
+}
+
+impl Inline {
+    pub fn span(&self) -> &Span {
+        match self {
+            Inline::Text(_, span)
+            | Inline::Styled(_, _, span)
+            | Inline::Code(_, span)
+            | Inline::SoftBreak(span)
+            | Inline::HardBreak(span)
+            | Inline::Rule(span)
+            | Inline::Image(_, _, _, _, span)
+            | Inline::Link(_, _, _, _, span)
+            | Inline::Html(_, span)
+            | Inline::Span(_, _, span)
+            | Inline::FootnoteReference(_, span) => span,
+            Inline::CodeBlock(block) => &block.span,
+            Inline::Raw(raw) => &raw.span,
+            Inline::Math(math) => &math.span,
+            Inline::Command(cmd) => &cmd.span,
+        }
+    }
+
+    pub fn span_mut(&mut self) -> &mut Span {
+        match self {
+            Inline::Text(_, span)
+            | Inline::Styled(_, _, span)
+            | Inline::Code(_, span)
+            | Inline::SoftBreak(span)
+            | Inline::HardBreak(span)
+            | Inline::Rule(span)
+            | Inline::Image(_, _, _, _, span)
+            | Inline::Link(_, _, _, _, span)
+            | Inline::Html(_, span)
+            | Inline::Span(_, _, span)
+            | Inline::FootnoteReference(_, span) => span,
+            Inline::CodeBlock(block) => &mut block.span,
+            Inline::Raw(raw) => &mut raw.span,
+            Inline::Math(math) => &mut math.span,
+            Inline::Command(cmd) => &mut cmd.span,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -109,22 +173,139 @@ pub enum Value {
     String(CowStr),
 }
 
+/// Block elements. Every variant carries the [Span] of its source, either directly or in its
+/// inner struct.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Block {
     Heading {
         lvl: u8,
         id: Option<CowStr>,
         classes: Vec<CowStr>,
+        /// Key-value attributes given in the attribute block of the heading, e.g.
+        /// `# Title {.wide level=intro}`.
+        attributes: LinkedHashMap<CowStr, CowStr>,
         inner: Vec<Inline>,
+        span: Span,
     },
-    Plain(Vec<Inline>),
-    Paragraph(Vec<Inline>),
-    BlockQuote(Vec<Inline>),
+    Plain(Vec<Inline>, Span),
+    Paragraph(Vec<Inline>, Span),
+    BlockQuote(Vec<Block>, Span),
+    /// A GitHub-style callout, written as a block quote starting with a marker like `[!NOTE]`.
+    Admonition(AdmonitionKind, Vec<Block>, Span),
     /// A list - ordered or unordered.
-    List(Option<u64>, Vec<Block>),
-    ListItem(Vec<Block>),
+    List(Option<u64>, Vec<Block>, Span),
+    ListItem(Vec<Block>, Span),
+    /// A table. The alignment is given per column.
+    Table {
+        alignments: Vec<Alignment>,
+        header: TableRow,
+        rows: Vec<TableRow>,
+        span: Span,
+    },
+    /// A footnote definition. The label is used to match the definition with its references.
+    FootnoteDefinition(CowStr, Vec<Block>, Span),
+    /// A code block that stands on its own, e.g. a fenced code block or a notebook code cell.
+    CodeBlock(CodeBlock),
+    /// Display math that stands on its own.
+    Math(Math),
+    /// A raw block for a specific output format.
+    Raw(RawContent),
+    /// A shortcode that stands on its own line, usually one with a body.
+    Command(Command),
+}
+
+impl Block {
+    pub fn span(&self) -> &Span {
+        match self {
+            Block::Heading { span, .. }
+            | Block::Plain(_, span)
+            | Block::Paragraph(_, span)
+            | Block::BlockQuote(_, span)
+            | Block::Admonition(_, _, span)
+            | Block::List(_, _, span)
+            | Block::ListItem(_, span)
+            | Block::Table { span, .. }
+            | Block::FootnoteDefinition(_, _, span) => span,
+            Block::CodeBlock(block) => &block.span,
+            Block::Math(math) => &math.span,
+            Block::Raw(raw) => &raw.span,
+            Block::Command(cmd) => &cmd.span,
+        }
+    }
+
+    pub fn span_mut(&mut self) -> &mut Span {
+        match self {
+            Block::Heading { span, .. }
+            | Block::Plain(_, span)
+            | Block::Paragraph(_, span)
+            | Block::BlockQuote(_, span)
+            | Block::Admonition(_, _, span)
+            | Block::List(_, _, span)
+            | Block::ListItem(_, span)
+            | Block::Table { span, .. }
+            | Block::FootnoteDefinition(_, _, span) => span,
+            Block::CodeBlock(block) => &mut block.span,
+            Block::Math(math) => &mut math.span,
+            Block::Raw(raw) => &mut raw.span,
+            Block::Command(cmd) => &mut cmd.span,
+        }
+    }
+}
+
+/// Calls `f` with the span of every node in the tree, including shortcode parameters.
+pub fn for_each_span_mut(blocks: &mut [Block], f: &mut impl FnMut(&mut Span)) {
+    for block in blocks {
+        f(block.span_mut());
+        match block {
+            Block::Heading { inner, .. } | Block::Plain(inner, _) | Block::Paragraph(inner, _) => {
+                inline_spans_mut(inner, f)
+            }
+            Block::BlockQuote(blocks, _)
+            | Block::Admonition(_, blocks, _)
+            | Block::List(_, blocks, _)
+            | Block::ListItem(blocks, _)
+            | Block::FootnoteDefinition(_, blocks, _) => for_each_span_mut(blocks, f),
+            Block::Table { header, rows, .. } => std::iter::once(header)
+                .chain(rows.iter_mut())
+                .flatten()
+                .for_each(|cell| inline_spans_mut(cell, f)),
+            Block::Command(cmd) => command_spans_mut(cmd, f),
+            Block::CodeBlock(_) | Block::Math(_) | Block::Raw(_) => {}
+        }
+    }
+}
+
+fn inline_spans_mut(inlines: &mut [Inline], f: &mut impl FnMut(&mut Span)) {
+    for inline in inlines {
+        f(inline.span_mut());
+        match inline {
+            Inline::Styled(inner, _, _)
+            | Inline::Span(inner, _, _)
+            | Inline::Image(_, _, _, inner, _)
+            | Inline::Link(_, _, _, inner, _) => inline_spans_mut(inner, f),
+            Inline::Command(cmd) => command_spans_mut(cmd, f),
+            _ => {}
+        }
+    }
+}
+
+fn command_spans_mut(cmd: &mut Command, f: &mut impl FnMut(&mut Span)) {
+    for param in &mut cmd.parameters {
+        f(&mut param.span);
+        if let Value::Content(blocks) = &mut param.value {
+            for_each_span_mut(blocks, f);
+        }
+    }
+    if let Some(body) = &mut cmd.body {
+        for_each_span_mut(body, f);
+    }
 }
 
+/// A table row consisting of one cell per column.
+pub type TableRow = Vec<TableCell>;
+/// The content of a single table cell.
+pub type TableCell = Vec<Inline>;
+
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct CodeMeta {
     pub id: String,
@@ -133,6 +314,42 @@ pub struct CodeMeta {
     pub custom: LinkedHashMap<String, String>,
 }
 
+/// The kind of an admonition, given by its marker (e.g. `[!WARNING]`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AdmonitionKind {
+    Note,
+    Tip,
+    Important,
+    Warning,
+    Caution,
+}
+
+impl AdmonitionKind {
+    /// Parses a marker of the form `[!KIND]` (case-insensitive).
+    pub fn from_marker(marker: &str) -> Option<Self> {
+        let kind = marker.strip_prefix("[!")?.strip_suffix(']')?;
+        match kind.to_lowercase().as_str() {
+            "note" => Some(AdmonitionKind::Note),
+            "tip" => Some(AdmonitionKind::Tip),
+            "important" => Some(AdmonitionKind::Important),
+            "warning" => Some(AdmonitionKind::Warning),
+            "caution" => Some(AdmonitionKind::Caution),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AdmonitionKind::Note => "note",
+            AdmonitionKind::Tip => "tip",
+            AdmonitionKind::Important => "important",
+            AdmonitionKind::Warning => "warning",
+            AdmonitionKind::Caution => "caution",
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Style {
     Emphasis,
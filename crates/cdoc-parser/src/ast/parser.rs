@@ -1,16 +1,18 @@
 use crate::ast::*;
 
+use crate::ast::events::{DocEvent, FusedEvents, MaskedSource};
+use crate::common::{Diagnostic, Span};
 use crate::raw;
-use crate::raw::{Child, ComposedMarkdown, Special};
+use crate::raw::{Child, ElementInfo, Special};
 use anyhow::anyhow;
 use cowstr::ToCowStr;
-use pulldown_cmark::{Event, HeadingLevel, Parser as MdParser, Tag};
-use regex::Regex;
-use std::str::FromStr;
+use pulldown_cmark::{Event, HeadingLevel, Tag};
 
 pub(crate) enum InnerContent {
     Blocks(Vec<Block>),
     Inlines(Vec<Inline>),
+    Rows(Vec<TableRow>),
+    Cells(TableRow),
 }
 
 impl InnerContent {
@@ -38,6 +40,38 @@ impl InnerContent {
         }
     }
 
+    pub(crate) fn into_rows(self) -> Vec<TableRow> {
+        if let InnerContent::Rows(r) = self {
+            r
+        } else {
+            panic!("Expected table rows")
+        }
+    }
+
+    pub(crate) fn into_cells(self) -> TableRow {
+        if let InnerContent::Cells(c) = self {
+            c
+        } else {
+            panic!("Expected table cells")
+        }
+    }
+
+    pub(crate) fn rows_mut(&mut self) -> anyhow::Result<&mut Vec<TableRow>> {
+        if let InnerContent::Rows(r) = self {
+            Ok(r)
+        } else {
+            Err(anyhow!("Expected table element"))
+        }
+    }
+
+    pub(crate) fn cells_mut(&mut self) -> anyhow::Result<&mut TableRow> {
+        if let InnerContent::Cells(c) = self {
+            Ok(c)
+        } else {
+            Err(anyhow!("Expected table row element"))
+        }
+    }
+
     #[allow(unused)]
     fn inlines_mut(&mut self) -> anyhow::Result<&mut Vec<Inline>> {
         if let InnerContent::Inlines(i) = self {
@@ -49,29 +83,35 @@ impl InnerContent {
 
     pub(crate) fn push_inline(&mut self, item: Inline) {
         match self {
-            InnerContent::Blocks(b) => b.push(Block::Plain(vec![item])),
+            InnerContent::Blocks(b) => {
+                if is_block_element(std::slice::from_ref(&item), 0) {
+                    b.push(into_block(item))
+                } else {
+                    let span = item.span().clone();
+                    b.push(Block::Plain(vec![item], span))
+                }
+            }
             InnerContent::Inlines(i) => i.push(item),
+            InnerContent::Rows(_) | InnerContent::Cells(_) => {
+                panic!("Inline elements must be placed in a table cell")
+            }
         }
     }
 }
 
-impl From<raw::Value> for Value {
-    fn from(value: raw::Value) -> Self {
-        match value {
-            raw::Value::Flag(f) => Value::Flag(f),
-            raw::Value::Content(c) => Value::Content(ComposedMarkdown::from(c).into()),
-            raw::Value::String(s) => Value::String(s),
-        }
+fn parse_value(value: raw::Value, diagnostics: &mut Vec<Diagnostic>) -> Value {
+    match value {
+        raw::Value::Flag(f) => Value::Flag(f),
+        raw::Value::Content(c) => Value::Content(parse_elements(c, diagnostics)),
+        raw::Value::String(s) => Value::String(s),
     }
 }
 
-impl From<raw::Parameter> for Parameter {
-    fn from(value: raw::Parameter) -> Self {
-        Parameter {
-            key: value.key,
-            value: value.value.into(),
-            span: value.span,
-        }
+fn parse_parameter(value: raw::Parameter, diagnostics: &mut Vec<Diagnostic>) -> Parameter {
+    Parameter {
+        key: value.key,
+        value: parse_value(value.value, diagnostics),
+        span: value.span,
     }
 }
 
@@ -88,177 +128,468 @@ impl From<raw::Parameter> for Parameter {
 //     }
 // }
 
-impl From<Child> for Inline {
-    fn from(value: Child) -> Self {
-        match value.elem {
-            Special::Math { inner, is_block } => Inline::Math(Math {
-                label: value.label,
-                source: inner,
-                display_block: is_block,
-                span: value.span,
-            }),
-            Special::CodeBlock {
-                inner, attributes, ..
-            } => Inline::CodeBlock(CodeBlock {
-                label: value.label,
-                source: inner,
-                attributes,
-                display_cell: false,
-                global_idx: value.identifier,
-                span: value.span,
-            }),
-            Special::CodeInline { inner } => Inline::Code(inner),
-            Special::Command {
+fn parse_child(value: Child, diagnostics: &mut Vec<Diagnostic>) -> Inline {
+    match value.elem {
+        Special::Math { inner, is_block } => Inline::Math(Math {
+            label: value.label,
+            source: inner,
+            display_block: is_block,
+            span: value.span,
+        }),
+        Special::CodeBlock {
+            inner, attributes, ..
+        } => Inline::CodeBlock(CodeBlock {
+            label: value.label,
+            source: inner,
+            attributes,
+            display_cell: false,
+            global_idx: value.identifier,
+            span: value.span,
+        }),
+        Special::CodeInline { inner } => Inline::Code(inner, value.span),
+        Special::Command {
+            function,
+            parameters,
+            body,
+        } => {
+            let parameters = parameters
+                .into_iter()
+                .map(|p| parse_parameter(p, diagnostics))
+                .collect();
+            let body = body.map(|body| parse_elements(body, diagnostics));
+
+            Inline::Command(Command {
                 function,
+                label: value.label,
                 parameters,
                 body,
-            } => {
-                let parameters = parameters.into_iter().map(|p| p.into()).collect();
-                let body = body.map(|b| ComposedMarkdown::from(b).into());
+                span: value.span,
+                global_idx: value.identifier,
+            })
+        }
+        Special::Verbatim { inner } => Inline::Text(inner, value.span),
+        Special::Raw {
+            format,
+            inner,
+            is_block,
+        } => Inline::Raw(RawContent {
+            format,
+            source: inner,
+            display_block: is_block,
+            span: value.span,
+        }),
+        Special::Span { inner, attributes } => {
+            Inline::Span(parse_inlines(inner, diagnostics), attributes, value.span)
+        }
+        // Attribute blocks that do not belong to a heading are plain text.
+        Special::Attributes { source, .. } => Inline::Text(source, value.span),
+    }
+}
 
-                Inline::Command(Command {
-                    function,
-                    label: value.label,
-                    parameters,
-                    body,
-                    span: value.span,
-                    global_idx: value.identifier,
-                })
+/// Parses the content of a bracketed span. Spans only hold inline content, so block elements
+/// that the markdown parser finds (e.g. a leading `#` making a heading) are flattened.
+fn parse_inlines(elements: Vec<ElementInfo>, diagnostics: &mut Vec<Diagnostic>) -> Vec<Inline> {
+    parse_elements(elements, diagnostics)
+        .into_iter()
+        .flat_map(|block| match block {
+            Block::Heading { inner, .. } | Block::Plain(inner, _) | Block::Paragraph(inner, _) => {
+                inner
             }
-            Special::Verbatim { inner } => Inline::Text(inner),
+            Block::CodeBlock(block) => vec![Inline::CodeBlock(block)],
+            Block::Math(math) => vec![Inline::Math(math)],
+            Block::Raw(raw) => vec![Inline::Raw(raw)],
+            Block::Command(cmd) => vec![Inline::Command(cmd)],
+            _ => vec![],
+        })
+        .collect()
+}
+
+/// Whether the inline at `idx` should be lifted out of its paragraph and become a block. Code
+/// blocks and display math always are, while shortcodes with a body are only lifted when they
+/// stand on a line of their own.
+fn is_block_element(inlines: &[Inline], idx: usize) -> bool {
+    match &inlines[idx] {
+        Inline::CodeBlock(_) => true,
+        Inline::Math(math) => math.display_block,
+        Inline::Raw(raw) => raw.display_block,
+        Inline::Command(cmd) => {
+            cmd.body.is_some()
+                && line_boundary(inlines[..idx].iter().rev())
+                && line_boundary(inlines[idx + 1..].iter())
         }
+        _ => false,
     }
 }
 
-impl From<ComposedMarkdown> for Vec<Block> {
-    fn from(composed: ComposedMarkdown) -> Self {
-        let parser: MdParser = MdParser::new(&composed.src);
-        let r = Regex::new(r"elem-([0-9]+)").expect("invalid regex expression");
-        let mut inners = vec![InnerContent::Blocks(Vec::new())];
-
-        for event in parser {
-            match event {
-                Event::Start(t) => match t {
-                    Tag::Paragraph
-                    | Tag::Heading(_, _, _)
-                    | Tag::BlockQuote
-                    | Tag::CodeBlock(_)
-                    | Tag::TableHead
-                    | Tag::TableRow
-                    | Tag::TableCell
-                    | Tag::Emphasis
-                    | Tag::Strong
-                    | Tag::Strikethrough
-                    | Tag::Image(_, _, _) => inners.push(InnerContent::Inlines(Vec::new())),
-                    Tag::Link(_, _, _) => inners.push(InnerContent::Inlines(Vec::new())),
-                    Tag::List(_) | Tag::Item | Tag::Table(_) | Tag::FootnoteDefinition(_) => {
-                        inners.push(InnerContent::Blocks(Vec::new()))
-                    }
-                },
-                Event::End(t) => {
-                    let inner = inners.pop().expect("No inner content");
-                    match t {
-                        Tag::Paragraph => inners
+/// Whether a paragraph consists of nothing but special elements (shortcodes, math and code).
+/// Those are not wrapped in a paragraph so that e.g. a figure shortcode can stand on its own.
+fn only_special_elements(inlines: &[Inline]) -> bool {
+    let mut found = false;
+    for inline in inlines {
+        match inline {
+            Inline::Command(_)
+            | Inline::Math(_)
+            | Inline::CodeBlock(_)
+            | Inline::Code(..)
+            | Inline::Raw(_) => found = true,
+            Inline::SoftBreak(_) | Inline::HardBreak(_) => {}
+            Inline::Text(s, _) if s.trim().is_empty() => {}
+            _ => return false,
+        }
+    }
+    found
+}
+
+/// Checks that the next non-whitespace element is a line break or that there is none.
+fn line_boundary<'a>(mut inlines: impl Iterator<Item = &'a Inline>) -> bool {
+    match inlines.find(|i| !matches!(i, Inline::Text(s, _) if s.trim().is_empty())) {
+        Some(i) => matches!(i, Inline::SoftBreak(_) | Inline::HardBreak(_)),
+        None => true,
+    }
+}
+
+fn into_block(inline: Inline) -> Block {
+    match inline {
+        Inline::CodeBlock(block) => Block::CodeBlock(block),
+        Inline::Math(math) => Block::Math(math),
+        Inline::Raw(raw) => Block::Raw(raw),
+        Inline::Command(cmd) => Block::Command(cmd),
+        other => {
+            let span = other.span().clone();
+            Block::Plain(vec![other], span)
+        }
+    }
+}
+
+/// Splits a paragraph around the elements that should be blocks (see [is_block_element]). The
+/// remaining inline runs are wrapped using `wrap`, with surrounding whitespace and line breaks
+/// removed.
+fn split_block_elements(
+    inlines: Vec<Inline>,
+    span: Span,
+    wrap: fn(Vec<Inline>, Span) -> Block,
+) -> Vec<Block> {
+    let promote: Vec<bool> = (0..inlines.len())
+        .map(|idx| is_block_element(&inlines, idx))
+        .collect();
+    if !promote.contains(&true) {
+        return vec![wrap(inlines, span)];
+    }
+
+    let mut blocks = Vec::new();
+    let mut run = Vec::new();
+    for (inline, promote) in inlines.into_iter().zip(promote) {
+        if promote {
+            push_inline_run(&mut blocks, std::mem::take(&mut run), wrap);
+            blocks.push(into_block(inline));
+        } else {
+            run.push(inline);
+        }
+    }
+    push_inline_run(&mut blocks, run, wrap);
+
+    blocks
+}
+
+fn push_inline_run(
+    blocks: &mut Vec<Block>,
+    run: Vec<Inline>,
+    wrap: fn(Vec<Inline>, Span) -> Block,
+) {
+    let is_blank = |i: &Inline| match i {
+        Inline::SoftBreak(_) | Inline::HardBreak(_) => true,
+        Inline::Text(s, _) => s.trim().is_empty(),
+        _ => false,
+    };
+
+    if let Some(start) = run.iter().position(|i| !is_blank(i)) {
+        let end = run.iter().rposition(|i| !is_blank(i)).unwrap();
+        let run = run[start..=end].to_vec();
+        let span = inlines_span(&run);
+        blocks.push(wrap(run, span));
+    }
+}
+
+/// Removes trailing whitespace, e.g. the space before the attribute block of a heading.
+fn trim_end(mut inlines: Vec<Inline>) -> Vec<Inline> {
+    while let Some(Inline::Text(s, span)) = inlines.last_mut() {
+        let trimmed = s.trim_end();
+        if !trimmed.is_empty() {
+            span.range.end -= s.len() - trimmed.len();
+            *s = trimmed.into();
+            break;
+        }
+        inlines.pop();
+    }
+    inlines
+}
+
+/// The span covering all of the given (non-empty) inlines.
+fn inlines_span(inlines: &[Inline]) -> Span {
+    let start = inlines.first().map_or(0, |i| i.span().range.start);
+    let end = inlines.last().map_or(start, |i| i.span().range.end);
+    Span::new(start, end)
+}
+
+/// Removes a leading callout marker (e.g. `[!NOTE]`) from the first line of a block quote and
+/// returns its kind. The blocks are left untouched if there is no valid marker.
+fn take_admonition_marker(blocks: &mut Vec<Block>) -> Option<AdmonitionKind> {
+    let Some(Block::Paragraph(inlines, span)) = blocks.first_mut() else {
+        return None;
+    };
+
+    // The marker may be split into several text events by the markdown parser.
+    let mut marker = String::new();
+    let mut line_end = inlines.len();
+    for (idx, inline) in inlines.iter().enumerate() {
+        match inline {
+            Inline::Text(s, _) => marker.push_str(s),
+            Inline::SoftBreak(_) | Inline::HardBreak(_) => {
+                line_end = idx;
+                break;
+            }
+            _ => return None,
+        }
+    }
+
+    let kind = AdmonitionKind::from_marker(marker.trim())?;
+    let remove = (line_end + 1).min(inlines.len());
+    inlines.drain(..remove);
+    if inlines.is_empty() {
+        blocks.remove(0);
+    } else {
+        *span = inlines_span(inlines);
+    }
+    Some(kind)
+}
+
+/// Builds the blocks for a sequence of raw elements. The markdown is parsed once with the special
+/// elements masked out, and the elements are inserted where the masks show up in the events.
+/// Elements that end up where the AST has no room for them (e.g. in a link destination) are
+/// reported as diagnostics.
+pub(crate) fn parse_elements(
+    elements: Vec<ElementInfo>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<Block> {
+    let MaskedSource {
+        text,
+        offset,
+        children,
+    } = MaskedSource::from(elements);
+    let mut inners = vec![InnerContent::Blocks(Vec::new())];
+    // Attribute block of the heading currently being parsed, if any.
+    let mut heading_attrs: Option<Option<raw::Attributes>> = None;
+
+    let mut events = FusedEvents::new(&text, offset, children);
+    for event in events.by_ref() {
+        let (event, span) = match event {
+            DocEvent::Markdown(event, span) => (event, span),
+            DocEvent::Special(Child {
+                elem: Special::Attributes { attributes, .. },
+                ..
+            }) if heading_attrs.is_some() => {
+                heading_attrs = Some(Some(attributes));
+                continue;
+            }
+            DocEvent::Special(child) => {
+                inners
+                    .last_mut()
+                    .unwrap()
+                    .push_inline(parse_child(child, diagnostics));
+                continue;
+            }
+        };
+        match event {
+            Event::Start(t) => match t {
+                Tag::Heading(_, _, _) => {
+                    heading_attrs = Some(None);
+                    inners.push(InnerContent::Inlines(Vec::new()))
+                }
+                Tag::Paragraph
+                | Tag::CodeBlock(_)
+                | Tag::TableCell
+                | Tag::Emphasis
+                | Tag::Strong
+                | Tag::Strikethrough
+                | Tag::Image(_, _, _) => inners.push(InnerContent::Inlines(Vec::new())),
+                Tag::Link(_, _, _) => inners.push(InnerContent::Inlines(Vec::new())),
+                Tag::BlockQuote | Tag::List(_) | Tag::Item | Tag::FootnoteDefinition(_) => {
+                    inners.push(InnerContent::Blocks(Vec::new()))
+                }
+                Tag::Table(_) => inners.push(InnerContent::Rows(Vec::new())),
+                Tag::TableHead | Tag::TableRow => inners.push(InnerContent::Cells(Vec::new())),
+            },
+            Event::End(t) => {
+                let inner = inners.pop().expect("No inner content");
+                match t {
+                    Tag::Paragraph => {
+                        let inlines = inner.into_inlines();
+                        let wrap: fn(Vec<Inline>, Span) -> Block =
+                            if only_special_elements(&inlines) {
+                                Block::Plain
+                            } else {
+                                Block::Paragraph
+                            };
+                        inners
                             .last_mut()
                             .unwrap()
                             .blocks_mut()
                             .expect("for paragraph")
-                            .push(Block::Paragraph(inner.into_inlines())),
-                        Tag::Heading(lvl, id, classes) => inners
+                            .extend(split_block_elements(inlines, span, wrap))
+                    }
+                    Tag::Heading(lvl, id, classes) => {
+                        let mut attributes = heading_attrs.take().flatten().unwrap_or_default();
+                        let id = attributes.values.remove("id").or(id.map(|s| s.into()));
+                        let classes = classes
+                            .into_iter()
+                            .map(|s| s.into())
+                            .chain(attributes.classes)
+                            .collect();
+                        inners
                             .last_mut()
                             .unwrap()
                             .blocks_mut()
                             .expect("for heading")
                             .push(Block::Heading {
                                 lvl: heading_to_lvl(lvl),
-                                id: id.map(|s| s.into()),
-                                classes: classes.into_iter().map(|s| s.into()).collect(),
-                                inner: inner.into_inlines(),
-                            }),
-                        Tag::BlockQuote => inners
+                                id,
+                                classes,
+                                attributes: attributes.values,
+                                inner: trim_end(inner.into_inlines()),
+                                span,
+                            })
+                    }
+                    Tag::BlockQuote => {
+                        let mut blocks = inner.into_blocks();
+                        let block = match take_admonition_marker(&mut blocks) {
+                            Some(kind) => Block::Admonition(kind, blocks, span),
+                            None => Block::BlockQuote(blocks, span),
+                        };
+                        inners
                             .last_mut()
                             .unwrap()
                             .blocks_mut()
                             .expect("for blockquote")
-                            .push(Block::BlockQuote(inner.into_inlines())),
-                        Tag::List(idx) => inners
-                            .last_mut()
-                            .unwrap()
-                            .blocks_mut()
-                            .expect("for list")
-                            .push(Block::List(idx, inner.into_blocks())),
-                        Tag::Item => inners
-                            .last_mut()
-                            .unwrap()
-                            .blocks_mut()
-                            .expect("for item")
-                            .push(Block::ListItem(inner.into_blocks())),
-                        Tag::Emphasis => {
-                            let src = inner.into_inlines();
-
-                            inners
-                                .last_mut()
-                                .unwrap()
-                                .push_inline(Inline::Styled(src, Style::Emphasis))
-                        }
-                        Tag::Strong => inners
-                            .last_mut()
-                            .unwrap()
-                            .push_inline(Inline::Styled(inner.into_inlines(), Style::Strong)),
-                        Tag::Strikethrough => inners.last_mut().unwrap().push_inline(
-                            Inline::Styled(inner.into_inlines(), Style::Strikethrough),
-                        ),
-                        Tag::Link(tp, url, alt) => {
-                            inners.last_mut().unwrap().push_inline(Inline::Link(
-                                tp,
-                                url.to_cowstr(),
-                                alt.to_cowstr(),
-                                inner.into_inlines(),
-                            ))
-                        }
-                        Tag::Image(tp, url, alt) => {
-                            inners.last_mut().unwrap().push_inline(Inline::Image(
-                                tp,
-                                url.to_cowstr(),
-                                alt.to_cowstr(),
-                                inner.into_inlines(),
-                            ))
-                        }
-                        _ => {} // TODO: Implement rest
+                            .push(block)
                     }
-                }
-                Event::Html(src) => {
-                    let is_insert = r.captures(src.as_ref()).and_then(|c| c.get(1));
-
-                    if let Some(match_) = is_insert {
-                        let idx = usize::from_str(match_.as_str()).unwrap();
-                        let elem = composed.children[idx].clone();
-                        inners.last_mut().unwrap().push_inline(elem.into());
-                    } else {
+                    Tag::List(idx) => inners
+                        .last_mut()
+                        .unwrap()
+                        .blocks_mut()
+                        .expect("for list")
+                        .push(Block::List(idx, inner.into_blocks(), span)),
+                    Tag::Item => inners
+                        .last_mut()
+                        .unwrap()
+                        .blocks_mut()
+                        .expect("for item")
+                        .push(Block::ListItem(inner.into_blocks(), span)),
+                    Tag::Table(alignments) => {
+                        let mut rows = inner.into_rows().into_iter();
+                        let header = rows.next().unwrap_or_default();
                         inners
                             .last_mut()
                             .unwrap()
-                            .push_inline(Inline::Html(src.to_cowstr()));
+                            .blocks_mut()
+                            .expect("for table")
+                            .push(Block::Table {
+                                alignments,
+                                header,
+                                rows: rows.collect(),
+                                span,
+                            })
                     }
-                }
-                other => {
-                    let inner = match other {
-                        Event::Text(s) => Inline::Text(s.to_cowstr()),
-                        Event::Code(s) => Inline::Code(s.to_cowstr()),
-                        Event::SoftBreak => Inline::SoftBreak,
-                        Event::HardBreak => Inline::HardBreak,
-                        Event::Rule => Inline::Rule,
-                        _ => unreachable!(),
-                    };
-
-                    let c = inners.last_mut().unwrap();
-                    c.push_inline(inner);
+                    Tag::TableHead | Tag::TableRow => inners
+                        .last_mut()
+                        .unwrap()
+                        .rows_mut()
+                        .expect("for table row")
+                        .push(inner.into_cells()),
+                    Tag::TableCell => inners
+                        .last_mut()
+                        .unwrap()
+                        .cells_mut()
+                        .expect("for table cell")
+                        .push(inner.into_inlines()),
+                    Tag::FootnoteDefinition(label) => inners
+                        .last_mut()
+                        .unwrap()
+                        .blocks_mut()
+                        .expect("for footnote definition")
+                        .push(Block::FootnoteDefinition(
+                            label.to_cowstr(),
+                            inner.into_blocks(),
+                            span,
+                        )),
+                    Tag::Emphasis => {
+                        let src = inner.into_inlines();
+
+                        inners.last_mut().unwrap().push_inline(Inline::Styled(
+                            src,
+                            Style::Emphasis,
+                            span,
+                        ))
+                    }
+                    Tag::Strong => inners.last_mut().unwrap().push_inline(Inline::Styled(
+                        inner.into_inlines(),
+                        Style::Strong,
+                        span,
+                    )),
+                    Tag::Strikethrough => inners.last_mut().unwrap().push_inline(Inline::Styled(
+                        inner.into_inlines(),
+                        Style::Strikethrough,
+                        span,
+                    )),
+                    Tag::Link(tp, url, alt) => {
+                        inners.last_mut().unwrap().push_inline(Inline::Link(
+                            tp,
+                            url.to_cowstr(),
+                            alt.to_cowstr(),
+                            inner.into_inlines(),
+                            span,
+                        ))
+                    }
+                    Tag::Image(tp, url, alt) => {
+                        inners.last_mut().unwrap().push_inline(Inline::Image(
+                            tp,
+                            url.to_cowstr(),
+                            alt.to_cowstr(),
+                            inner.into_inlines(),
+                            span,
+                        ))
+                    }
+                    _ => {} // TODO: Implement rest
                 }
             }
+            Event::Html(src) => inners
+                .last_mut()
+                .unwrap()
+                .push_inline(Inline::Html(src.to_cowstr(), span)),
+            other => {
+                let inner = match other {
+                    Event::Text(s) => Inline::Text(s.to_cowstr(), span),
+                    Event::Code(s) => Inline::Code(s.to_cowstr(), span),
+                    Event::SoftBreak => Inline::SoftBreak(span),
+                    Event::HardBreak => Inline::HardBreak(span),
+                    Event::Rule => Inline::Rule(span),
+                    Event::FootnoteReference(label) => {
+                        Inline::FootnoteReference(label.to_cowstr(), span)
+                    }
+                    _ => unreachable!(),
+                };
+
+                let c = inners.last_mut().unwrap();
+                c.push_inline(inner);
+            }
         }
-        let b = inners.remove(0).into_blocks();
-        b.clone()
     }
+    diagnostics.extend(events.unplaced.into_iter().map(|span| {
+        Diagnostic::new(
+            "shortcodes, math and code cannot be used in a link destination or title",
+            span,
+        )
+    }));
+    inners.remove(0).into_blocks()
 }
 
 fn heading_to_lvl(value: HeadingLevel) -> u8 {
@@ -276,19 +607,29 @@ fn heading_to_lvl(value: HeadingLevel) -> u8 {
 mod tests {
     use crate::ast;
     use crate::ast::Block::ListItem;
-    use crate::ast::{Block, Command, Inline, Math, Parameter, Style, Value};
+    use crate::ast::{
+        AdmonitionKind, Block, Command, Inline, Math, Parameter, RawContent, Style, Value,
+    };
     use crate::code_ast::types::{CodeContent, CodeElem};
-    use crate::common::Span;
-    use crate::raw::{parse_to_doc, ComposedMarkdown, Element, ElementInfo, Special};
+    use crate::common::{Diagnostic, LineCol, Span};
+    use crate::document::Document;
+    use crate::raw::{parse_to_doc, Attributes, Element, ElementInfo, Special};
 
-    use pulldown_cmark::LinkType;
+    use pulldown_cmark::{Alignment, LinkType};
+
+    fn parse_elements(elements: Vec<ElementInfo>) -> Vec<Block> {
+        let mut diagnostics = Vec::new();
+        let blocks = super::parse_elements(elements, &mut diagnostics);
+        assert_eq!(Vec::<Diagnostic>::new(), diagnostics);
+        blocks
+    }
 
     #[test]
     fn simple_command() {
         let stuff = vec![
             ElementInfo {
                 element: Element::Markdown("regular stuff ".into()),
-                span: Span::new(0, 0),
+                span: Span::new(0, 14),
             },
             ElementInfo {
                 element: Element::Special(
@@ -298,28 +639,33 @@ mod tests {
                         parameters: vec![],
                         body: Some(vec![ElementInfo {
                             element: Element::Markdown("x".into()),
-                            span: Span::new(0, 0),
+                            span: Span::new(20, 21),
                         }]),
                     },
                 ),
-                span: Span::new(0, 0),
+                span: Span::new(14, 22),
             },
         ];
 
-        let composed = ComposedMarkdown::from(stuff);
-        let doc = Vec::from(composed);
+        let doc = parse_elements(stuff);
 
-        let expected = vec![Block::Paragraph(vec![
-            Inline::Text("regular stuff ".into()),
-            Inline::Command(Command {
-                function: "func".into(),
-                label: None,
-                parameters: vec![],
-                body: Some(vec![Block::Paragraph(vec![Inline::Text("x".into())])]),
-                span: Span::new(0, 0),
-                global_idx: 0,
-            }),
-        ])];
+        let expected = vec![Block::Paragraph(
+            vec![
+                Inline::Text("regular stuff ".into(), Span::new(0, 14)),
+                Inline::Command(Command {
+                    function: "func".into(),
+                    label: None,
+                    parameters: vec![],
+                    body: Some(vec![Block::Paragraph(
+                        vec![Inline::Text("x".into(), Span::new(20, 21))],
+                        Span::new(20, 21),
+                    )]),
+                    span: Span::new(14, 22),
+                    global_idx: 0,
+                }),
+            ],
+            Span::new(0, 22),
+        )];
 
         assert_eq!(expected, doc);
     }
@@ -328,62 +674,106 @@ mod tests {
     fn markdown_elements() {
         let input = include_str!("../../resources/tests/markdown_elems.md");
         let input_doc = parse_to_doc(input).expect("rawdoc parse error");
-        let composed = ComposedMarkdown::from(input_doc.src);
-        let output_doc = Vec::from(composed);
+        let output_doc = parse_elements(input_doc.src);
 
         let expected = vec![
             Block::Heading {
                 lvl: 1,
                 id: None,
                 classes: vec![],
-                inner: vec![Inline::Text("Heading".into())],
+                attributes: Default::default(),
+                inner: vec![Inline::Text("Heading".into(), Span::new(23, 30))],
+                span: Span::new(21, 31),
             },
             Block::Heading {
                 lvl: 2,
                 id: None,
                 classes: vec![],
-                inner: vec![Inline::Text("Subheading".into())],
+                attributes: Default::default(),
+                inner: vec![Inline::Text("Subheading".into(), Span::new(35, 45))],
+                span: Span::new(32, 46),
             },
             Block::List(
                 None,
                 vec![
-                    ListItem(vec![Block::Plain(vec![Inline::Text(
-                        "unordered list".into(),
-                    )])]),
-                    ListItem(vec![Block::Plain(vec![Inline::Text("item 2".into())])]),
+                    ListItem(
+                        vec![Block::Plain(
+                            vec![Inline::Text("unordered list".into(), Span::new(49, 63))],
+                            Span::new(49, 63),
+                        )],
+                        Span::new(47, 64),
+                    ),
+                    ListItem(
+                        vec![Block::Plain(
+                            vec![Inline::Text("item 2".into(), Span::new(66, 72))],
+                            Span::new(66, 72),
+                        )],
+                        Span::new(64, 74),
+                    ),
                 ],
+                Span::new(47, 74),
             ),
             Block::List(
                 Some(1),
                 vec![
-                    ListItem(vec![Block::Plain(vec![Inline::Text(
-                        "ordered list".into(),
-                    )])]),
-                    ListItem(vec![Block::Plain(vec![Inline::Text("item 2".into())])]),
+                    ListItem(
+                        vec![Block::Plain(
+                            vec![Inline::Text("ordered list".into(), Span::new(77, 89))],
+                            Span::new(77, 89),
+                        )],
+                        Span::new(74, 90),
+                    ),
+                    ListItem(
+                        vec![Block::Plain(
+                            vec![Inline::Text("item 2".into(), Span::new(93, 99))],
+                            Span::new(93, 99),
+                        )],
+                        Span::new(90, 101),
+                    ),
                 ],
+                Span::new(74, 101),
             ),
-            Block::Paragraph(vec![
-                Inline::Link(
-                    LinkType::Inline,
-                    "path/is/here".into(),
-                    "".into(),
-                    vec![Inline::Text("link".into())],
-                ),
-                Inline::SoftBreak,
-                Inline::Image(
-                    LinkType::Inline,
-                    "path/is/here".into(),
-                    "".into(),
-                    vec![Inline::Text("image".into())],
-                ),
-            ]),
-            Block::Paragraph(vec![
-                Inline::Styled(vec![Inline::Text("emph".into())], Style::Emphasis),
-                Inline::SoftBreak,
-                Inline::Styled(vec![Inline::Text("strong".into())], Style::Strong),
-            ]),
-            Block::Plain(vec![Inline::Code("code inline".into())]),
-            Block::Plain(vec![Inline::CodeBlock(ast::CodeBlock {
+            Block::Paragraph(
+                vec![
+                    Inline::Link(
+                        LinkType::Inline,
+                        "path/is/here".into(),
+                        "".into(),
+                        vec![Inline::Text("link".into(), Span::new(102, 106))],
+                        Span::new(101, 121),
+                    ),
+                    Inline::SoftBreak(Span::new(121, 122)),
+                    Inline::Image(
+                        LinkType::Inline,
+                        "path/is/here".into(),
+                        "".into(),
+                        vec![Inline::Text("image".into(), Span::new(124, 129))],
+                        Span::new(122, 144),
+                    ),
+                ],
+                Span::new(101, 145),
+            ),
+            Block::Paragraph(
+                vec![
+                    Inline::Styled(
+                        vec![Inline::Text("emph".into(), Span::new(147, 151))],
+                        Style::Emphasis,
+                        Span::new(146, 152),
+                    ),
+                    Inline::SoftBreak(Span::new(152, 153)),
+                    Inline::Styled(
+                        vec![Inline::Text("strong".into(), Span::new(155, 161))],
+                        Style::Strong,
+                        Span::new(153, 163),
+                    ),
+                ],
+                Span::new(146, 164),
+            ),
+            Block::Plain(
+                vec![Inline::Code("code inline".into(), Span::new(165, 178))],
+                Span::new(165, 179),
+            ),
+            Block::CodeBlock(ast::CodeBlock {
                 label: None,
                 source: CodeContent {
                     blocks: vec![CodeElem::Src("\ncode block\n\n".to_string())],
@@ -394,20 +784,23 @@ mod tests {
                 display_cell: false,
                 global_idx: 0,
                 span: Span::new(180, 198),
-                attributes: vec![],
-            })]),
-            Block::Plain(vec![Inline::Math(Math {
-                label: None,
-                source: "math inline".into(),
-                display_block: false,
-                span: Span::new(200, 213),
-            })]),
-            Block::Plain(vec![Inline::Math(Math {
+                attributes: Default::default(),
+            }),
+            Block::Plain(
+                vec![Inline::Math(Math {
+                    label: None,
+                    source: "math inline".into(),
+                    display_block: false,
+                    span: Span::new(200, 213),
+                })],
+                Span::new(200, 214),
+            ),
+            Block::Math(Math {
                 label: None,
                 source: "\nmath block\n".into(),
                 display_block: true,
                 span: Span::new(215, 231),
-            })]),
+            }),
         ];
 
         assert_eq!(expected, output_doc);
@@ -417,49 +810,55 @@ mod tests {
     fn commands() {
         let input = include_str!("../../resources/tests/commands.md");
         let input_doc = parse_to_doc(input).expect("rawdoc parse error");
-        let composed = ComposedMarkdown::from(input_doc.src);
-        let output_doc = Vec::from(composed);
+        let output_doc = parse_elements(input_doc.src);
 
         let expected = vec![
-            Block::Plain(vec![Inline::Command(Command {
-                function: "func".into(),
-                label: None,
-                parameters: vec![],
-                body: None,
-                span: Span::new(0, 5),
-                global_idx: 0,
-            })]),
-            Block::Plain(vec![Inline::Command(Command {
-                function: "func_param".into(),
-                label: None,
-                parameters: vec![
-                    Parameter {
-                        key: None,
-                        value: Value::String("p1".into()),
-                        span: Span::new(19, 21),
-                    },
-                    Parameter {
-                        key: Some("x".into()),
-                        value: Value::String("p2".into()),
-                        span: Span::new(23, 27),
-                    },
-                ],
-                body: None,
-                span: Span::new(7, 28),
+            Block::Plain(
+                vec![Inline::Command(Command {
+                    function: "func".into(),
+                    label: None,
+                    parameters: vec![],
+                    body: None,
+                    span: Span::new(0, 5),
+                    global_idx: 0,
+                })],
+                Span::new(0, 6),
+            ),
+            Block::Plain(
+                vec![Inline::Command(Command {
+                    function: "func_param".into(),
+                    label: None,
+                    parameters: vec![
+                        Parameter {
+                            key: None,
+                            value: Value::String("p1".into()),
+                            span: Span::new(19, 21),
+                        },
+                        Parameter {
+                            key: Some("x".into()),
+                            value: Value::String("p2".into()),
+                            span: Span::new(23, 27),
+                        },
+                    ],
+                    body: None,
+                    span: Span::new(7, 28),
 
-                global_idx: 1,
-            })]),
-            Block::Plain(vec![Inline::Command(Command {
+                    global_idx: 1,
+                })],
+                Span::new(7, 29),
+            ),
+            Block::Command(Command {
                 function: "func_body".into(),
                 label: None,
                 parameters: vec![],
-                body: Some(vec![Block::Paragraph(vec![Inline::Text(
-                    "hello there".into(),
-                )])]),
+                body: Some(vec![Block::Paragraph(
+                    vec![Inline::Text("hello there".into(), Span::new(42, 53))],
+                    Span::new(42, 54),
+                )]),
                 span: Span::new(30, 55),
                 global_idx: 2,
-            })]),
-            Block::Plain(vec![Inline::Command(Command {
+            }),
+            Block::Command(Command {
                 function: "func_all".into(),
                 label: None,
                 parameters: vec![
@@ -474,37 +873,414 @@ mod tests {
                         span: Span::new(71, 75),
                     },
                 ],
-                body: Some(vec![Block::Paragraph(vec![Inline::Text(
-                    "hello there".into(),
-                )])]),
+                body: Some(vec![Block::Paragraph(
+                    vec![Inline::Text("hello there".into(), Span::new(78, 89))],
+                    Span::new(78, 90),
+                )]),
                 span: Span::new(57, 91),
                 global_idx: 3,
-            })]),
-            Block::Plain(vec![Inline::Command(Command {
+            }),
+            Block::Command(Command {
                 function: "func_inner".into(),
                 label: None,
                 parameters: vec![],
                 body: Some(vec![
-                    Block::Plain(vec![Inline::Code("#func".into())]),
-                    Block::Plain(vec![Inline::Command(Command {
+                    Block::Plain(
+                        vec![Inline::Code("#func".into(), Span::new(106, 113))],
+                        Span::new(106, 113),
+                    ),
+                    Block::Command(Command {
                         function: "inner".into(),
                         label: None,
                         parameters: vec![],
-                        body: Some(vec![Block::Plain(vec![Inline::Math(Math {
-                            label: None,
-                            source: "math".into(),
-                            display_block: false,
-                            span: Span::new(122, 128),
-                        })])]),
+                        body: Some(vec![Block::Plain(
+                            vec![Inline::Math(Math {
+                                label: None,
+                                source: "math".into(),
+                                display_block: false,
+                                span: Span::new(122, 128),
+                            })],
+                            Span::new(122, 129),
+                        )]),
                         span: Span::new(114, 130),
                         global_idx: 0,
-                    })]),
+                    }),
                 ]),
                 span: Span::new(93, 132),
                 global_idx: 4,
-            })]),
+            }),
+        ];
+
+        assert_eq!(expected, output_doc);
+    }
+
+    #[test]
+    fn tables() {
+        let input = include_str!("../../resources/tests/tables.md");
+        let input_doc = parse_to_doc(input).expect("rawdoc parse error");
+        let output_doc = parse_elements(input_doc.src);
+
+        let expected = vec![Block::Table {
+            alignments: vec![Alignment::Left, Alignment::Center, Alignment::Right],
+            header: vec![
+                vec![Inline::Text("Name".into(), Span::new(2, 6))],
+                vec![Inline::Text("Value".into(), Span::new(9, 14))],
+                vec![Inline::Text("Note".into(), Span::new(17, 21))],
+            ],
+            rows: vec![
+                vec![
+                    vec![Inline::Text("a".into(), Span::new(50, 51))],
+                    vec![Inline::Styled(
+                        vec![Inline::Text("b".into(), Span::new(54, 55))],
+                        Style::Emphasis,
+                        Span::new(53, 56),
+                    )],
+                    vec![Inline::Text("c".into(), Span::new(58, 59))],
+                ],
+                vec![
+                    vec![Inline::Text("d".into(), Span::new(64, 65))],
+                    vec![Inline::Text("e".into(), Span::new(68, 69))],
+                    vec![Inline::Text("f".into(), Span::new(72, 73))],
+                ],
+            ],
+            span: Span::new(0, 76),
+        }];
+
+        assert_eq!(expected, output_doc);
+    }
+
+    #[test]
+    fn footnotes() {
+        let input = include_str!("../../resources/tests/footnotes.md");
+        let input_doc = parse_to_doc(input).expect("rawdoc parse error");
+        let output_doc = parse_elements(input_doc.src);
+
+        let expected = vec![
+            Block::Paragraph(
+                vec![
+                    Inline::Text("Text with a note".into(), Span::new(0, 16)),
+                    Inline::FootnoteReference("first".into(), Span::new(16, 24)),
+                    Inline::Text(".".into(), Span::new(24, 25)),
+                ],
+                Span::new(0, 26),
+            ),
+            Block::FootnoteDefinition(
+                "first".into(),
+                vec![Block::Paragraph(
+                    vec![
+                        Inline::Text("The ".into(), Span::new(37, 41)),
+                        Inline::Styled(
+                            vec![Inline::Text("note".into(), Span::new(42, 46))],
+                            Style::Emphasis,
+                            Span::new(41, 47),
+                        ),
+                        Inline::Text(".".into(), Span::new(47, 48)),
+                    ],
+                    Span::new(37, 49),
+                )],
+                Span::new(27, 49),
+            ),
+        ];
+
+        assert_eq!(expected, output_doc);
+    }
+
+    #[test]
+    fn blockquotes() {
+        let input = include_str!("../../resources/tests/blockquotes.md");
+        let input_doc = parse_to_doc(input).expect("rawdoc parse error");
+        let output_doc = parse_elements(input_doc.src);
+
+        let expected = vec![
+            Block::BlockQuote(
+                vec![
+                    Block::Paragraph(
+                        vec![Inline::Text("Quote with a list:".into(), Span::new(2, 20))],
+                        Span::new(2, 21),
+                    ),
+                    Block::List(
+                        None,
+                        vec![
+                            ListItem(
+                                vec![Block::Plain(
+                                    vec![Inline::Text("one".into(), Span::new(27, 30))],
+                                    Span::new(27, 30),
+                                )],
+                                Span::new(25, 31),
+                            ),
+                            ListItem(
+                                vec![Block::Plain(
+                                    vec![Inline::Text("two".into(), Span::new(35, 38))],
+                                    Span::new(35, 38),
+                                )],
+                                Span::new(33, 39),
+                            ),
+                        ],
+                        Span::new(25, 39),
+                    ),
+                ],
+                Span::new(0, 39),
+            ),
+            Block::Admonition(
+                AdmonitionKind::Warning,
+                vec![Block::Paragraph(
+                    vec![
+                        Inline::Text("Be ".into(), Span::new(55, 58)),
+                        Inline::Styled(
+                            vec![Inline::Text("careful".into(), Span::new(59, 66))],
+                            Style::Emphasis,
+                            Span::new(58, 67),
+                        ),
+                        Inline::Text(".".into(), Span::new(67, 68)),
+                    ],
+                    Span::new(55, 68),
+                )],
+                Span::new(40, 69),
+            ),
+        ];
+
+        assert_eq!(expected, output_doc);
+    }
+
+    #[test]
+    fn block_elements() {
+        let input = include_str!("../../resources/tests/block_elements.md");
+        let input_doc = parse_to_doc(input).expect("rawdoc parse error");
+        let output_doc = parse_elements(input_doc.src);
+
+        let expected = vec![
+            Block::Paragraph(
+                vec![Inline::Text("Some text".into(), Span::new(0, 9))],
+                Span::new(0, 9),
+            ),
+            Block::Math(Math {
+                label: None,
+                source: "\nx^2\n".into(),
+                display_block: true,
+                span: Span::new(10, 19),
+            }),
+            Block::Paragraph(
+                vec![Inline::Text("more text".into(), Span::new(20, 29))],
+                Span::new(20, 29),
+            ),
+            Block::Command(Command {
+                function: "note".into(),
+                label: None,
+                parameters: vec![],
+                body: Some(vec![Block::Paragraph(
+                    vec![Inline::Text("body".into(), Span::new(36, 40))],
+                    Span::new(36, 40),
+                )]),
+                span: Span::new(30, 41),
+                global_idx: 0,
+            }),
         ];
 
         assert_eq!(expected, output_doc);
     }
+
+    #[test]
+    fn nested_elements() {
+        let input = include_str!("../../resources/tests/nested_elements.md");
+        let input_doc = parse_to_doc(input).expect("rawdoc parse error");
+        let output_doc = parse_elements(input_doc.src);
+
+        let expected = vec![
+            Block::Heading {
+                lvl: 1,
+                id: None,
+                classes: vec![],
+                attributes: Default::default(),
+                inner: vec![
+                    Inline::Text("Heading with ".into(), Span::new(2, 15)),
+                    Inline::Math(Math {
+                        label: None,
+                        source: "x".into(),
+                        display_block: false,
+                        span: Span::new(15, 18),
+                    }),
+                ],
+                span: Span::new(0, 19),
+            },
+            Block::Table {
+                alignments: vec![Alignment::None, Alignment::None],
+                header: vec![
+                    vec![Inline::Text("a".into(), Span::new(22, 23))],
+                    vec![Inline::Text("b".into(), Span::new(26, 27))],
+                ],
+                rows: vec![vec![
+                    vec![Inline::Math(Math {
+                        label: None,
+                        source: "y".into(),
+                        display_block: false,
+                        span: Span::new(42, 45),
+                    })],
+                    vec![Inline::Command(Command {
+                        function: "func".into(),
+                        label: None,
+                        parameters: vec![],
+                        body: None,
+                        span: Span::new(48, 53),
+                        global_idx: 0,
+                    })],
+                ]],
+                span: Span::new(20, 56),
+            },
+            Block::Paragraph(
+                vec![Inline::Link(
+                    LinkType::Inline,
+                    "url".into(),
+                    "".into(),
+                    vec![
+                        Inline::Text("link with ".into(), Span::new(58, 68)),
+                        Inline::Code("code".into(), Span::new(68, 74)),
+                    ],
+                    Span::new(57, 80),
+                )],
+                Span::new(57, 81),
+            ),
+        ];
+
+        assert_eq!(expected, output_doc);
+    }
+
+    #[test]
+    fn raw_content() {
+        let input = include_str!("../../resources/tests/raw_content.md");
+        let input_doc = parse_to_doc(input).expect("rawdoc parse error");
+        let output_doc = parse_elements(input_doc.src);
+
+        let expected = vec![
+            Block::Paragraph(
+                vec![
+                    Inline::Text("Line ".into(), Span::new(0, 5)),
+                    Inline::Raw(RawContent {
+                        format: "html".into(),
+                        source: "<br>".into(),
+                        display_block: false,
+                        span: Span::new(5, 18),
+                    }),
+                ],
+                Span::new(0, 19),
+            ),
+            Block::Raw(RawContent {
+                format: "latex".into(),
+                source: "\\newpage".into(),
+                display_block: true,
+                span: Span::new(20, 44),
+            }),
+        ];
+
+        assert_eq!(expected, output_doc);
+    }
+
+    #[test]
+    fn attributes() {
+        let input = include_str!("../../resources/tests/attributes.md");
+        let input_doc = parse_to_doc(input).expect("rawdoc parse error");
+        let output_doc = parse_elements(input_doc.src);
+
+        let expected = vec![
+            Block::Heading {
+                lvl: 1,
+                id: Some("intro".into()),
+                classes: vec!["wide".into()],
+                attributes: [("level".into(), "1".into())].into_iter().collect(),
+                inner: vec![Inline::Text("Title".into(), Span::new(2, 7))],
+                span: Span::new(0, 31),
+            },
+            Block::Paragraph(
+                vec![
+                    Inline::Text("Some ".into(), Span::new(32, 37)),
+                    Inline::Span(
+                        vec![
+                            Inline::Text("marked ".into(), Span::new(38, 45)),
+                            Inline::Styled(
+                                vec![Inline::Text("text".into(), Span::new(46, 50))],
+                                Style::Emphasis,
+                                Span::new(45, 51),
+                            ),
+                        ],
+                        Attributes {
+                            classes: vec!["highlight".into()],
+                            ..Default::default()
+                        },
+                        Span::new(37, 64),
+                    ),
+                    Inline::Text(", a set {x}.".into(), Span::new(64, 76)),
+                ],
+                Span::new(32, 77),
+            ),
+        ];
+
+        assert_eq!(expected, output_doc);
+    }
+
+    #[test]
+    fn line_positions() {
+        let input = include_str!("../../resources/tests/block_elements.md");
+        let doc = Document::try_from(input).expect("parse error");
+
+        let Block::Command(cmd) = &doc.content.blocks[3] else {
+            panic!("expected a command block");
+        };
+        assert_eq!(LineCol { line: 6, column: 1 }, cmd.span.start);
+        assert_eq!(
+            LineCol {
+                line: 6,
+                column: 12
+            },
+            cmd.span.end
+        );
+
+        let body = cmd.body.as_ref().unwrap();
+        assert_eq!(LineCol { line: 6, column: 7 }, body[0].span().start);
+    }
+
+    #[test]
+    fn link_destinations() {
+        let input = "See [the docs](https://example.com/a$b$c#d) and $x$.";
+        let input_doc = parse_to_doc(input).expect("rawdoc parse error");
+        let output_doc = parse_elements(input_doc.src);
+
+        let Block::Paragraph(inlines, _) = &output_doc[0] else {
+            panic!("expected a paragraph");
+        };
+        let Inline::Link(_, url, _, inner, _) = &inlines[1] else {
+            panic!("expected a link, got {:?}", inlines[1]);
+        };
+        assert_eq!("https://example.com/a$b$c#d", url.as_str());
+        assert_eq!(
+            vec![Inline::Text("the docs".into(), Span::new(5, 13))],
+            *inner
+        );
+        assert!(matches!(inlines[3], Inline::Math { .. }));
+    }
+
+    #[test]
+    fn elements_in_link_destinations() {
+        let input = "See [the docs](#ref(intro)) and [more](https://example.com \"About $x$\").\n\nEnd $y$.";
+        let input_doc = parse_to_doc(input).expect("rawdoc parse error");
+        let mut diagnostics = Vec::new();
+        let output_doc = super::parse_elements(input_doc.src, &mut diagnostics);
+
+        // Destinations are not parsed for elements, but titles are.
+        let spans: Vec<_> = diagnostics.iter().map(|d| d.span.range.clone()).collect();
+        assert_eq!(vec![66..69], spans);
+        let Block::Paragraph(inlines, _) = &output_doc[0] else {
+            panic!("expected a paragraph");
+        };
+        let Inline::Link(_, url, _, _, _) = &inlines[1] else {
+            panic!("expected a link, got {:?}", inlines[1]);
+        };
+        assert_eq!("#ref(intro)", url.as_str());
+        // The elements after the links are kept.
+        let Block::Paragraph(inlines, _) = &output_doc[1] else {
+            panic!("expected a paragraph");
+        };
+        assert!(matches!(inlines[1], Inline::Math { .. }));
+
+        let err = Document::try_from(input).err().unwrap();
+        assert!(err.to_string().contains("link destination"), "{err}");
+    }
 }
@@ -0,0 +1,309 @@
+//! Prints a parsed document back to courses markdown.
+//!
+//! The printer is source-preserving: markdown and front matter are copied from the source as
+//! written, while shortcodes, math and code fences are written in a canonical style. Shortcode
+//! parameters are separated by `, ` (or put on separate lines if the list spans several lines)
+//! and values are only quoted when they have to be. Math is
+//! written with `$` delimiters and code fence attributes in the order flags, `#id`, `.class`,
+//! `key=value`. Printing a document that is already in the canonical style returns its source.
+
+use crate::ast::visitor::AstVisit;
+use crate::ast::{Ast, Block, CodeBlock, Command, Math, Parameter, Value};
+use crate::document::Document;
+use crate::raw::Attributes;
+use anyhow::Result;
+use std::ops::Range;
+
+/// Prints the document in the canonical style.
+pub fn print_document(doc: &Document<Ast>) -> String {
+    print_ast(&doc.content)
+}
+
+/// Prints the Ast in the canonical style. The Ast must have been parsed from its `source`.
+pub fn print_ast(ast: &Ast) -> String {
+    let mut printer = Printer {
+        source: &ast.source,
+        out: String::with_capacity(ast.source.len()),
+    };
+    printer.splice(0..ast.source.len(), &ast.blocks);
+    printer.out
+}
+
+enum Element<'a> {
+    Command(&'a Command),
+    Math(&'a Math),
+    CodeBlock(&'a CodeBlock),
+}
+
+impl Element<'_> {
+    fn range(&self) -> Range<usize> {
+        match self {
+            Element::Command(cmd) => cmd.span.range.clone(),
+            Element::Math(math) => math.span.range.clone(),
+            Element::CodeBlock(block) => block.span.range.clone(),
+        }
+    }
+}
+
+/// Collects the elements that are printed in the canonical style. Shortcodes are not descended
+/// into since they print their own content.
+#[derive(Default)]
+struct ElementCollector<'a> {
+    found: Vec<Element<'a>>,
+}
+
+impl<'a> AstVisit<'a> for ElementCollector<'a> {
+    fn visit_code_block(&mut self, block: &'a CodeBlock) -> Result<()> {
+        self.found.push(Element::CodeBlock(block));
+        Ok(())
+    }
+
+    fn visit_math(&mut self, math: &'a Math) -> Result<()> {
+        self.found.push(Element::Math(math));
+        Ok(())
+    }
+
+    fn visit_command(&mut self, cmd: &'a Command) -> Result<()> {
+        self.found.push(Element::Command(cmd));
+        Ok(())
+    }
+}
+
+struct Printer<'a> {
+    source: &'a str,
+    out: String,
+}
+
+impl Printer<'_> {
+    /// Copies the source in `range`, with the elements of `blocks` printed in the canonical style.
+    fn splice(&mut self, range: Range<usize>, blocks: &[Block]) {
+        let mut collector = ElementCollector::default();
+        collector
+            .walk_ast(blocks)
+            .expect("collecting elements cannot fail");
+
+        let mut pos = range.start;
+        for elem in collector.found {
+            let elem_range = elem.range();
+            if elem_range.start < pos || elem_range.end > range.end || elem_range.is_empty() {
+                continue;
+            }
+            self.out.push_str(&self.source[pos..elem_range.start]);
+            match elem {
+                Element::Command(cmd) => self.print_command(cmd),
+                Element::Math(math) => self.print_math(math),
+                Element::CodeBlock(block) => self.print_code_block(block),
+            }
+            pos = elem_range.end;
+        }
+        self.out.push_str(&self.source[pos..range.end]);
+    }
+
+    fn print_command(&mut self, cmd: &Command) {
+        let range = cmd.span.range.clone();
+        self.out.push('#');
+        self.out.push_str(&cmd.function);
+        if let Some(label) = &cmd.label {
+            self.out.push('|');
+            self.out.push_str(label);
+        }
+
+        let mut header_end = range.start + 1 + cmd.function.len();
+        if let Some(label) = &cmd.label {
+            header_end += 1 + label.len();
+        }
+
+        if !cmd.parameters.is_empty() {
+            let last = cmd.parameters.last().unwrap().span.range.end;
+            let params_end = self.source[last..range.end]
+                .find(')')
+                .map_or(range.end, |idx| last + idx + 1);
+            // Parameter lists written over several lines get one parameter per line.
+            let multiline = self.source[header_end..params_end].contains('\n');
+            let (open, separator, close) = if multiline {
+                ("(\n    ", ",\n    ", "\n)")
+            } else {
+                ("(", ", ", ")")
+            };
+
+            self.out.push_str(open);
+            for (idx, param) in cmd.parameters.iter().enumerate() {
+                if idx > 0 {
+                    self.out.push_str(separator);
+                }
+                self.print_parameter(param);
+            }
+            self.out.push_str(close);
+            header_end = params_end;
+        }
+
+        if let Some(body) = &cmd.body {
+            // Bodies may be closed with the name of the shortcode, e.g. `#box{...}box#`.
+            let tag = format!("}}{}#", cmd.function);
+            let body_end = if self.source[..range.end].ends_with(&tag) {
+                range.end - tag.len()
+            } else {
+                range.end - 1
+            };
+
+            self.out.push('{');
+            self.splice(header_end + 1..body_end, body);
+            self.out.push('}');
+        }
+    }
+
+    fn print_parameter(&mut self, param: &Parameter) {
+        let mut value_start = param.span.range.start;
+        if let Some(key) = &param.key {
+            self.out.push_str(key);
+            self.out.push('=');
+            // There may be spaces around the `=` in the source.
+            let after_key = &self.source[value_start + key.len()..param.span.range.end];
+            let value = after_key
+                .trim_start()
+                .strip_prefix('=')
+                .unwrap_or(after_key);
+            value_start = param.span.range.end - value.trim_start().len();
+        }
+
+        match &param.value {
+            Value::Flag(flag) => {
+                self.out.push(':');
+                self.out.push_str(flag);
+            }
+            Value::String(s) => {
+                if is_bare_value(s) {
+                    self.out.push_str(s);
+                } else {
+                    self.out.push('"');
+                    self.out.push_str(&escape(s, &['"', '”']));
+                    self.out.push('"');
+                }
+            }
+            Value::Content(blocks) => {
+                self.out.push('{');
+                self.splice(value_start + 1..param.span.range.end - 1, blocks);
+                self.out.push('}');
+            }
+        }
+    }
+
+    fn print_math(&mut self, math: &Math) {
+        let delimiter = if math.display_block { "$$" } else { "$" };
+        self.out.push_str(delimiter);
+        self.out.push_str(&math.source);
+        self.out.push_str(delimiter);
+        if let Some(label) = &math.label {
+            self.out.push('|');
+            self.out.push_str(label);
+        }
+    }
+
+    fn print_code_block(&mut self, block: &CodeBlock) {
+        let mut text = &self.source[block.span.range.clone()];
+        if let Some(label) = &block.label {
+            text = text
+                .strip_suffix(label.as_str())
+                .and_then(|t| t.strip_suffix('|'))
+                .unwrap_or(text);
+        }
+        let fence_len = text.len() - text.trim_start_matches('`').len();
+        let fence = &text[..fence_len];
+
+        // The code is copied as written, since it may contain exercise markers.
+        let Some((_, code)) = text[fence_len..].split_once('\n') else {
+            self.out.push_str(&self.source[block.span.range.clone()]);
+            return;
+        };
+
+        self.out.push_str(fence);
+        self.out.push_str(&print_attributes(&block.attributes));
+        self.out.push('\n');
+        self.out.push_str(code);
+        if let Some(label) = &block.label {
+            self.out.push('|');
+            self.out.push_str(label);
+        }
+    }
+}
+
+/// Whether a parameter value can be written without quotes.
+fn is_bare_value(value: &str) -> bool {
+    value.chars().next().is_some_and(char::is_alphanumeric)
+        && value.trim_end() == value
+        && !value.contains(|c: char| "\\,()\"“”`={}\n".contains(c))
+}
+
+/// Escapes the given delimiters and the backslashes that would otherwise be read as escapes.
+fn escape(value: &str, delimiters: &[char]) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        if delimiters.contains(&c) {
+            out.push('\\');
+        } else if c == '\\' {
+            match chars.peek() {
+                None => out.push('\\'),
+                Some(next) if "\"”\\,()".contains(*next) => out.push('\\'),
+                _ => {}
+            }
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn print_attributes(attributes: &Attributes) -> String {
+    let mut parts: Vec<String> = attributes
+        .flags
+        .iter()
+        .map(|f| escape(f, &[',', '(', ')', '"']))
+        .collect();
+    if let Some(id) = attributes.id() {
+        parts.push(format!("#{id}"));
+    }
+    parts.extend(attributes.classes.iter().map(|c| format!(".{c}")));
+    parts.extend(
+        attributes
+            .values
+            .iter()
+            .filter(|(k, _)| k.as_str() != "id")
+            .map(|(k, v)| format!("{k}={}", escape(v, &[',', '(', ')', '"']))),
+    );
+    parts.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::print_document;
+    use crate::ast::Ast;
+    use crate::document::Document;
+
+    fn print(input: &str) -> String {
+        let doc: Document<Ast> = Document::try_from(input).expect("parse error");
+        print_document(&doc)
+    }
+
+    #[test]
+    fn canonical_style() {
+        let input = "---\ntitle: Test\n---\n# Heading\n\nText #cmd(a,  key=\"value\", flag=:x, q=\"a, b\", c={*md* $x$})\n\n#box|lbl(title=\"Title\",\n  x=1){\nSome $$y$$|eq text \\(z\\)\n}\n\n```python,   #main,  cell\nprint('x') # placeholder\n```|code\n";
+        let expected = "---\ntitle: Test\n---\n# Heading\n\nText #cmd(a, key=value, flag=:x, q=\"a, b\", c={*md* $x$})\n\n#box|lbl(\n    title=Title,\n    x=1\n){\nSome $$y$$|eq text $z$\n}\n\n```python, cell, #main\nprint('x') # placeholder\n```|code\n";
+
+        let printed = print(input);
+        assert_eq!(expected, printed);
+        assert_eq!(printed, print(&printed));
+    }
+
+    #[test]
+    fn spaced_parameters() {
+        let printed = print("Text #cmd(a , b = 1, c = {*abc*}, d = \"x {y}\").\n");
+        assert_eq!("Text #cmd(a, b=1, c={*abc*}, d=\"x {y}\").\n", printed);
+    }
+
+    #[test]
+    fn docs_are_stable() {
+        let input = include_str!("../../resources/tests/commands.md");
+        let printed = print(input);
+        assert_eq!(printed, print(&printed));
+    }
+}
@@ -0,0 +1,157 @@
+//! Helpers for finding nodes in the Ast. All queries search the whole tree, including shortcode
+//! bodies and parameters, and return the nodes in document order.
+
+use crate::ast::visitor::AstVisit;
+use crate::ast::{Block, Command, Inline};
+use anyhow::Result;
+
+/// Finds all shortcodes with the given name.
+pub fn find_commands<'a>(blocks: &'a [Block], name: &str) -> Vec<&'a Command> {
+    let mut visitor = CommandCollector {
+        name,
+        found: Vec::new(),
+    };
+    visitor
+        .walk_ast(blocks)
+        .expect("collecting commands cannot fail");
+    visitor.found
+}
+
+/// Finds all headings with a level of at most `max_level`.
+pub fn find_headings(blocks: &[Block], max_level: u8) -> Vec<&Block> {
+    find_blocks(
+        blocks,
+        |b| matches!(b, Block::Heading { lvl, .. } if *lvl <= max_level),
+    )
+}
+
+/// Finds all blocks for which `predicate` returns true.
+pub fn find_blocks(blocks: &[Block], predicate: impl Fn(&Block) -> bool) -> Vec<&Block> {
+    let mut visitor = BlockCollector {
+        predicate,
+        found: Vec::new(),
+    };
+    visitor
+        .walk_ast(blocks)
+        .expect("collecting blocks cannot fail");
+    visitor.found
+}
+
+/// Finds all inlines for which `predicate` returns true.
+pub fn find_inlines(blocks: &[Block], predicate: impl Fn(&Inline) -> bool) -> Vec<&Inline> {
+    let mut visitor = InlineCollector {
+        predicate,
+        found: Vec::new(),
+    };
+    visitor
+        .walk_ast(blocks)
+        .expect("collecting inlines cannot fail");
+    visitor.found
+}
+
+struct CommandCollector<'a, 'n> {
+    name: &'n str,
+    found: Vec<&'a Command>,
+}
+
+impl<'a> AstVisit<'a> for CommandCollector<'a, '_> {
+    fn visit_command(&mut self, cmd: &'a Command) -> Result<()> {
+        if cmd.function == self.name {
+            self.found.push(cmd);
+        }
+        self.walk_command(cmd)
+    }
+}
+
+struct BlockCollector<'a, F> {
+    predicate: F,
+    found: Vec<&'a Block>,
+}
+
+impl<'a, F: Fn(&Block) -> bool> AstVisit<'a> for BlockCollector<'a, F> {
+    fn visit_block(&mut self, block: &'a Block) -> Result<()> {
+        if (self.predicate)(block) {
+            self.found.push(block);
+        }
+        self.walk_block(block)
+    }
+}
+
+struct InlineCollector<'a, F> {
+    predicate: F,
+    found: Vec<&'a Inline>,
+}
+
+impl<'a, F: Fn(&Inline) -> bool> AstVisit<'a> for InlineCollector<'a, F> {
+    fn visit_inline(&mut self, inline: &'a Inline) -> Result<()> {
+        if (self.predicate)(inline) {
+            self.found.push(inline);
+        }
+        self.walk_inline(inline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_commands, find_headings, find_inlines};
+    use crate::ast::visitor::Fold;
+    use crate::ast::{Block, Inline};
+    use crate::document::Document;
+    use anyhow::Result;
+
+    fn parse(input: &str) -> Vec<Block> {
+        let doc: Document<crate::ast::Ast> = Document::try_from(input).expect("parse error");
+        doc.content.blocks
+    }
+
+    #[test]
+    fn queries() {
+        let blocks = parse("# One\n\n## Two #ref(a)\n\n#box{\n### Three\n\nSee #ref(b)\n}\n");
+
+        let refs: Vec<_> = find_commands(&blocks, "ref")
+            .into_iter()
+            .map(|c| c.span.range.clone())
+            .collect();
+        assert_eq!(vec![14..21, 44..51], refs);
+
+        let levels: Vec<_> = find_headings(&blocks, 2)
+            .into_iter()
+            .map(|b| match b {
+                Block::Heading { lvl, .. } => *lvl,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(vec![1, 2], levels);
+        assert_eq!(3, find_headings(&blocks, 6).len());
+
+        let texts = find_inlines(&blocks, |i| matches!(i, Inline::Text(..)));
+        assert_eq!(4, texts.len());
+    }
+
+    struct Duplicate;
+
+    impl Fold for Duplicate {
+        fn fold_inline(&mut self, inline: Inline) -> Result<Vec<Inline>> {
+            match inline {
+                Inline::Code(..) => Ok(vec![inline.clone(), inline]),
+                Inline::SoftBreak(_) => Ok(vec![]),
+                inline => Ok(vec![self.walk_inline(inline)?]),
+            }
+        }
+    }
+
+    #[test]
+    fn fold_replaces_nodes() {
+        let mut blocks = parse("*a `b`*\nc");
+        Duplicate.fold_ast(&mut blocks).unwrap();
+
+        let Block::Paragraph(inlines, _) = &blocks[0] else {
+            panic!("expected paragraph")
+        };
+        let Inline::Styled(styled, ..) = &inlines[0] else {
+            panic!("expected emphasis")
+        };
+        assert_eq!(3, styled.len());
+        assert_eq!(2, inlines.len());
+    }
+}
@@ -1,5 +1,6 @@
-use crate::ast::{Block, CodeBlock, Command, Inline, Math, Style};
+use crate::ast::{Block, CodeBlock, Command, Inline, Math, RawContent, Style, TableRow, Value};
 
+use crate::raw::Attributes;
 use anyhow::Result;
 use cowstr::CowStr;
 
@@ -17,12 +18,26 @@ pub trait AstVisitor {
     fn walk_block(&mut self, block: &mut Block) -> Result<()> {
         match *block {
             Block::Heading { .. } => Ok(()),
-            Block::Plain(ref mut i) => self.visit_vec_inline(i),
-            Block::Paragraph(ref mut is) | Block::BlockQuote(ref mut is) => {
-                self.visit_vec_inline(is)
+            Block::Plain(ref mut i, _) => self.visit_vec_inline(i),
+            Block::Paragraph(ref mut is, _) => self.visit_vec_inline(is),
+            Block::BlockQuote(ref mut blocks, _) | Block::Admonition(_, ref mut blocks, _) => {
+                self.visit_vec_block(blocks)
             }
-            Block::List(_, ref mut blocks) => self.visit_vec_block(blocks),
-            Block::ListItem(ref mut blocks) => self.visit_vec_block(blocks),
+            Block::List(_, ref mut blocks, _) => self.visit_vec_block(blocks),
+            Block::ListItem(ref mut blocks, _) => self.visit_vec_block(blocks),
+            Block::Table {
+                ref mut header,
+                ref mut rows,
+                ..
+            } => {
+                self.visit_table_row(header)?;
+                rows.iter_mut().try_for_each(|r| self.visit_table_row(r))
+            }
+            Block::FootnoteDefinition(_, ref mut blocks, _) => self.visit_vec_block(blocks),
+            Block::CodeBlock(ref mut block) => self.visit_code_block(block),
+            Block::Math(ref mut math) => self.visit_math(math),
+            Block::Raw(ref mut raw) => self.visit_raw(raw),
+            Block::Command(ref mut cmd) => self.visit_command(cmd),
         }
     }
 
@@ -32,25 +47,40 @@ pub trait AstVisitor {
 
     fn walk_inline(&mut self, inline: &mut Inline) -> Result<()> {
         match inline {
-            Inline::Text(_) => Ok(()),
-            Inline::Styled(ref mut is, ref mut style) => self.visit_styled(is, style),
-            Inline::Code(s) => self.visit_code(s),
-            Inline::SoftBreak => Ok(()),
-            Inline::HardBreak => Ok(()),
-            Inline::Rule => Ok(()),
-            Inline::Image(_tp, _url, _alt, _inner) => Ok(()),
-            Inline::Link(_tp, _url, _alt, _inner) => Ok(()),
-            Inline::Html(h) => self.visit_html_inline(h),
+            Inline::Text(..) => Ok(()),
+            Inline::Styled(ref mut is, ref mut style, _) => self.visit_styled(is, style),
+            Inline::Code(s, _) => self.visit_code(s),
+            Inline::SoftBreak(_) => Ok(()),
+            Inline::HardBreak(_) => Ok(()),
+            Inline::Rule(_) => Ok(()),
+            Inline::Image(..) => Ok(()),
+            Inline::Link(..) => Ok(()),
+            Inline::Html(h, _) => self.visit_html_inline(h),
+            Inline::Raw(raw) => self.visit_raw(raw),
+            Inline::Span(inner, attributes, _) => self.visit_span(inner, attributes),
             Inline::Math(math) => self.visit_math(math),
             Inline::Command(cmd) => self.visit_command(cmd),
             Inline::CodeBlock(block) => self.visit_code_block(block),
+            Inline::FootnoteReference(..) => Ok(()),
         }
     }
 
+    fn walk_table_row(&mut self, row: &mut TableRow) -> Result<()> {
+        row.iter_mut().try_for_each(|c| self.visit_vec_inline(c))
+    }
+
+    fn visit_table_row(&mut self, row: &mut TableRow) -> Result<()> {
+        self.walk_table_row(row)
+    }
+
     fn visit_html_inline(&mut self, _input: &str) -> Result<()> {
         Ok(())
     }
 
+    fn visit_raw(&mut self, _raw: &mut RawContent) -> Result<()> {
+        Ok(())
+    }
+
     fn visit_vec_block(&mut self, blocks: &mut Vec<Block>) -> Result<()> {
         self.walk_vec_block(blocks)
     }
@@ -64,6 +94,14 @@ pub trait AstVisitor {
         self.walk_inline(inline)
     }
 
+    fn visit_span(
+        &mut self,
+        inlines: &mut Vec<Inline>,
+        _attributes: &mut Attributes,
+    ) -> Result<()> {
+        self.walk_vec_inline(inlines)
+    }
+
     fn visit_styled(&mut self, inlines: &mut Vec<Inline>, _style: &mut Style) -> Result<()> {
         self.walk_vec_inline(inlines)
     }
@@ -94,3 +132,230 @@ pub trait AstVisitor {
         self.walk_command(&mut cmd.body)
     }
 }
+
+/// Read-only counterpart of [AstVisitor]. Unlike the mutable visitor, every node in the tree is
+/// visited, including the content of headings, links, images and shortcode parameters. The
+/// lifetime allows implementors to keep references to the nodes they visit.
+pub trait AstVisit<'a> {
+    fn walk_ast(&mut self, ast: &'a [Block]) -> Result<()> {
+        self.visit_vec_block(ast)
+    }
+
+    fn walk_vec_block(&mut self, blocks: &'a [Block]) -> Result<()> {
+        blocks.iter().try_for_each(|b| self.visit_block(b))
+    }
+
+    fn walk_block(&mut self, block: &'a Block) -> Result<()> {
+        match block {
+            Block::Heading { inner, .. } | Block::Plain(inner, _) | Block::Paragraph(inner, _) => {
+                self.visit_vec_inline(inner)
+            }
+            Block::BlockQuote(blocks, _)
+            | Block::Admonition(_, blocks, _)
+            | Block::List(_, blocks, _)
+            | Block::ListItem(blocks, _)
+            | Block::FootnoteDefinition(_, blocks, _) => self.visit_vec_block(blocks),
+            Block::Table { header, rows, .. } => {
+                self.visit_table_row(header)?;
+                rows.iter().try_for_each(|r| self.visit_table_row(r))
+            }
+            Block::CodeBlock(block) => self.visit_code_block(block),
+            Block::Math(math) => self.visit_math(math),
+            Block::Raw(raw) => self.visit_raw(raw),
+            Block::Command(cmd) => self.visit_command(cmd),
+        }
+    }
+
+    fn walk_vec_inline(&mut self, inlines: &'a [Inline]) -> Result<()> {
+        inlines.iter().try_for_each(|i| self.visit_inline(i))
+    }
+
+    fn walk_inline(&mut self, inline: &'a Inline) -> Result<()> {
+        match inline {
+            Inline::Styled(inner, ..)
+            | Inline::Span(inner, ..)
+            | Inline::Image(_, _, _, inner, _)
+            | Inline::Link(_, _, _, inner, _) => self.visit_vec_inline(inner),
+            Inline::CodeBlock(block) => self.visit_code_block(block),
+            Inline::Raw(raw) => self.visit_raw(raw),
+            Inline::Math(math) => self.visit_math(math),
+            Inline::Command(cmd) => self.visit_command(cmd),
+            Inline::Text(..)
+            | Inline::Code(..)
+            | Inline::SoftBreak(_)
+            | Inline::HardBreak(_)
+            | Inline::Rule(_)
+            | Inline::Html(..)
+            | Inline::FootnoteReference(..) => Ok(()),
+        }
+    }
+
+    fn walk_table_row(&mut self, row: &'a TableRow) -> Result<()> {
+        row.iter().try_for_each(|c| self.visit_vec_inline(c))
+    }
+
+    /// Walks the content of parameters and the body.
+    fn walk_command(&mut self, cmd: &'a Command) -> Result<()> {
+        for param in &cmd.parameters {
+            if let Value::Content(blocks) = &param.value {
+                self.visit_vec_block(blocks)?;
+            }
+        }
+        if let Some(body) = &cmd.body {
+            self.visit_vec_block(body)?;
+        }
+        Ok(())
+    }
+
+    fn visit_vec_block(&mut self, blocks: &'a [Block]) -> Result<()> {
+        self.walk_vec_block(blocks)
+    }
+    fn visit_block(&mut self, block: &'a Block) -> Result<()> {
+        self.walk_block(block)
+    }
+    fn visit_vec_inline(&mut self, inlines: &'a [Inline]) -> Result<()> {
+        self.walk_vec_inline(inlines)
+    }
+    fn visit_inline(&mut self, inline: &'a Inline) -> Result<()> {
+        self.walk_inline(inline)
+    }
+    fn visit_table_row(&mut self, row: &'a TableRow) -> Result<()> {
+        self.walk_table_row(row)
+    }
+    fn visit_code_block(&mut self, _block: &'a CodeBlock) -> Result<()> {
+        Ok(())
+    }
+    fn visit_math(&mut self, _math: &'a Math) -> Result<()> {
+        Ok(())
+    }
+    fn visit_raw(&mut self, _raw: &'a RawContent) -> Result<()> {
+        Ok(())
+    }
+    fn visit_command(&mut self, cmd: &'a Command) -> Result<()> {
+        self.walk_command(cmd)
+    }
+}
+
+/// Transforms the Ast by value. Each node can be replaced by zero or more nodes, which makes it
+/// possible to remove nodes or insert new ones next to them without tracking indices. The default
+/// implementations rebuild the tree unchanged, so implementors only override the methods for the
+/// nodes they transform. Override `fold_*` to replace a node and call the matching `walk_*` to
+/// transform its children.
+pub trait Fold {
+    /// Transforms the blocks in place.
+    fn fold_ast(&mut self, ast: &mut Vec<Block>) -> Result<()> {
+        *ast = self.fold_vec_block(std::mem::take(ast))?;
+        Ok(())
+    }
+
+    fn fold_vec_block(&mut self, blocks: Vec<Block>) -> Result<Vec<Block>> {
+        let mut folded = Vec::with_capacity(blocks.len());
+        for block in blocks {
+            folded.extend(self.fold_block(block)?);
+        }
+        Ok(folded)
+    }
+
+    fn fold_block(&mut self, block: Block) -> Result<Vec<Block>> {
+        Ok(vec![self.walk_block(block)?])
+    }
+
+    fn walk_block(&mut self, block: Block) -> Result<Block> {
+        Ok(match block {
+            Block::Heading {
+                lvl,
+                id,
+                classes,
+                attributes,
+                inner,
+                span,
+            } => Block::Heading {
+                lvl,
+                id,
+                classes,
+                attributes,
+                inner: self.fold_vec_inline(inner)?,
+                span,
+            },
+            Block::Plain(inner, span) => Block::Plain(self.fold_vec_inline(inner)?, span),
+            Block::Paragraph(inner, span) => Block::Paragraph(self.fold_vec_inline(inner)?, span),
+            Block::BlockQuote(blocks, span) => {
+                Block::BlockQuote(self.fold_vec_block(blocks)?, span)
+            }
+            Block::Admonition(kind, blocks, span) => {
+                Block::Admonition(kind, self.fold_vec_block(blocks)?, span)
+            }
+            Block::List(start, blocks, span) => {
+                Block::List(start, self.fold_vec_block(blocks)?, span)
+            }
+            Block::ListItem(blocks, span) => Block::ListItem(self.fold_vec_block(blocks)?, span),
+            Block::Table {
+                alignments,
+                header,
+                rows,
+                span,
+            } => Block::Table {
+                alignments,
+                header: self.fold_table_row(header)?,
+                rows: rows
+                    .into_iter()
+                    .map(|r| self.fold_table_row(r))
+                    .collect::<Result<_>>()?,
+                span,
+            },
+            Block::FootnoteDefinition(label, blocks, span) => {
+                Block::FootnoteDefinition(label, self.fold_vec_block(blocks)?, span)
+            }
+            Block::Command(cmd) => Block::Command(self.fold_command(cmd)?),
+            block @ (Block::CodeBlock(_) | Block::Math(_) | Block::Raw(_)) => block,
+        })
+    }
+
+    fn fold_vec_inline(&mut self, inlines: Vec<Inline>) -> Result<Vec<Inline>> {
+        let mut folded = Vec::with_capacity(inlines.len());
+        for inline in inlines {
+            folded.extend(self.fold_inline(inline)?);
+        }
+        Ok(folded)
+    }
+
+    fn fold_inline(&mut self, inline: Inline) -> Result<Vec<Inline>> {
+        Ok(vec![self.walk_inline(inline)?])
+    }
+
+    fn walk_inline(&mut self, inline: Inline) -> Result<Inline> {
+        Ok(match inline {
+            Inline::Styled(inner, style, span) => {
+                Inline::Styled(self.fold_vec_inline(inner)?, style, span)
+            }
+            Inline::Span(inner, attributes, span) => {
+                Inline::Span(self.fold_vec_inline(inner)?, attributes, span)
+            }
+            Inline::Image(tp, url, alt, inner, span) => {
+                Inline::Image(tp, url, alt, self.fold_vec_inline(inner)?, span)
+            }
+            Inline::Link(tp, url, alt, inner, span) => {
+                Inline::Link(tp, url, alt, self.fold_vec_inline(inner)?, span)
+            }
+            Inline::Command(cmd) => Inline::Command(self.fold_command(cmd)?),
+            inline => inline,
+        })
+    }
+
+    fn fold_table_row(&mut self, row: TableRow) -> Result<TableRow> {
+        row.into_iter()
+            .map(|cell| self.fold_vec_inline(cell))
+            .collect()
+    }
+
+    /// Transforms the content of parameters and the body.
+    fn fold_command(&mut self, mut cmd: Command) -> Result<Command> {
+        for param in &mut cmd.parameters {
+            if let Value::Content(blocks) = &mut param.value {
+                *blocks = self.fold_vec_block(std::mem::take(blocks))?;
+            }
+        }
+        cmd.body = cmd.body.map(|b| self.fold_vec_block(b)).transpose()?;
+        Ok(cmd)
+    }
+}
@@ -108,15 +108,29 @@ pub fn parse_code_string(content: CowStr) -> Result<CodeContent, Box<pest::error
         .into_iter()
         .filter_map(|v| parse_value(&content, v, &mut meta).transpose())
         .collect::<anyhow::Result<Vec<CodeElem>, Box<pest::error::Error<Rule>>>>()?;
-    let mut hasher = DefaultHasher::new();
-    content.hash(&mut hasher);
     Ok(CodeContent {
         blocks,
         meta,
-        hash: hasher.finish(),
+        hash: hash_code(&content),
     })
 }
 
+/// Wraps code that could not be parsed as plain source, so the rest of the document can still be
+/// processed.
+pub(crate) fn unparsed_code_string(content: CowStr) -> CodeContent {
+    CodeContent {
+        blocks: vec![CodeElem::Src(content.to_string())],
+        meta: LinkedHashMap::new(),
+        hash: hash_code(&content),
+    }
+}
+
+fn hash_code(content: &CowStr) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
 pub(crate) fn human_errors(error: pest::error::Error<Rule>) -> Box<pest::error::Error<Rule>> {
     Box::new(error.renamed_rules(|rule| match *rule {
         Rule::source_code_block => "code".to_owned(),
@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use std::cmp::min;
+use std::fmt::{Display, Formatter};
 use std::ops::Range;
 
 // #[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
@@ -30,26 +31,133 @@ use std::ops::Range;
 //     }
 // }
 
+/// A line/column position in the source. Both values are 1-based (zero means unknown).
+#[derive(Debug, PartialEq, Eq, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct LineCol {
+    pub line: usize,
+    pub column: usize,
+}
+
 #[derive(Debug, PartialEq, Default, Clone, Serialize, Deserialize)]
 pub struct Span {
     pub range: Range<usize>,
+    /// Position of the start of the span. Only set once the full document has been parsed.
+    #[serde(default)]
+    pub start: LineCol,
+    /// Position of the end of the span. Only set once the full document has been parsed.
+    #[serde(default)]
+    pub end: LineCol,
+    /// Index of the notebook cell the span originates from. Positions are relative to the cell
+    /// when this is set.
+    #[serde(default)]
+    pub cell: Option<usize>,
 }
 
 impl Span {
-    pub fn new(start: usize, end: usize) -> Self {
-        Self { range: start..end }
+    pub const fn new(start: usize, end: usize) -> Self {
+        Self {
+            range: start..end,
+            start: LineCol { line: 0, column: 0 },
+            end: LineCol { line: 0, column: 0 },
+            cell: None,
+        }
     }
 
+    /// The source of the span with up to `margin` bytes of context on both sides. The range is
+    /// clamped to the input (and widened to character boundaries), so it never panics.
     pub fn get_with_margin<'a>(&self, input: &'a str, margin: usize) -> &'a str {
-        &input[self.range.start.checked_sub(margin).unwrap_or_default()
-            ..min(self.range.end + margin, input.len())]
+        let mut start = min(self.range.start.saturating_sub(margin), input.len());
+        let mut end = min(self.range.end.saturating_add(margin), input.len()).max(start);
+        while !input.is_char_boundary(start) {
+            start -= 1;
+        }
+        while !input.is_char_boundary(end) {
+            end += 1;
+        }
+        &input[start..end]
+    }
+
+    /// Computes the line/column positions from the byte range.
+    pub fn set_position(&mut self, index: &LineIndex) {
+        self.start = index.position(self.range.start);
+        self.end = index.position(self.range.end);
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(cell) = self.cell {
+            write!(f, "cell {}, ", cell)?;
+        }
+        if self.start.line == 0 {
+            write!(f, "position {}", self.range.start)
+        } else {
+            write!(f, "line {}, column {}", self.start.line, self.start.column)
+        }
     }
 }
 
 impl From<pest::Span<'_>> for Span {
     fn from(value: pest::Span) -> Self {
-        Self {
-            range: value.start()..value.end(),
+        Self::new(value.start(), value.end())
+    }
+}
+
+/// Byte offsets of line starts, used to convert offsets into line/column positions.
+pub struct LineIndex {
+    line_starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(input: &str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(input.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+        LineIndex { line_starts }
+    }
+
+    /// Returns the line and column of a byte offset. The column is counted in bytes.
+    pub fn position(&self, offset: usize) -> LineCol {
+        let line = self.line_starts.partition_point(|&start| start <= offset);
+        LineCol {
+            line,
+            column: offset - self.line_starts[line - 1] + 1,
         }
     }
 }
+
+/// A problem found while parsing a document, e.g. a syntax error that the parser recovered from.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            message: message.into(),
+            span,
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.span, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Span;
+
+    #[test]
+    fn margin_is_clamped() {
+        let input = "ab ÆØÅ cd";
+        assert_eq!("ÆØ", Span::new(4, 5).get_with_margin(input, 1));
+        assert_eq!(input, Span::new(0, 3).get_with_margin(input, 100));
+        assert_eq!("", Span::new(40, 50).get_with_margin(input, 2));
+        assert_eq!(" cd", Span::new(10, 20).get_with_margin(input, 1));
+    }
+}
@@ -1,6 +1,8 @@
-use crate::ast::Ast;
-use crate::raw::{parse_to_doc, ComposedMarkdown, RawDocument, Special};
-use anyhow::Result;
+use crate::ast::parser::parse_elements;
+use crate::ast::{for_each_span_mut, Ast};
+use crate::common::{Diagnostic, LineIndex, Span};
+use crate::raw::{parse_to_doc, parse_to_doc_recovering, Element, RawDocument, Special};
+use anyhow::{bail, Result};
 use linked_hash_map::LinkedHashMap;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -33,6 +35,10 @@ pub struct Metadata {
     pub layout: LayoutSettings,
     #[serde(default)]
     pub exclude_outputs: Option<Vec<String>>,
+    /// Values for `#var` shortcodes in this document. They take precedence over the project's
+    /// variables.
+    #[serde(default)]
+    pub variables: LinkedHashMap<String, Value>,
 
     #[serde(flatten)]
     pub user_defined: LinkedHashMap<String, Value>,
@@ -70,31 +76,86 @@ pub enum Image {
 }
 
 fn parse_raw(doc: RawDocument) -> Result<Document<Ast>> {
-    let composed = ComposedMarkdown::from(doc.src);
-    let code_outputs = composed
-        .children
+    let meta = parse_meta(&doc)?;
+    let (doc, diagnostics) = build_document(doc, meta);
+    if let Some(diagnostic) = diagnostics.first() {
+        bail!("{}", diagnostic);
+    }
+    Ok(doc)
+}
+
+fn parse_meta(doc: &RawDocument) -> Result<Metadata, serde_yaml::Error> {
+    doc.meta
+        .as_ref()
+        .map_or(Ok(Metadata::default()), |meta| serde_yaml::from_str(meta))
+}
+
+/// Builds the AST of a parsed document. Problems found while building it are returned as
+/// diagnostics.
+fn build_document(doc: RawDocument, meta: Metadata) -> (Document<Ast>, Vec<Diagnostic>) {
+    let code_outputs = doc
+        .src
         .iter()
-        .filter_map(|c| match &c.elem {
-            Special::CodeBlock { inner, .. } => Some((inner.hash, CodeOutput::default())),
+        .filter_map(|e| match &e.element {
+            Element::Special(_, Special::CodeBlock { inner, .. }) => {
+                Some((inner.hash, CodeOutput::default()))
+            }
             _ => None,
         })
         .collect();
 
-    let ast = composed.into();
+    let mut diagnostics = Vec::new();
+    let mut ast = parse_elements(doc.src, &mut diagnostics);
+    let index = LineIndex::new(&doc.input);
+    for_each_span_mut(&mut ast, &mut |span| span.set_position(&index));
+    diagnostics
+        .iter_mut()
+        .for_each(|d| d.span.set_position(&index));
 
     let doc = Document {
         content: Ast {
             blocks: ast,
             source: doc.input,
         },
-        meta: doc.meta.map_or(
-            Ok::<Metadata, serde_yaml::Error>(Metadata::default()),
-            |meta| serde_yaml::from_str(&meta),
-        )?,
+        meta,
         code_outputs,
     };
+    (doc, diagnostics)
+}
 
-    Ok(doc)
+impl Document<Ast> {
+    /// Parses a document without stopping at the first problem. Syntax errors and invalid front
+    /// matter are returned as diagnostics together with the parts of the document that could be
+    /// parsed.
+    pub fn parse_with_diagnostics(input: &str) -> (Self, Vec<Diagnostic>) {
+        let (raw, mut diagnostics) = parse_to_doc_recovering(input);
+
+        let meta = parse_meta(&raw).unwrap_or_else(|e| {
+            let offset = raw
+                .meta
+                .as_ref()
+                .and_then(|meta| input.find(meta.as_str()))
+                .unwrap_or_default();
+            let pos = offset + e.location().map(|l| l.index()).unwrap_or_default();
+            // The location is reported through the span instead.
+            let message = e.to_string();
+            let message = message.split(" at line ").next().unwrap_or_default();
+            diagnostics.push(Diagnostic::new(
+                format!("invalid front matter: {}", message),
+                Span::new(pos, pos),
+            ));
+            Metadata::default()
+        });
+
+        let index = LineIndex::new(input);
+        diagnostics
+            .iter_mut()
+            .for_each(|d| d.span.set_position(&index));
+
+        let (doc, mut unplaced) = build_document(raw, meta);
+        diagnostics.append(&mut unplaced);
+        (doc, diagnostics)
+    }
 }
 
 impl TryFrom<&str> for Document<Ast> {
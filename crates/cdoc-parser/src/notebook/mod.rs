@@ -4,7 +4,8 @@ use std::collections::hash_map::DefaultHasher;
 
 use anyhow::Result;
 
-use crate::ast::Ast;
+use crate::ast::{for_each_span_mut, Ast};
+use crate::common::{LineIndex, Span};
 use crate::document::{CodeOutput, Document, Image, Metadata};
 
 use crate::document;
@@ -17,7 +18,7 @@ use serde_with::{formats::PreferOne, serde_as, EnumMap, OneOrMany};
 use std::collections::HashMap;
 use std::default::Default;
 use std::hash::{Hash, Hasher};
-use std::io::{BufWriter, Write};
+use std::io::Write;
 
 /// Top-level notebook structure (the type is a mostly complete implementation of the official
 /// notebook specification (http://ipython.org/ipython-doc/3/notebook/nbformat.html).
@@ -328,16 +329,22 @@ impl From<Vec<CellOutput>> for CodeOutput {
 }
 
 pub fn notebook_to_doc(nb: Notebook, accept_draft: bool) -> Result<Option<Document<Ast>>> {
-    let mut writer = BufWriter::new(Vec::new());
+    let mut writer = Vec::new();
 
     let mut output_map = HashMap::new();
 
     let mut doc_meta = None;
 
-    for cell in nb.cells {
+    // Start of each cell in the combined source, and where its own source begins.
+    let mut cell_offsets = Vec::new();
+
+    for (cell_idx, cell) in nb.cells.into_iter().enumerate() {
+        let cell_start = writer.len();
         match &cell {
             Cell::Markdown { common } => {
-                write!(&mut writer, "\n{}\n", common.source)?;
+                writer.push(b'\n');
+                cell_offsets.push((cell_start, writer.len(), cell_idx));
+                writeln!(&mut writer, "{}", common.source)?;
             }
             Cell::Code {
                 common, outputs, ..
@@ -348,9 +355,12 @@ pub fn notebook_to_doc(nb: Notebook, accept_draft: bool) -> Result<Option<Docume
                     .as_ref()
                     .map(|tags| tags.join(", "))
                     .unwrap_or(String::new());
-                let full = format!("#| tags: {}\n{}\n", attr, common.source);
+                let header = format!("#| tags: {}\n", attr);
+                let full = format!("{}{}\n", header, common.source);
 
-                write!(&mut writer, "\n```python, cell\n{}```\n", full)?;
+                write!(&mut writer, "\n```python, cell\n{}", header)?;
+                cell_offsets.push((cell_start, writer.len(), cell_idx));
+                write!(&mut writer, "{}\n```\n", common.source)?;
 
                 let mut hasher = DefaultHasher::new();
                 full.hash(&mut hasher);
@@ -368,16 +378,32 @@ pub fn notebook_to_doc(nb: Notebook, accept_draft: bool) -> Result<Option<Docume
         }
     }
 
-    let source = String::from_utf8(writer.into_inner()?)?;
+    let source = String::from_utf8(writer)?;
     // println!("{source}");
 
     let mut doc = Document::try_from(source.as_str())?;
     doc.code_outputs = output_map;
     doc.meta = doc_meta.unwrap_or_default();
 
+    let index = LineIndex::new(&source);
+    for_each_span_mut(&mut doc.content.blocks, &mut |span| {
+        map_span_to_cell(span, &cell_offsets, &index)
+    });
+
     Ok(Some(doc))
 }
 
+/// Sets the cell index of a span and makes its line numbers relative to the cell source.
+fn map_span_to_cell(span: &mut Span, cell_offsets: &[(usize, usize, usize)], index: &LineIndex) {
+    let pos = cell_offsets.partition_point(|(start, _, _)| *start <= span.range.start);
+    if let Some((_, source_start, cell_idx)) = pos.checked_sub(1).map(|p| cell_offsets[p]) {
+        let first_line = index.position(source_start).line;
+        span.cell = Some(cell_idx);
+        span.start.line = (span.start.line + 1).saturating_sub(first_line).max(1);
+        span.end.line = (span.end.line + 1).saturating_sub(first_line).max(1);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -385,7 +411,8 @@ mod tests {
     use crate::ast;
     use crate::ast::{Block, Command, Inline};
     use crate::code_ast::types::{CodeContent, CodeElem};
-    use crate::common::Span;
+    use crate::common::{LineCol, Span};
+    use crate::raw::Attributes;
     use std::fs::File;
     use std::io::BufReader;
     use std::path::PathBuf;
@@ -430,6 +457,22 @@ mod tests {
             ],
         };
 
+        let span =
+            |range: std::ops::Range<usize>, start: (usize, usize), end: (usize, usize), cell| {
+                Span {
+                    range,
+                    start: LineCol {
+                        line: start.0,
+                        column: start.1,
+                    },
+                    end: LineCol {
+                        line: end.0,
+                        column: end.1,
+                    },
+                    cell: Some(cell),
+                }
+            };
+
         let expected = Document {
             meta: Default::default(),
             content: Ast {
@@ -438,17 +481,25 @@ mod tests {
                         lvl: 1,
                         id: None,
                         classes: vec![],
-                        inner: vec![Inline::Text("Heading".into())],
+                        attributes: Default::default(),
+                        inner: vec![Inline::Text(
+                            "Heading".into(),
+                            span(3..10, (1, 3), (1, 10), 0),
+                        )],
+                        span: span(1..11, (1, 1), (2, 1), 0),
                     },
-                    Block::Plain(vec![Inline::Command(Command {
-                        function: "func".into(),
-                        label: None,
-                        parameters: vec![],
-                        body: None,
-                        span: Span::new(11, 16),
-                        global_idx: 0,
-                    })]),
-                    Block::Plain(vec![Inline::CodeBlock(ast::CodeBlock {
+                    Block::Plain(
+                        vec![Inline::Command(Command {
+                            function: "func".into(),
+                            label: None,
+                            parameters: vec![],
+                            body: None,
+                            span: span(11..16, (2, 1), (2, 6), 0),
+                            global_idx: 0,
+                        })],
+                        span(11..17, (2, 1), (3, 1), 0),
+                    ),
+                    Block::CodeBlock(ast::CodeBlock {
                         label: None,
                         source: CodeContent {
                             blocks: vec![CodeElem::Src("print('x')\n\n".into())],
@@ -457,11 +508,14 @@ mod tests {
                             ),
                             hash: 14521985544978239724,
                         },
-                        attributes: vec!["python".into(), "cell".into()],
+                        attributes: Attributes {
+                            flags: vec!["python".into(), "cell".into()],
+                            ..Default::default()
+                        },
                         display_cell: false,
                         global_idx: 0,
-                        span: Span::new(18, 58),
-                    })]),
+                        span: span(18..58, (1, 1), (2, 4), 1),
+                    }),
                 ],
                 source: "\n# Heading\n#func\n\n```python, cell\n#| tags: \nprint('x')\n```\n"
                     .into(),
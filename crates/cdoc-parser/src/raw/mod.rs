@@ -4,10 +4,10 @@ pub use parser::*;
 use std::collections::HashMap;
 
 use crate::code_ast::types::CodeContent;
-use crate::common::Span;
+use crate::common::{Diagnostic, Span};
 use cowstr::CowStr;
+use linked_hash_map::LinkedHashMap;
 use serde::{Deserialize, Serialize};
-use std::io::{BufWriter, Write};
 
 #[derive(Debug, PartialEq, Default)]
 pub struct RawDocument {
@@ -15,6 +15,8 @@ pub struct RawDocument {
     pub(crate) input: CowStr,
     pub(crate) meta: Option<CowStr>,
     pub(crate) references: HashMap<CowStr, Reference>,
+    /// Recoverable errors are collected here instead of failing the parse when this is set.
+    pub(crate) diagnostics: Option<Vec<Diagnostic>>,
 }
 
 impl RawDocument {
@@ -24,6 +26,7 @@ impl RawDocument {
             input: CowStr::from(input),
             meta: None,
             references: Default::default(),
+            diagnostics: None,
         }
     }
 }
@@ -36,6 +39,7 @@ pub enum Reference {
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum Element {
     Markdown(CowStr),
     Special(Option<CowStr>, Special),
@@ -53,7 +57,7 @@ pub enum Special {
     CodeBlock {
         lvl: usize,
         inner: CodeContent,
-        attributes: Vec<CowStr>,
+        attributes: Attributes,
     },
     Command {
         function: CowStr,
@@ -63,12 +67,54 @@ pub enum Special {
     Verbatim {
         inner: CowStr,
     },
+    /// Content that is only included in the output for the given format.
+    Raw {
+        format: CowStr,
+        inner: CowStr,
+        is_block: bool,
+    },
+    /// A bracketed span, e.g. `[some text]{.highlight}`.
+    Span {
+        inner: Vec<ElementInfo>,
+        attributes: Attributes,
+    },
+    /// An attribute block at the end of a line, e.g. `{#intro .unnumbered}`. It applies to the
+    /// heading on that line and is kept as text anywhere else.
+    Attributes {
+        attributes: Attributes,
+        source: CowStr,
+    },
 }
 
-#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-pub struct CodeAttr {
-    pub key: Option<CowStr>,
-    pub value: CowStr,
+/// Attributes of a fenced code block (e.g. ```` ```python, cell, #main, .wide, height=10 ````),
+/// a bracketed span or a heading (e.g. `{#main .wide height=10}`).
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
+pub struct Attributes {
+    /// Key-value attributes in the order they were given. The `#id` shorthand is stored with the
+    /// key `id`.
+    pub values: LinkedHashMap<CowStr, CowStr>,
+    /// Attributes without a value, such as the language or `cell`.
+    pub flags: Vec<CowStr>,
+    /// Classes given with the `.class` shorthand.
+    pub classes: Vec<CowStr>,
+}
+
+impl Attributes {
+    pub fn get(&self, key: &str) -> Option<&CowStr> {
+        self.values.get(key)
+    }
+
+    pub fn id(&self) -> Option<&CowStr> {
+        self.get("id")
+    }
+
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|f| f == flag)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty() && self.flags.is_empty() && self.classes.is_empty()
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -119,6 +165,7 @@ impl ToString for Value {
     }
 }
 
+/// A special element together with its position among elements of the same kind.
 #[derive(Clone)]
 pub struct Child {
     pub elem: Special,
@@ -126,72 +173,3 @@ pub struct Child {
     pub label: Option<CowStr>,
     pub identifier: usize,
 }
-
-pub struct ComposedMarkdown {
-    pub src: CowStr,
-    pub children: Vec<Child>,
-}
-
-impl From<Vec<ElementInfo>> for ComposedMarkdown {
-    fn from(value: Vec<ElementInfo>) -> Self {
-        let mut writer = BufWriter::new(Vec::new());
-        let mut children = Vec::new();
-
-        let mut code_idx = 0;
-        let mut command_idx = 0;
-        let mut math_idx = 0;
-        let mut extra_idx = 0;
-
-        for elem in value.into_iter() {
-            match elem.element {
-                Element::Markdown(s) => {
-                    writer.write_all(s.as_bytes()).unwrap();
-                }
-                Element::Special(label, inner) => {
-                    let idx = code_idx + command_idx + math_idx + extra_idx;
-
-                    let identifier = match inner {
-                        Special::Math { .. } => {
-                            math_idx += 1;
-                            math_idx - 1
-                        }
-                        Special::CodeBlock { lvl, .. } => {
-                            if lvl > 1 {
-                                code_idx += 1;
-                                code_idx - 1
-                            } else {
-                                extra_idx += 1;
-                                0
-                            }
-                        }
-                        Special::Command { .. } => {
-                            command_idx += 1;
-                            command_idx - 1
-                        }
-                        Special::Verbatim { .. } => {
-                            extra_idx += 1;
-                            0
-                        }
-                        Special::CodeInline { .. } => {
-                            extra_idx += 1;
-                            0
-                        }
-                    };
-
-                    children.push(Child {
-                        elem: inner,
-                        span: elem.span,
-                        label,
-                        identifier,
-                    });
-                    write!(&mut writer, "<elem-{}>", idx).unwrap() // Important: Trailing space is necessary as it is eaten by the parser
-                }
-            }
-        }
-
-        ComposedMarkdown {
-            src: CowStr::from(String::from_utf8(writer.into_inner().unwrap()).unwrap()),
-            children,
-        }
-    }
-}
@@ -3,14 +3,16 @@ use cowstr::CowStr;
 use pest::iterators::Pair;
 use pest::Parser;
 use pest_derive::Parser;
+use std::ops::Range;
 
 #[derive(Parser)]
 #[grammar = "grammars/raw_doc.pest"]
 pub struct RawDocParser;
 
-use crate::code_ast::parse_code_string;
-use crate::common::Span;
-use crate::raw::{Element, ElementInfo, Parameter, Special, Value};
+use crate::code_ast::{human_errors, parse_code_string, unparsed_code_string};
+use crate::common::{Diagnostic, Span};
+use crate::raw::{Attributes, Element, ElementInfo, Parameter, Special, Value};
+use pest::error::InputLocation;
 use pest::iterators::Pairs;
 use thiserror::Error;
 
@@ -54,7 +56,9 @@ impl RawDocument {
             Rule::math_block => self.parse_math_block(pair),
             Rule::code_def => self.parse_code(pair)?,
             Rule::verbatim => self.parse_verbatim(pair),
-            Rule::src | Rule::string | Rule::body => self.parse_src(pair),
+            Rule::span_def => self.parse_span(pair)?,
+            Rule::line_attrs => self.parse_line_attributes(pair),
+            Rule::src | Rule::string | Rule::body | Rule::span_src => self.parse_src(pair),
             _ => unreachable!(),
         };
 
@@ -135,7 +139,7 @@ impl RawDocument {
 
     fn parse_value(&mut self, pair: Pair<Rule>) -> Result<Value, ParserError> {
         Ok(match pair.as_rule() {
-            Rule::basic_val | Rule::string => Value::String(pair.as_str().into()),
+            Rule::basic_val | Rule::quoted => Value::String(unescape(pair.as_str())),
             Rule::md_val => Value::Content(self.parse_elements(pair.into_inner())?),
             Rule::flag => Value::Flag(pair.as_str().into()),
             _ => unreachable!(),
@@ -143,9 +147,16 @@ impl RawDocument {
     }
 
     fn parse_math_block(&mut self, pair: Pair<Rule>) -> Element {
-        let (lvl, src, label) = self.block_parser(pair);
-
-        let src = self.parse_math(src);
+        let first = pair.clone().into_inner().next().expect("empty math block");
+        let (src, label, is_block) = match first.as_rule() {
+            Rule::math_inline_tex | Rule::math_display_tex | Rule::math_env => {
+                self.parse_tex_math(first)
+            }
+            _ => {
+                let (lvl, src, label) = self.block_parser(pair);
+                (self.parse_math(src), label, lvl.len() != 1)
+            }
+        };
 
         if let Some(label) = label.clone() {
             self.references.insert(label, Reference::Math(src.clone()));
@@ -155,60 +166,160 @@ impl RawDocument {
             label,
             Special::Math {
                 inner: src,
-                is_block: lvl.len() != 1,
+                is_block,
             },
         )
     }
 
-    fn parse_code_attributes(&mut self, pairs: Pairs<Rule>) -> Vec<CowStr> {
-        pairs
-            .into_iter()
-            .map(|elem| {
-                if let Rule::code_param = elem.as_rule() {
-                    self.parse_code_attribute(elem)
-                } else {
-                    unreachable!()
-                }
-            })
-            .collect()
+    /// Parses math written with LaTeX delimiters. Environments are display math and their body is
+    /// wrapped in the matching inner environment (e.g. `aligned` for `align`), so that it can be
+    /// placed in the display math of any output format. A `\label{}` in display math is removed
+    /// from the source and becomes the label of the element.
+    fn parse_tex_math(&self, pair: Pair<Rule>) -> (CowStr, Option<CowStr>, bool) {
+        let rule = pair.as_rule();
+        let mut inner = pair.into_inner();
+        let (src, is_block) = match rule {
+            Rule::math_inline_tex => (inner.next().expect("missing math").as_str(), false),
+            Rule::math_display_tex => (inner.next().expect("missing math").as_str(), true),
+            _ => {
+                let name = inner.next().expect("missing environment").as_str();
+                let body = inner.next().expect("missing math").as_str();
+                let (body, label) = take_tex_label(body);
+                let src = match name.trim_end_matches('*') {
+                    "equation" => body,
+                    "alignat" => cowstr::format!("\\begin{{alignedat}}{body}\\end{{alignedat}}"),
+                    "gather" => cowstr::format!("\\begin{{gathered}}{body}\\end{{gathered}}"),
+                    _ => cowstr::format!("\\begin{{aligned}}{body}\\end{{aligned}}"),
+                };
+                return (src, label, true);
+            }
+        };
+
+        if is_block {
+            let (src, label) = take_tex_label(src);
+            (src, label, true)
+        } else {
+            (src.into(), None, false)
+        }
     }
 
-    fn parse_code_attribute(&mut self, pair: Pair<Rule>) -> CowStr {
-        let mut pairs = pair.into_inner();
-        let first = pairs.next().expect("empty param");
+    fn parse_attributes(&mut self, pairs: Pairs<Rule>) -> Attributes {
+        let mut attributes = Attributes::default();
+        for elem in pairs {
+            let (first, value) = match elem.as_rule() {
+                Rule::code_param | Rule::attr_pair => {
+                    let mut pairs = elem.into_inner();
+                    (pairs.next().expect("empty param"), pairs.next())
+                }
+                Rule::attr_id | Rule::attr_class => (elem, None),
+                _ => unreachable!(),
+            };
+            self.parse_attribute(first, value, &mut attributes);
+        }
+        attributes
+    }
 
-        if let Rule::key = first.as_rule() {
-            let value = pairs.next().expect("no value");
-            // CodeAttr {
-            //     key: Some(first.as_str().to_string()),
-            //     value: value.as_str().to_string(),
-            // }
-            self.cowstr_from_span(value.as_span())
-        } else {
-            // CodeAttr {
-            //     key: None,
-            //     value: first.as_str().to_string(),
-            // }
-            self.cowstr_from_span(first.as_span())
+    fn parse_attribute(
+        &mut self,
+        first: Pair<Rule>,
+        value: Option<Pair<Rule>>,
+        attributes: &mut Attributes,
+    ) {
+        match first.as_rule() {
+            Rule::attr_id => {
+                let id = first.into_inner().next().expect("missing id");
+                attributes.values.insert("id".into(), id.as_str().into());
+            }
+            Rule::attr_class => {
+                let class = first.into_inner().next().expect("missing class");
+                attributes.classes.push(class.as_str().into());
+            }
+            Rule::key => {
+                let value = value.expect("no value");
+                attributes
+                    .values
+                    .insert(first.as_str().into(), unescape(value.as_str().trim()));
+            }
+            _ => attributes.flags.push(unescape(first.as_str().trim())),
         }
     }
 
+    fn parse_span(&mut self, pair: Pair<Rule>) -> Result<Element, ParserError> {
+        let mut inner = pair.into_inner();
+        let content = inner.next().expect("missing span content");
+        let content = self.parse_elements(content.into_inner())?;
+        let attributes =
+            self.parse_attributes(inner.next().expect("missing attributes").into_inner());
+
+        Ok(Element::Special(
+            None,
+            Special::Span {
+                inner: content,
+                attributes,
+            },
+        ))
+    }
+
+    fn parse_line_attributes(&mut self, pair: Pair<Rule>) -> Element {
+        let block = pair.into_inner().next().expect("missing attributes");
+        let source = self.cowstr_from_span(block.as_span());
+        let attributes = self.parse_attributes(block.into_inner());
+
+        Element::Special(None, Special::Attributes { attributes, source })
+    }
+
     fn parse_code(&mut self, pair: Pair<Rule>) -> Result<Element, ParserError> {
         let mut inner = pair.into_inner();
         let lvl = inner.next().expect("missing code_lvl").as_str().to_string();
 
         let maybe_param = inner.next().expect("missing code_src");
-        let (src_pair, params) = if let Rule::code_params = maybe_param.as_rule() {
-            let attributes = self.parse_code_attributes(maybe_param.into_inner());
-            (inner.next().expect("missing code_src"), Some(attributes))
-        } else {
-            (maybe_param, None)
+        let (src_pair, params, block_format) = match maybe_param.as_rule() {
+            Rule::code_params => {
+                let attributes = self.parse_attributes(maybe_param.into_inner());
+                (
+                    inner.next().expect("missing code_src"),
+                    Some(attributes),
+                    None,
+                )
+            }
+            Rule::raw_format => (
+                inner.next().expect("missing code_src"),
+                None,
+                Some(raw_format_name(maybe_param)),
+            ),
+            _ => (maybe_param, None, None),
         };
 
         let src_span = src_pair.as_span();
         let src = self.cowstr_from_span(src_span);
 
-        let id = inner.next().map(|val| self.cowstr_from_span(val.as_span()));
+        let trailing = inner.next();
+        if let Some(format) = block_format {
+            let inner = src.strip_suffix('\n').unwrap_or(&src).into();
+            return Ok(Element::Special(
+                None,
+                Special::Raw {
+                    format,
+                    inner,
+                    is_block: true,
+                },
+            ));
+        }
+        if let Some(format) = trailing
+            .as_ref()
+            .filter(|p| p.as_rule() == Rule::raw_format)
+        {
+            return Ok(Element::Special(
+                None,
+                Special::Raw {
+                    format: raw_format_name(format.clone()),
+                    inner: src,
+                    is_block: false,
+                },
+            ));
+        }
+
+        let id = trailing.map(|val| self.cowstr_from_span(val.as_span()));
 
         if let Some(label) = id.clone() {
             self.references.insert(label, Reference::Code(src.clone()));
@@ -219,7 +330,24 @@ impl RawDocument {
             if lvl.len() == 1 {
                 Special::CodeInline { inner: src }
             } else {
-                let content = parse_code_string(src)?;
+                let content = match parse_code_string(src.clone()) {
+                    Ok(content) => content,
+                    Err(e) => {
+                        let Some(diagnostics) = self.diagnostics.as_mut() else {
+                            return Err(e.into());
+                        };
+                        let offset = match e.location {
+                            InputLocation::Pos(pos) => pos,
+                            InputLocation::Span((start, _)) => start,
+                        };
+                        let pos = src_span.start() + offset.min(src.len());
+                        diagnostics.push(Diagnostic::new(
+                            format!("invalid code block: {}", human_errors(*e).variant.message()),
+                            Span::new(pos, pos),
+                        ));
+                        unparsed_code_string(src)
+                    }
+                };
 
                 Special::CodeBlock {
                     lvl: lvl.len(),
@@ -282,14 +410,187 @@ pub fn parse_to_doc(input: &str) -> Result<RawDocument, ParserError> {
     Ok(doc)
 }
 
+/// Parses a document without stopping at the first syntax error. Each error is recorded as a
+/// [Diagnostic] and parsing resumes at the next sync point, which is the closing `}` of the
+/// enclosing body or the end of the paragraph. The skipped input is left out of the document.
+pub fn parse_to_doc_recovering(input: &str) -> (RawDocument, Vec<Diagnostic>) {
+    // Skipped input is blanked out rather than removed so that all offsets stay valid.
+    let mut masked = input.to_string();
+    let mut diagnostics = vec![];
+    // Everything before `resume` parsed cleanly, so each error only rescans the rest of the input.
+    let mut resume = 0;
+
+    loop {
+        let parsed = RawDocParser::parse(Rule::doc, &masked[resume..])
+            .ok()
+            .and_then(|mut pairs| pairs.next())
+            .map(|pair| pair.as_span().end())
+            .unwrap_or_default();
+        let start = resume + parsed;
+
+        if start == masked.len() {
+            // Blanking an error can complete an enclosing element that failed before, which is
+            // then checked from the beginning.
+            if resume == 0 || RawDocParser::parse(Rule::top, &masked).is_ok() {
+                break;
+            }
+            resume = 0;
+            continue;
+        }
+
+        // No element matches at `start`, so this fails within the first element.
+        let e = RawDocParser::parse(Rule::top, &masked[start..])
+            .expect_err("no element can match where the document stopped");
+        let skipped = skipped_range(&masked, start);
+
+        diagnostics.push(Diagnostic::new(
+            syntax_error_message(&masked[start..], &e),
+            Span::new(start, skipped.end),
+        ));
+
+        blank_out(&mut masked, skipped);
+        resume = start;
+    }
+
+    let mut doc = RawDocument::new(input);
+    doc.diagnostics = Some(vec![]);
+    let pairs = RawDocParser::parse(Rule::top, &masked).expect("masked input must parse");
+    if let Err(e) = doc.parse_doc(pairs) {
+        diagnostics.push(Diagnostic::new(e.to_string(), Span::new(0, 0)));
+    }
+    diagnostics.extend(doc.diagnostics.take().unwrap_or_default());
+    diagnostics.sort_by_key(|d| d.span.range.start);
+
+    (doc, diagnostics)
+}
+
+/// Input that is skipped after an error at `start`. Without a sync point after the error, the
+/// rest of the input is skipped so that the document parsed up to the error is still returned.
+fn skipped_range(input: &str, start: usize) -> Range<usize> {
+    let end = sync_point(input, start);
+    if end > start {
+        start..end
+    } else {
+        start..input.len()
+    }
+}
+
+/// Finds where parsing can resume after an error at `start`.
+fn sync_point(input: &str, start: usize) -> usize {
+    let rest = &input[start..];
+    // Braces in unterminated code are not shortcode syntax.
+    let track_braces = !rest.starts_with('`');
+    let mut depth = 0usize;
+
+    for (idx, c) in rest.char_indices() {
+        match c {
+            '\n' if rest[idx + 1..]
+                .split('\n')
+                .next()
+                .unwrap()
+                .trim()
+                .is_empty() =>
+            {
+                return start + idx;
+            }
+            '{' if track_braces => depth += 1,
+            '}' if track_braces => {
+                if depth == 0 {
+                    return start + idx;
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+    }
+
+    input.len()
+}
+
+fn syntax_error_message(rest: &str, error: &pest::error::Error<Rule>) -> String {
+    if rest.starts_with('$') {
+        "unterminated math (missing closing `$` or unbalanced braces)".to_string()
+    } else if rest.starts_with('`') {
+        "unterminated code (missing closing backticks)".to_string()
+    } else if rest.starts_with("\\{") {
+        "unterminated verbatim block (missing closing `\\}`)".to_string()
+    } else if rest.starts_with("\\}") {
+        "unexpected `\\}` without an opening `\\{`".to_string()
+    } else {
+        error.variant.message().to_string()
+    }
+}
+
+/// Replaces the given range with spaces, keeping line breaks.
+fn blank_out(input: &mut String, range: Range<usize>) {
+    let blanked: String = input[range.clone()]
+        .chars()
+        .map(|c| match c {
+            '\n' => "\n".to_string(),
+            c => " ".repeat(c.len_utf8()),
+        })
+        .collect();
+    input.replace_range(range, &blanked);
+}
+
+fn raw_format_name(pair: Pair<Rule>) -> CowStr {
+    pair.into_inner()
+        .next()
+        .expect("missing raw format")
+        .as_str()
+        .into()
+}
+
+/// Removes the first `\label{...}` from a LaTeX math source and returns it separately.
+fn take_tex_label(src: &str) -> (CowStr, Option<CowStr>) {
+    let Some(start) = src.find("\\label{") else {
+        return (src.into(), None);
+    };
+    let label_start = start + "\\label{".len();
+    let Some(len) = src[label_start..].find('}') else {
+        return (src.into(), None);
+    };
+
+    let label = src[label_start..label_start + len].trim();
+    let rest = cowstr::format!("{}{}", &src[..start], &src[label_start + len + 1..]);
+    (rest, Some(label.into()))
+}
+
+/// Resolves backslash escapes in parameter values. Backslashes that do not escape a delimiter are
+/// kept, so values like LaTeX commands are left as written.
+fn unescape(value: &str) -> CowStr {
+    if !value.contains('\\') {
+        return value.into();
+    }
+
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(&next) = chars.peek() {
+                if matches!(next, '"' | '”' | '\\' | ',' | '(' | ')') {
+                    chars.next();
+                    out.push(next);
+                    continue;
+                }
+            }
+        }
+        out.push(c);
+    }
+    out.into()
+}
+
 #[cfg(test)]
 mod tests {
+    use super::skipped_range;
     use crate::code_ast::types::{CodeContent, CodeElem};
     use crate::common::Span;
     use crate::raw::{
-        parse_to_doc, Element, ElementInfo, Parameter, RawDocument, Reference, Special, Value,
+        parse_to_doc, parse_to_doc_recovering, Attributes, Element, ElementInfo, Parameter,
+        RawDocument, Reference, Special, Value,
     };
     use cowstr::CowStr;
+    use linked_hash_map::LinkedHashMap;
     use std::collections::HashMap;
 
     macro_rules! doc_tests {
@@ -299,7 +600,7 @@ mod tests {
             #[test]
             fn [<$prefix _ $name>]() {
                 let (input, expected) = $value;
-                let doc = RawDocument { input: CowStr::from(input), src: expected, meta: None, references: Default::default() };
+                let doc = RawDocument { input: CowStr::from(input), src: expected, meta: None, references: Default::default(), diagnostics: None };
                 compare(doc, input);
             }
             }
@@ -323,7 +624,7 @@ code
                             meta: Default::default(),
                             hash: 3750657748055546767,
                         },
-                        attributes: vec![],
+                        attributes: Default::default(),
                     },
                 ),
                 span: Span::new(0, 12),
@@ -331,6 +632,7 @@ code
             input: CowStr::from(input),
             meta: None,
             references: Default::default(),
+            diagnostics: None,
         };
 
         compare(expected, input);
@@ -338,7 +640,7 @@ code
 
     #[test]
     fn test_code_param() {
-        let input = r#"```lang, val
+        let input = r#"```lang, #main, key=value, .wide, val
 code
 ```"#;
         let expected = RawDocument {
@@ -352,19 +654,85 @@ code
                             meta: Default::default(),
                             hash: 15492099155864206242,
                         },
-                        attributes: vec!["lang".into(), "val".into()],
+                        attributes: Attributes {
+                            values: LinkedHashMap::from_iter([
+                                ("id".into(), "main".into()),
+                                ("key".into(), "value".into()),
+                            ]),
+                            flags: vec!["lang".into(), "val".into()],
+                            classes: vec!["wide".into()],
+                        },
                     },
                 ),
-                span: Span::new(0, 21),
+                span: Span::new(0, 46),
             }],
             input: CowStr::from(input),
             meta: None,
             references: Default::default(),
+            diagnostics: None,
         };
 
         compare(expected, input);
     }
 
+    /// Attributes of the first code block, span or attribute block in the input.
+    fn first_attributes(input: &str) -> Attributes {
+        parse_to_doc(input)
+            .unwrap()
+            .src
+            .into_iter()
+            .find_map(|e| match e.element {
+                Element::Special(
+                    _,
+                    Special::CodeBlock { attributes, .. }
+                    | Special::Span { attributes, .. }
+                    | Special::Attributes { attributes, .. },
+                ) => Some(attributes),
+                _ => None,
+            })
+            .expect("no attributes")
+    }
+
+    #[test]
+    fn test_code_attributes() {
+        let attributes = first_attributes(
+            "```python, cell, #main, .wide, .dark, title=Two words, hl=1-3\nx\n```",
+        );
+        assert_eq!(
+            Attributes {
+                values: LinkedHashMap::from_iter([
+                    ("id".into(), "main".into()),
+                    ("title".into(), "Two words".into()),
+                    ("hl".into(), "1-3".into()),
+                ]),
+                flags: vec!["python".into(), "cell".into()],
+                classes: vec!["wide".into(), "dark".into()],
+            },
+            attributes
+        );
+    }
+
+    #[test]
+    fn test_attribute_block() {
+        let attributes = first_attributes("{#intro .wide .dark key=value title=\"Two words\"}\n");
+        assert_eq!(
+            Attributes {
+                values: LinkedHashMap::from_iter([
+                    ("id".into(), "intro".into()),
+                    ("key".into(), "value".into()),
+                    ("title".into(), "Two words".into()),
+                ]),
+                flags: vec![],
+                classes: vec!["wide".into(), "dark".into()],
+            },
+            attributes
+        );
+
+        let attributes = first_attributes("[text]{.note data=\"a \\\"b\\\"\"}");
+        assert_eq!(Some(&"a \"b\"".into()), attributes.get("data"));
+        assert_eq!(vec![CowStr::from("note")], attributes.classes);
+    }
+
     #[test]
     fn test_math() {
         let input = "$inline$";
@@ -382,11 +750,57 @@ code
             input: CowStr::from(input),
             meta: None,
             references: Default::default(),
+            diagnostics: None,
         };
 
         compare(expected, input);
     }
 
+    #[test]
+    fn test_tex_math() {
+        let input = "\\(a\\) \\[b \\label{eq:b}\\]\n\\begin{align*}c &= d\\end{align*}";
+        let doc = parse_to_doc(input).expect("parse error");
+
+        let math: Vec<_> = doc
+            .src
+            .into_iter()
+            .filter_map(|e| match e.element {
+                Element::Special(label, Special::Math { inner, is_block }) => {
+                    Some((inner, label, is_block))
+                }
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(
+            vec![
+                ("a".into(), None, false),
+                ("b ".into(), Some("eq:b".into()), true),
+                ("\\begin{aligned}c &= d\\end{aligned}".into(), None, true),
+            ],
+            math
+        );
+        assert!(doc.references.contains_key("eq:b"));
+    }
+
+    #[test]
+    fn test_tex_escaped_brackets() {
+        let input = "a\\[0\\] and f\\(x\\), \\\\[y] #cmd{ b\\[1\\] [c]{.d} }";
+        let doc = parse_to_doc(input).expect("parse error");
+
+        let has_math = |src: &[ElementInfo]| {
+            src.iter()
+                .any(|e| matches!(e.element, Element::Special(_, Special::Math { .. })))
+        };
+        assert!(!has_math(&doc.src));
+        match &doc.src.last().unwrap().element {
+            Element::Special(_, Special::Command { body, .. }) => {
+                assert!(!has_math(body.as_ref().unwrap()))
+            }
+            e => panic!("expected a command, got {:?}", e),
+        }
+    }
+
     #[test]
     fn test_verbatim() {
         let input = "\\{verbatim\\}";
@@ -403,6 +817,7 @@ code
             input: CowStr::from(input),
             meta: None,
             references: Default::default(),
+            diagnostics: None,
         };
 
         compare(expected, input);
@@ -419,6 +834,7 @@ code
             input: CowStr::from(input),
             meta: None,
             references: Default::default(),
+            diagnostics: None,
         };
 
         compare(expected, input);
@@ -442,6 +858,7 @@ code
             input: CowStr::from(input),
             meta: None,
             references: HashMap::from([("id".into(), Reference::Command("call".into(), vec![]))]),
+            diagnostics: None,
         };
 
         compare(expected, input);
@@ -543,6 +960,100 @@ code
         ]),
     }
 
+    #[test]
+    fn unicode_and_escapes() {
+        let input = "#figur(billedtekst=Æbler og “pærer” 🍎, alt=\"Et \\\"citat\\\", med komma\", a\\, b, \\(x\\) \\alpha, :særlig)";
+        let doc = parse_to_doc(input).expect("Parse error");
+
+        let Element::Special(
+            None,
+            Special::Command {
+                function,
+                parameters,
+                ..
+            },
+        ) = &doc.src[0].element
+        else {
+            panic!("expected a command");
+        };
+        assert_eq!("figur", function.as_str());
+
+        let values: Vec<_> = parameters
+            .iter()
+            .map(|p| (p.key.as_ref().map(|k| k.as_str()), p.value.clone()))
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    Some("billedtekst"),
+                    Value::String("Æbler og “pærer” 🍎".into())
+                ),
+                (Some("alt"), Value::String("Et \"citat\", med komma".into())),
+                (None, Value::String("a, b".into())),
+                (None, Value::String("(x) \\alpha".into())),
+                (None, Value::Flag("særlig".into())),
+            ],
+            values
+        );
+    }
+
+    #[test]
+    fn recover_from_errors() {
+        let input = "#cmd{ a $x b } after\n\nnext $y\n\n```\n#| solution <<\nx\n```\n";
+        assert!(parse_to_doc(input).is_err());
+
+        let (doc, diagnostics) = parse_to_doc_recovering(input);
+
+        let ranges: Vec<_> = diagnostics.iter().map(|d| d.span.range.clone()).collect();
+        assert_eq!(vec![8..13, 27..29, 52..52], ranges);
+        assert!(diagnostics[0].message.starts_with("unterminated math"));
+        assert!(diagnostics[2].message.starts_with("invalid code block"));
+
+        assert_eq!(
+            Element::Special(
+                None,
+                Special::Command {
+                    function: "cmd".into(),
+                    parameters: vec![],
+                    body: Some(vec![ElementInfo {
+                        element: Element::Markdown(" a      ".into()),
+                        span: Span::new(5, 13),
+                    }]),
+                }
+            ),
+            doc.src[0].element
+        );
+        assert!(doc
+            .src
+            .iter()
+            .any(|e| matches!(e.element, Element::Special(None, Special::CodeBlock { .. }))));
+    }
+
+    #[test]
+    fn recover_from_many_errors() {
+        let input = "a \\} b\n\n".repeat(200);
+
+        let (doc, diagnostics) = parse_to_doc_recovering(&input);
+
+        assert_eq!(200, diagnostics.len());
+        assert!(diagnostics
+            .iter()
+            .all(|d| d.message.starts_with("unexpected `\\}`")));
+        assert_eq!(2..3, diagnostics[0].span.range);
+        assert_eq!(10..11, diagnostics[1].span.range);
+        assert!(doc
+            .src
+            .iter()
+            .all(|e| matches!(e.element, Element::Markdown(_))));
+    }
+
+    #[test]
+    fn skip_without_sync_point() {
+        assert_eq!(2..4, skipped_range("a $x\n\nb", 2));
+        assert_eq!(1..4, skipped_range("a\n\nb", 1));
+        assert_eq!(2..5, skipped_range("a }b}", 2));
+    }
+
     fn compare(expected: RawDocument, input: &str) {
         let doc = parse_to_doc(input).expect("Parse error");
 
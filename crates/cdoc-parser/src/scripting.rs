@@ -14,6 +14,7 @@ impl CustomType for Metadata {
             .with_get("editable", |s: &mut Self| s.editable)
             .with_get("hide_sidebar", |s: &mut Self| s.layout.hide_sidebar)
             .with_get("exclude_outputs", |s: &mut Self| s.exclude_outputs.clone())
+            .with_get("variables", |s: &mut Self| s.variables.clone())
             .with_get("user_defined", |s: &mut Self| s.user_defined.clone());
     }
 }
@@ -0,0 +1,512 @@
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// A bibliography entry. Fields from BibTeX and CSL-JSON are mapped to the same names so that
+/// templates work for both input formats.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct BibEntry {
+    /// Citation key.
+    pub key: String,
+    /// Entry type as given in the source file (e.g. `article` or `book`).
+    pub kind: String,
+    /// Author names in "Given Family" order.
+    pub authors: Vec<String>,
+    pub title: Option<String>,
+    pub year: Option<String>,
+    /// Journal, book or proceedings the entry is part of.
+    pub container: Option<String>,
+    pub publisher: Option<String>,
+    pub volume: Option<String>,
+    pub pages: Option<String>,
+    pub url: Option<String>,
+    pub doi: Option<String>,
+}
+
+/// Entries loaded from the bibliography files of a project.
+#[derive(Debug, Clone, Default)]
+pub struct Bibliography {
+    entries: HashMap<String, BibEntry>,
+}
+
+impl Bibliography {
+    /// Loads BibTeX (`.bib`) and CSL-JSON (`.json`) files.
+    pub fn load<P: AsRef<Path>>(paths: &[P]) -> anyhow::Result<Self> {
+        let mut bibliography = Bibliography::default();
+        for path in paths {
+            let path = path.as_ref();
+            let source = fs::read_to_string(path)
+                .with_context(|| format!("could not read bibliography {}", path.display()))?;
+            let entries = match path.extension().and_then(|e| e.to_str()) {
+                Some("bib") => parse_bibtex(&source),
+                Some("json") => parse_csl_json(&source),
+                _ => Err(anyhow!("expected a .bib or .json (CSL-JSON) file")),
+            }
+            .with_context(|| format!("in bibliography {}", path.display()))?;
+            bibliography.extend(entries);
+        }
+        Ok(bibliography)
+    }
+
+    pub fn extend(&mut self, entries: Vec<BibEntry>) {
+        self.entries
+            .extend(entries.into_iter().map(|e| (e.key.clone(), e)));
+    }
+
+    pub fn get(&self, key: &str) -> Option<&BibEntry> {
+        self.entries.get(key)
+    }
+}
+
+/// Parses the entries of a BibTeX file. `@string` abbreviations and `#` concatenation are
+/// supported, while `@comment` and `@preamble` are skipped. Braces used for protecting case are
+/// removed from values.
+pub fn parse_bibtex(source: &str) -> anyhow::Result<Vec<BibEntry>> {
+    let mut parser = BibParser {
+        chars: source.chars().collect(),
+        pos: 0,
+        strings: HashMap::new(),
+    };
+    let mut entries = Vec::new();
+
+    while parser.skip_to('@') {
+        let kind = parser.identifier().to_lowercase();
+        parser.skip_whitespace();
+        let close = match parser.next() {
+            Some('{') => '}',
+            Some('(') => ')',
+            _ => bail!("expected '{{' after @{kind} at line {}", parser.line()),
+        };
+
+        match kind.as_str() {
+            "comment" | "preamble" => parser.skip_group(close)?,
+            "string" => {
+                for (name, value) in parser.fields(close)? {
+                    parser.strings.insert(name, value);
+                }
+            }
+            _ => {
+                parser.skip_whitespace();
+                let key = parser.until(&[',', close]).trim().to_string();
+                if key.is_empty() {
+                    bail!("missing citation key at line {}", parser.line());
+                }
+                let fields = parser.fields(close)?;
+                entries.push(bibtex_entry(key, kind, fields));
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+fn bibtex_entry(key: String, kind: String, mut fields: HashMap<String, String>) -> BibEntry {
+    let authors = fields
+        .remove("author")
+        .or_else(|| fields.remove("editor"))
+        .map(|a| a.split(" and ").map(bibtex_name).collect())
+        .unwrap_or_default();
+
+    BibEntry {
+        key,
+        kind,
+        authors,
+        title: fields.remove("title"),
+        year: fields
+            .remove("year")
+            .or_else(|| fields.remove("date").map(|d| d.chars().take(4).collect())),
+        container: fields
+            .remove("journal")
+            .or_else(|| fields.remove("journaltitle"))
+            .or_else(|| fields.remove("booktitle")),
+        publisher: fields
+            .remove("publisher")
+            .or_else(|| fields.remove("institution"))
+            .or_else(|| fields.remove("school")),
+        volume: fields.remove("volume"),
+        pages: fields.remove("pages").map(|p| p.replace("--", "–")),
+        url: fields.remove("url"),
+        doi: fields.remove("doi"),
+    }
+}
+
+/// Converts "Family, Given" to "Given Family".
+fn bibtex_name(name: &str) -> String {
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    match name.split_once(',') {
+        Some((family, given)) => format!("{} {}", given.trim(), family.trim()),
+        None => name,
+    }
+}
+
+struct BibParser {
+    chars: Vec<char>,
+    pos: usize,
+    strings: HashMap<String, String>,
+}
+
+impl BibParser {
+    fn line(&self) -> usize {
+        self.chars[..self.pos]
+            .iter()
+            .filter(|c| **c == '\n')
+            .count()
+            + 1
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.get(self.pos).copied();
+        self.pos += 1;
+        c
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_to(&mut self, target: char) -> bool {
+        while let Some(c) = self.next() {
+            if c == target {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn identifier(&mut self) -> String {
+        self.skip_whitespace();
+        let mut ident = String::new();
+        while let Some(c) = self
+            .peek()
+            .filter(|c| c.is_alphanumeric() || "_-:.".contains(*c))
+        {
+            ident.push(c);
+            self.pos += 1;
+        }
+        ident
+    }
+
+    fn until(&mut self, stop: &[char]) -> String {
+        let mut value = String::new();
+        while let Some(c) = self.peek().filter(|c| !stop.contains(c)) {
+            value.push(c);
+            self.pos += 1;
+        }
+        value
+    }
+
+    /// Skips a group whose opening delimiter has already been consumed.
+    fn skip_group(&mut self, close: char) -> anyhow::Result<()> {
+        let mut depth = 0;
+        while let Some(c) = self.next() {
+            match c {
+                '{' => depth += 1,
+                '}' if depth > 0 => depth -= 1,
+                c if c == close && depth == 0 => return Ok(()),
+                _ => {}
+            }
+        }
+        bail!("unterminated entry")
+    }
+
+    /// Parses `name = value` pairs until the closing delimiter of the entry.
+    fn fields(&mut self, close: char) -> anyhow::Result<HashMap<String, String>> {
+        let mut fields = HashMap::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => {
+                    self.pos += 1;
+                    continue;
+                }
+                Some(c) if c == close => {
+                    self.pos += 1;
+                    return Ok(fields);
+                }
+                None => bail!("unterminated entry"),
+                _ => {}
+            }
+
+            let name = self.identifier().to_lowercase();
+            self.skip_whitespace();
+            if name.is_empty() || self.next() != Some('=') {
+                bail!("expected a field at line {}", self.line());
+            }
+            let value = self.value(close)?;
+            fields.insert(name, value.split_whitespace().collect::<Vec<_>>().join(" "));
+        }
+    }
+
+    /// Parses a (possibly concatenated) field value.
+    fn value(&mut self, close: char) -> anyhow::Result<String> {
+        let mut value = String::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('{') => {
+                    self.pos += 1;
+                    value.push_str(&self.delimited('}')?);
+                }
+                Some('"') => {
+                    self.pos += 1;
+                    value.push_str(&self.delimited('"')?);
+                }
+                Some(c) if c.is_ascii_digit() => value.push_str(&self.until(&[',', '#', close])),
+                _ => {
+                    let name = self.identifier();
+                    let abbreviation = self.strings.get(&name.to_lowercase()).ok_or_else(|| {
+                        anyhow!("unknown string '{name}' at line {}", self.line())
+                    })?;
+                    value.push_str(abbreviation);
+                }
+            }
+            self.skip_whitespace();
+            if self.peek() == Some('#') {
+                self.pos += 1;
+            } else {
+                return Ok(value.trim().to_string());
+            }
+        }
+    }
+
+    /// Reads a value up to the closing delimiter, removing inner braces.
+    fn delimited(&mut self, end: char) -> anyhow::Result<String> {
+        let mut value = String::new();
+        let mut depth = 0;
+        while let Some(c) = self.next() {
+            match c {
+                '{' => depth += 1,
+                '}' if depth > 0 => depth -= 1,
+                c if c == end && depth == 0 => return Ok(value),
+                '\\' => {
+                    if let Some(escaped) = self.next() {
+                        value.push(escaped);
+                    }
+                }
+                c => value.push(c),
+            }
+        }
+        bail!("unterminated value")
+    }
+}
+
+/// Parses a CSL-JSON file (an array of items).
+pub fn parse_csl_json(source: &str) -> anyhow::Result<Vec<BibEntry>> {
+    let items: Vec<Value> = serde_json::from_str(source)?;
+    items
+        .into_iter()
+        .map(|item| {
+            let text = |name: &str| match item.get(name) {
+                Some(Value::String(s)) => Some(s.clone()),
+                Some(Value::Number(n)) => Some(n.to_string()),
+                _ => None,
+            };
+            let key = text("id").ok_or_else(|| anyhow!("item without an id"))?;
+
+            let names = item
+                .get("author")
+                .or_else(|| item.get("editor"))
+                .and_then(Value::as_array);
+            let authors = names
+                .map(|names| {
+                    names
+                        .iter()
+                        .filter_map(|name| {
+                            let part = |p: &str| name.get(p).and_then(Value::as_str);
+                            match (part("given"), part("family"), part("literal")) {
+                                (_, _, Some(literal)) => Some(literal.to_string()),
+                                (Some(given), Some(family), _) => Some(format!("{given} {family}")),
+                                (None, Some(family), _) => Some(family.to_string()),
+                                _ => None,
+                            }
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            let year = item
+                .pointer("/issued/date-parts/0/0")
+                .map(|y| y.to_string().trim_matches('"').to_string())
+                .or_else(|| {
+                    item.pointer("/issued/literal")
+                        .and_then(Value::as_str)
+                        .map(str::to_string)
+                });
+
+            Ok(BibEntry {
+                kind: text("type").unwrap_or_default(),
+                authors,
+                title: text("title"),
+                year,
+                container: text("container-title"),
+                publisher: text("publisher"),
+                volume: text("volume"),
+                pages: text("page").map(|p| p.replace('-', "–")),
+                url: text("URL"),
+                doi: text("DOI"),
+                key,
+            })
+        })
+        .collect()
+}
+
+/// Writes entries as BibTeX, e.g. so that entries from CSL-JSON files can be loaded by biblatex.
+pub fn to_bibtex(entries: &[BibEntry]) -> String {
+    let mut out = String::new();
+    for entry in entries {
+        let kind = match entry.kind.as_str() {
+            "article" | "article-journal" | "article-magazine" | "article-newspaper" => "article",
+            "book" => "book",
+            "chapter" | "incollection" => "incollection",
+            "paper-conference" | "inproceedings" => "inproceedings",
+            "report" | "techreport" => "report",
+            "thesis" | "phdthesis" | "mastersthesis" => "thesis",
+            "webpage" | "online" => "online",
+            _ => "misc",
+        };
+        let container = match kind {
+            "article" => "journal",
+            "incollection" | "inproceedings" => "booktitle",
+            _ => "howpublished",
+        };
+
+        let authors = (!entry.authors.is_empty()).then(|| entry.authors.join(" and "));
+        let fields = [
+            ("author", authors.map(|a| escape_bibtex(&a))),
+            ("title", entry.title.as_deref().map(escape_bibtex)),
+            ("year", entry.year.as_deref().map(escape_bibtex)),
+            (container, entry.container.as_deref().map(escape_bibtex)),
+            ("publisher", entry.publisher.as_deref().map(escape_bibtex)),
+            ("volume", entry.volume.as_deref().map(escape_bibtex)),
+            ("pages", entry.pages.as_ref().map(|p| p.replace('–', "--"))),
+            // Verbatim fields in biblatex
+            ("url", entry.url.clone()),
+            ("doi", entry.doi.clone()),
+        ];
+
+        out.push_str(&format!("@{kind}{{{},\n", entry.key));
+        for (name, value) in fields {
+            if let Some(value) = value {
+                out.push_str(&format!("  {name} = {{{value}}},\n"));
+            }
+        }
+        out.push_str("}\n\n");
+    }
+    out
+}
+
+fn escape_bibtex(value: &str) -> String {
+    value
+        .chars()
+        .flat_map(|c| match c {
+            '&' | '%' | '$' | '#' | '_' => vec!['\\', c],
+            '{' | '}' => vec![],
+            c => vec![c],
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_bibtex, parse_csl_json, to_bibtex};
+
+    #[test]
+    fn bibtex() {
+        let input = r#"
+@string{acm = "ACM Press"}
+@comment{ignored {entry}}
+
+@Book{knuth1984,
+  author    = {Knuth, Donald E.},
+  title     = {The {\TeX}book},
+  publisher = acm # " (reprint)",
+  year      = 1984,
+}
+
+@inproceedings{vaswani2017,
+  author = "Ashish Vaswani and Shazeer, Noam",
+  title = "Attention is all you need",
+  booktitle = {Advances in Neural
+               Information Processing Systems},
+  pages = {5998--6008}
+}
+"#;
+        let entries = parse_bibtex(input).unwrap();
+        assert_eq!(2, entries.len());
+
+        let book = &entries[0];
+        assert_eq!(
+            ("knuth1984", "book"),
+            (book.key.as_str(), book.kind.as_str())
+        );
+        assert_eq!(vec!["Donald E. Knuth"], book.authors);
+        assert_eq!(Some("The TeXbook"), book.title.as_deref());
+        assert_eq!(Some("ACM Press (reprint)"), book.publisher.as_deref());
+        assert_eq!(Some("1984"), book.year.as_deref());
+
+        let paper = &entries[1];
+        assert_eq!(vec!["Ashish Vaswani", "Noam Shazeer"], paper.authors);
+        assert_eq!(
+            Some("Advances in Neural Information Processing Systems"),
+            paper.container.as_deref()
+        );
+        assert_eq!(Some("5998–6008"), paper.pages.as_deref());
+    }
+
+    #[test]
+    fn csl_json() {
+        let input = r#"[{
+            "id": "knuth1984",
+            "type": "book",
+            "title": "The TeXbook",
+            "author": [{"family": "Knuth", "given": "Donald E."}],
+            "issued": {"date-parts": [[1984]]},
+            "publisher": "Addison-Wesley"
+        }]"#;
+        let entries = parse_csl_json(input).unwrap();
+        assert_eq!(vec!["Donald E. Knuth"], entries[0].authors);
+        assert_eq!(Some("1984"), entries[0].year.as_deref());
+        assert_eq!(Some("Addison-Wesley"), entries[0].publisher.as_deref());
+    }
+
+    #[test]
+    fn csl_json_to_bibtex() {
+        let input = r#"[{
+            "id": "vaswani2017",
+            "type": "paper-conference",
+            "title": "Attention & memory",
+            "author": [{"family": "Vaswani", "given": "Ashish"}, {"family": "Shazeer", "given": "Noam"}],
+            "container-title": "Advances in Neural Information Processing Systems",
+            "page": "5998-6008",
+            "URL": "https://example.com/a_b"
+        }]"#;
+        let entries = parse_csl_json(input).unwrap();
+        let output = to_bibtex(&entries);
+        assert!(
+            output.starts_with("@inproceedings{vaswani2017,\n"),
+            "{output}"
+        );
+        assert!(
+            output.contains("  title = {Attention \\& memory},\n"),
+            "{output}"
+        );
+        assert!(
+            output.contains("  url = {https://example.com/a_b},\n"),
+            "{output}"
+        );
+
+        let parsed = parse_bibtex(&output).unwrap();
+        assert_eq!(vec!["Ashish Vaswani", "Noam Shazeer"], parsed[0].authors);
+        assert_eq!(entries[0].container, parsed[0].container);
+        assert_eq!(entries[0].pages, parsed[0].pages);
+    }
+}
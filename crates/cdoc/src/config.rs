@@ -39,6 +39,11 @@ pub trait Format: DynClone + Debug + Send + Sync {
     /// Determines whether non-source files should be copied to
     fn include_resources(&self) -> bool;
     fn layout(&self) -> Option<String>;
+    /// Whether raw content targeting the given format (e.g. a ```` ```{=html} ```` block) is
+    /// included in the output. Matches the format name or the template prefix by default.
+    fn accepts_raw(&self, format: &str) -> bool {
+        format == self.name() || format == self.template_prefix()
+    }
 }
 
 impl PartialEq for dyn Format {
@@ -165,6 +170,11 @@ impl Format for NotebookFormat {
     fn layout(&self) -> Option<String> {
         None
     }
+
+    /// Markdown cells are rendered by Jupyter, which also displays html.
+    fn accepts_raw(&self, format: &str) -> bool {
+        matches!(format, "notebook" | "markdown" | "html")
+    }
 }
 
 #[typetag::serde(name = "html")]
@@ -0,0 +1,238 @@
+use crate::renderers::generic::string_param;
+use anyhow::Context;
+use cdoc_parser::ast::visitor::AstVisitor;
+use cdoc_parser::ast::{Ast, Command};
+use cdoc_parser::document::Document;
+use cdoc_parser::Span;
+use linked_hash_map::LinkedHashMap;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// Terms and their definitions from the project's `glossary.yml`.
+#[derive(Debug, Clone, Default)]
+pub struct Glossary {
+    terms: LinkedHashMap<String, String>,
+}
+
+impl Glossary {
+    pub fn new(terms: LinkedHashMap<String, String>) -> Self {
+        Glossary { terms }
+    }
+
+    /// Loads a yaml file that maps each term to its definition.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("could not read glossary {}", path.display()))?;
+        let terms = serde_yaml::from_str(&source)
+            .with_context(|| format!("invalid glossary {}", path.display()))?;
+        Ok(Glossary { terms })
+    }
+
+    /// Looks up a term (ignoring case) and returns its name as defined and its definition.
+    pub fn get(&self, term: &str) -> Option<(&str, &str)> {
+        self.terms
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(term))
+            .map(|(name, definition)| (name.as_str(), definition.as_str()))
+    }
+}
+
+/// Id of a term in the glossary (lowercase with dashes).
+pub fn term_slug(term: &str) -> String {
+    term.split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Anchor of the `num`th use of a term in a document.
+pub fn term_anchor(term: &str, num: usize) -> String {
+    format!("term-{}-{num}", term_slug(term))
+}
+
+/// Name of the term used by a `#term` shortcode and the text that is shown in its place.
+pub fn term_params(cmd: &Command) -> Option<(&str, &str)> {
+    let term = string_param(cmd, "term", true)?;
+    Some((term, string_param(cmd, "text", false).unwrap_or(term)))
+}
+
+/// A use of the `#term` shortcode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TermUse {
+    /// The term as written in the document.
+    pub term: String,
+    /// Id of the rendered term, used for linking to it from the index.
+    pub anchor: String,
+    pub span: Span,
+}
+
+/// Collects the uses of `#term` in document order. Uses of the same term are numbered in the same
+/// way as when the document is rendered.
+#[derive(Default)]
+struct TermVisitor {
+    uses: Vec<TermUse>,
+    counts: HashMap<String, usize>,
+}
+
+impl AstVisitor for TermVisitor {
+    fn visit_command(&mut self, cmd: &mut Command) -> anyhow::Result<()> {
+        if cmd.function == "term" {
+            if let Some((term, _)) = term_params(cmd) {
+                let num = self.counts.entry(term_slug(term)).or_insert(0);
+                *num += 1;
+                self.uses.push(TermUse {
+                    term: term.to_string(),
+                    anchor: term_anchor(term, *num),
+                    span: cmd.span.clone(),
+                });
+            }
+        }
+        if let Some(body) = &mut cmd.body {
+            self.walk_vec_block(body)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn collect_terms(doc: &mut Document<Ast>) -> anyhow::Result<Vec<TermUse>> {
+    let mut visitor = TermVisitor::default();
+    visitor.walk_ast(&mut doc.content.blocks)?;
+    Ok(visitor.uses)
+}
+
+/// A place where a term is used.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TermOccurrence {
+    /// The document (its path in the content folder without extension).
+    pub doc: String,
+    pub title: String,
+    /// Output file of the document, relative to the build folder of the format.
+    pub path: String,
+    pub anchor: String,
+}
+
+/// A term with its definition (if it is defined) and all places where it is used.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GlossaryEntry {
+    pub term: String,
+    pub id: String,
+    pub definition: Option<String>,
+    pub occurrences: Vec<TermOccurrence>,
+}
+
+/// Uses of terms in all documents of a project (for a single output format).
+#[derive(Debug, Clone, Default)]
+pub struct TermIndex {
+    documents: HashMap<String, Vec<(String, TermOccurrence)>>,
+}
+
+impl TermIndex {
+    /// Adds (or replaces) the term uses of a document.
+    pub fn insert_document(&mut self, doc: &str, path: &str, title: &str, uses: &[TermUse]) {
+        let occurrences = uses
+            .iter()
+            .map(|u| {
+                (
+                    u.term.clone(),
+                    TermOccurrence {
+                        doc: doc.to_string(),
+                        title: title.to_string(),
+                        path: path.to_string(),
+                        anchor: u.anchor.clone(),
+                    },
+                )
+            })
+            .collect();
+        self.documents.insert(doc.to_string(), occurrences);
+    }
+
+    /// All defined and used terms in alphabetical order. Occurrences are ordered by document.
+    pub fn entries(&self, glossary: &Glossary) -> Vec<GlossaryEntry> {
+        let mut entries: BTreeMap<String, GlossaryEntry> = glossary
+            .terms
+            .iter()
+            .map(|(term, definition)| {
+                (
+                    term.to_lowercase(),
+                    GlossaryEntry {
+                        term: term.clone(),
+                        id: term_slug(term),
+                        definition: Some(definition.clone()),
+                        occurrences: vec![],
+                    },
+                )
+            })
+            .collect();
+
+        let mut docs: Vec<&String> = self.documents.keys().collect();
+        docs.sort();
+        for (term, occurrence) in docs.into_iter().flat_map(|d| &self.documents[d]) {
+            let term = glossary.get(term).map_or(term.as_str(), |(t, _)| t);
+            entries
+                .entry(term.to_lowercase())
+                .or_insert_with(|| GlossaryEntry {
+                    term: term.to_string(),
+                    id: term_slug(term),
+                    definition: None,
+                    occurrences: vec![],
+                })
+                .occurrences
+                .push(occurrence.clone());
+        }
+
+        entries.into_values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{collect_terms, term_slug, Glossary, TermIndex};
+    use cdoc_parser::ast::Ast;
+    use cdoc_parser::document::Document;
+    use linked_hash_map::LinkedHashMap;
+
+    #[test]
+    fn term_index() {
+        let glossary = Glossary::new(LinkedHashMap::from_iter([
+            ("Loss".to_string(), "Error of a model.".to_string()),
+            ("gradient descent".to_string(), "Optimisation.".to_string()),
+        ]));
+
+        let mut doc: Document<Ast> = Document::try_from(
+            "#term(gradient descent) minimises the #term(loss), see #term(Gradient Descent, text=GD) and #term(epoch).",
+        )
+        .unwrap();
+        let uses = collect_terms(&mut doc).unwrap();
+        let anchors: Vec<&str> = uses.iter().map(|u| u.anchor.as_str()).collect();
+        assert_eq!(
+            vec![
+                "term-gradient-descent-1",
+                "term-loss-1",
+                "term-gradient-descent-2",
+                "term-epoch-1"
+            ],
+            anchors
+        );
+
+        let mut index = TermIndex::default();
+        index.insert_document("intro", "intro.html", "Intro", &uses);
+        let entries = index.entries(&glossary);
+
+        let terms: Vec<(&str, usize, bool)> = entries
+            .iter()
+            .map(|e| (e.term.as_str(), e.occurrences.len(), e.definition.is_some()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("epoch", 1, false),
+                ("gradient descent", 2, true),
+                ("Loss", 1, true)
+            ],
+            terms
+        );
+        assert_eq!("gradient-descent", term_slug("Gradient  descent"));
+    }
+}
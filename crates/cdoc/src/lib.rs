@@ -1,12 +1,21 @@
 //! CDoc is a tool for rendering markdown-based document formats (markdown and jupyter notebooks) to
 //! user-defined output formats. Output formats are entirely user defined through templates.
 
+/// Loads BibTeX and CSL-JSON bibliographies used for citations.
+pub mod bibliography;
+
 /// Defines input/output format configuration types.
 pub mod config;
 
+/// Glossary terms and the index of where they are used.
+pub mod glossary;
+
 /// Defines types for loading content files and parsing them to the internal format. Can be extended.
 pub mod loader;
 
+/// Numbering of labelled elements (figures, equations, ...) across the documents of a project.
+pub mod numbering;
+
 /// Provides a type for applying preprocessors to documents.
 pub mod parser;
 
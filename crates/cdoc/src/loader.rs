@@ -6,6 +6,7 @@ use anyhow::{anyhow, Context};
 use cdoc_parser::ast::Ast;
 use cdoc_parser::document::{Document, Metadata};
 use cdoc_parser::notebook::{notebook_to_doc, Notebook};
+use cdoc_parser::Diagnostic;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -18,11 +19,27 @@ pub enum LoaderError {
     Json(#[from] serde_json::Error),
 }
 
+/// A loaded document together with the syntax problems that the parser recovered from.
+#[derive(Debug)]
+pub struct LoadedDocument {
+    pub doc: Document<Ast>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl From<Document<Ast>> for LoadedDocument {
+    fn from(doc: Document<Ast>) -> Self {
+        LoadedDocument {
+            doc,
+            diagnostics: vec![],
+        }
+    }
+}
+
 /// A loader creates an initial [Document] from a raw input string loaded from a content file.
 #[typetag::serde(tag = "type")]
 pub trait Loader: Debug {
     /// Perform any parsing/conversion necessary.
-    fn load(&self, input: &str, accept_draft: bool) -> anyhow::Result<Option<Document<Ast>>>;
+    fn load(&self, input: &str, accept_draft: bool) -> anyhow::Result<Option<LoadedDocument>>;
 }
 
 /// Parses a Jupyter Notebook file (.ipynb).
@@ -31,10 +48,10 @@ pub struct NotebookLoader;
 
 #[typetag::serde(name = "notebook_loader")]
 impl Loader for NotebookLoader {
-    fn load(&self, input: &str, accept_draft: bool) -> anyhow::Result<Option<Document<Ast>>> {
+    fn load(&self, input: &str, accept_draft: bool) -> anyhow::Result<Option<LoadedDocument>> {
         let nb: Notebook =
             serde_json::from_str(input).context(anyhow!("deserializing notebook"))?;
-        notebook_to_doc(nb, accept_draft)
+        Ok(notebook_to_doc(nb, accept_draft)?.map(LoadedDocument::from))
     }
 }
 
@@ -44,14 +61,14 @@ pub struct MarkdownLoader;
 
 #[typetag::serde(name = "markdown_loader")]
 impl Loader for MarkdownLoader {
-    fn load(&self, input: &str, accept_draft: bool) -> anyhow::Result<Option<Document<Ast>>> {
+    fn load(&self, input: &str, accept_draft: bool) -> anyhow::Result<Option<LoadedDocument>> {
         if accept_draft {
-            Some(Document::try_from(input)).transpose()
+            Ok(Some(parse_markdown(input)))
         } else {
             let doc: yaml_front_matter::Document<Metadata> =
                 yaml_front_matter::YamlFrontMatter::parse(input).unwrap();
             if !doc.metadata.draft {
-                Some(Document::try_from(input)).transpose()
+                Ok(Some(parse_markdown(input)))
             } else {
                 Ok(None)
             }
@@ -59,19 +76,45 @@ impl Loader for MarkdownLoader {
     }
 }
 
+/// Parses a markdown document. Syntax problems do not stop the build: the parts of the document
+/// that could be parsed are kept and the problems are returned as diagnostics.
+fn parse_markdown(input: &str) -> LoadedDocument {
+    let (doc, diagnostics) = Document::parse_with_diagnostics(input);
+    LoadedDocument { doc, diagnostics }
+}
+
 #[cfg(test)]
 mod tests {
-    // use super::*;
-    //
-    // #[test]
-    // fn test_deserialization() {
-    //     let config = r#"
-    //         {
-    //             ".md": {"type": "markdown_loader"},
-    //             ".ipynb": {"type": "notebook_loader"}
-    //         }
-    //     "#;
-    //
-    //     let p: LoaderConfig = serde_json::from_str(config).unwrap();
-    // }
+    use super::{Loader, MarkdownLoader};
+    use crate::config::{Format, HtmlFormat};
+    use crate::parser::ParserSettings;
+    use crate::renderers::RenderContext;
+    use crate::templates::TemplateManager;
+    use std::path::PathBuf;
+
+    #[test]
+    fn render_with_syntax_error() {
+        let input = "---\ntitle: Test\n---\n# Title\n\n#message(color=info){ Unterminated $x }\n\nAfter the *error*.\n";
+        let mut loaded = MarkdownLoader.load(input, false).unwrap().unwrap();
+        assert_eq!(1, loaded.diagnostics.len(), "{:?}", loaded.diagnostics);
+
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/config/templates");
+        let templates = TemplateManager::from_path(root.clone(), root, false).unwrap();
+        let format = HtmlFormat {};
+        let meta = Default::default();
+        let mut ctx = RenderContext::new(
+            &mut loaded.doc,
+            &templates,
+            Default::default(),
+            &meta,
+            &format,
+            ParserSettings::default(),
+            Default::default(),
+        )
+        .unwrap();
+        let output = format.renderer().render_doc(&mut ctx, vec![]).unwrap();
+
+        assert!(output.content.contains("message is-info"));
+        assert!(output.content.contains("<em>error</em>"));
+    }
 }
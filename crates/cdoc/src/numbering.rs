@@ -0,0 +1,183 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Where the counter of an element type restarts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NumberingScope {
+    /// A single counter for the whole project.
+    Global,
+    /// The counter restarts in every section at the reset level of the content tree.
+    Section,
+    /// The counter restarts in every document.
+    #[default]
+    Document,
+}
+
+/// Numbering scheme of an element type (set in the `numbering` section of `config.yml`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Numbering {
+    #[serde(default)]
+    pub scope: NumberingScope,
+    /// Depth of the sections whose counters are separate (1 restarts numbering in every top-level
+    /// section, 2 in every subsection etc.). Only used with the `section` scope.
+    #[serde(default = "default_reset_level")]
+    pub reset_level: usize,
+    /// Prefixes numbers with the position of the section (or document for the `document`
+    /// scope), e.g. `3.2` for the second figure of the third chapter.
+    #[serde(default)]
+    pub prefix: bool,
+}
+
+fn default_reset_level() -> usize {
+    1
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Counter {
+    prefix: Vec<usize>,
+    /// Number of elements that precede the document's first element.
+    offset: usize,
+}
+
+/// Numbers of the labelled elements in a single document.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DocumentNumbering {
+    counters: HashMap<String, Counter>,
+}
+
+impl DocumentNumbering {
+    /// Number of the `num`th labelled element of the given type in the document. Types without a
+    /// numbering scheme are numbered within the document.
+    pub fn format(&self, obj_type: &str, num: usize) -> String {
+        match self.counters.get(obj_type) {
+            Some(counter) => counter
+                .prefix
+                .iter()
+                .chain([&(counter.offset + num)])
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("."),
+            None => num.to_string(),
+        }
+    }
+}
+
+/// Assigns numbers to the documents of a project. Documents must be added in the order of the
+/// content tree.
+#[derive(Debug, Clone, Default)]
+pub struct ProjectNumbering {
+    config: HashMap<String, Numbering>,
+    /// Elements numbered so far by type and counter (the section or document position).
+    counts: HashMap<(String, Vec<usize>), usize>,
+}
+
+impl ProjectNumbering {
+    pub fn new(config: HashMap<String, Numbering>) -> Self {
+        ProjectNumbering {
+            config,
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Numbering of the next document. The position is given by the (1-based) index of the
+    /// document at each level of the content tree, where section index documents have the
+    /// position of their section. `counts` is the number of labelled elements of each type.
+    pub fn next_document(
+        &mut self,
+        position: &[usize],
+        counts: &HashMap<String, usize>,
+    ) -> DocumentNumbering {
+        let counters = self
+            .config
+            .iter()
+            .map(|(obj_type, numbering)| {
+                let key = match numbering.scope {
+                    NumberingScope::Global => vec![],
+                    NumberingScope::Section => {
+                        position[..numbering.reset_level.min(position.len())].to_vec()
+                    }
+                    NumberingScope::Document => position.to_vec(),
+                };
+
+                let count = self
+                    .counts
+                    .entry((obj_type.clone(), key.clone()))
+                    .or_default();
+                let offset = *count;
+                *count += counts.get(obj_type).copied().unwrap_or_default();
+
+                let prefix = if numbering.prefix { key } else { vec![] };
+                (obj_type.clone(), Counter { prefix, offset })
+            })
+            .collect();
+
+        DocumentNumbering { counters }
+    }
+}
+
+/// Position of a document in the content tree from its path index (see `next_document`).
+pub fn content_position(path_idx: &[usize]) -> &[usize] {
+    let position = path_idx.get(1..).unwrap_or_default();
+    match position.split_last() {
+        Some((0, section)) => section,
+        _ => position,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{content_position, Numbering, NumberingScope, ProjectNumbering};
+    use std::collections::HashMap;
+
+    fn numbering(scope: NumberingScope, prefix: bool) -> Numbering {
+        Numbering {
+            scope,
+            reset_level: 1,
+            prefix,
+        }
+    }
+
+    #[test]
+    fn project_numbering() {
+        let mut numbering = ProjectNumbering::new(HashMap::from([
+            (
+                "figure".to_string(),
+                numbering(NumberingScope::Section, true),
+            ),
+            (
+                "equation".to_string(),
+                numbering(NumberingScope::Global, false),
+            ),
+            (
+                "code".to_string(),
+                numbering(NumberingScope::Document, true),
+            ),
+        ]));
+        let counts = HashMap::from([
+            ("figure".to_string(), 2),
+            ("equation".to_string(), 1),
+            ("code".to_string(), 1),
+        ]);
+
+        let numbers = |position: &[usize], numbering: &mut ProjectNumbering| {
+            let doc = numbering.next_document(content_position(position), &counts);
+            ["figure", "equation", "code", "exercise"].map(|t| doc.format(t, 1))
+        };
+
+        assert_eq!(["1", "1", "1", "1"], numbers(&[0, 0], &mut numbering));
+        assert_eq!(
+            ["3.1", "2", "3.1", "1"],
+            numbers(&[0, 3, 0], &mut numbering)
+        );
+        assert_eq!(
+            ["3.3", "3", "3.1.1", "1"],
+            numbers(&[0, 3, 1], &mut numbering)
+        );
+        assert_eq!(
+            ["3.5", "4", "3.2.1", "1"],
+            numbers(&[0, 3, 2], &mut numbering)
+        );
+        assert_eq!(["4.1", "5", "4.1", "1"], numbers(&[0, 4], &mut numbering));
+    }
+}
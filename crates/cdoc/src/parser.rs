@@ -3,7 +3,11 @@ use cdoc_parser::document::Document;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::preprocessors::conditionals::Conditionals;
+use crate::preprocessors::md_labels::MdLabels;
+use crate::preprocessors::variables::Variables;
 use crate::preprocessors::{AstPreprocessor, AstPreprocessorConfig, PreprocessorContext};
+use cdoc_parser::ast::visitor::Fold;
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Parser {
@@ -31,7 +35,12 @@ impl Parser {
         doc: Document<Ast>,
         ctx: &PreprocessorContext,
     ) -> Result<Document<Ast>, anyhow::Error> {
-        let doc_ast = self.run_ast_processors(doc.clone(), ctx)?;
+        let mut doc = doc;
+        Conditionals::new(ctx).fold_ast(&mut doc.content.blocks)?;
+        Variables::new(&doc.meta, ctx.variables).fold_ast(&mut doc.content.blocks)?;
+        // Headings always get ids so that links and the table of contents work without setup.
+        let doc = MdLabels::default().process(doc)?;
+        let doc_ast = self.run_ast_processors(doc, ctx)?;
 
         Ok(doc_ast)
     }
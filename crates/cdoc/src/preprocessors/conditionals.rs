@@ -0,0 +1,141 @@
+use crate::preprocessors::PreprocessorContext;
+use anyhow::{anyhow, bail};
+use cdoc_parser::ast::visitor::Fold;
+use cdoc_parser::ast::{Block, Command, Inline, Value};
+
+/// Evaluates the conditional shortcodes `#only(...){...}` and `#except(...){...}`. The body of
+/// `only` is kept if all conditions hold and the body of `except` is kept unless they all hold.
+/// Conditions are comma-separated lists of allowed values for the output `format`, the build
+/// `profile` and the build `mode`, e.g. `#only(format="html, latex", mode=draft){...}`.
+///
+/// Removed content is gone before references and numbers are assigned.
+pub struct Conditionals<'a> {
+    pub format: &'a str,
+    pub profile: &'a str,
+    pub mode: &'a str,
+}
+
+impl<'a> Conditionals<'a> {
+    pub fn new(ctx: &PreprocessorContext<'a>) -> Self {
+        Conditionals {
+            format: ctx.output_format.name(),
+            profile: ctx.profile,
+            mode: ctx.mode,
+        }
+    }
+
+    /// Returns the body of a conditional shortcode if it should be kept, and `None` for other
+    /// shortcodes.
+    fn evaluate(&self, cmd: &Command) -> anyhow::Result<Option<Vec<Block>>> {
+        let invert = match cmd.function.as_str() {
+            "only" => false,
+            "except" => true,
+            _ => return Ok(None),
+        };
+
+        let mut holds = true;
+        for param in &cmd.parameters {
+            let actual = match param.key.as_deref() {
+                Some("format") => self.format,
+                Some("profile") => self.profile,
+                Some("mode") => self.mode,
+                Some(key) => bail!(
+                    "unknown condition '{key}' for #{} at {} (expected format, profile or mode)",
+                    cmd.function,
+                    cmd.span
+                ),
+                None => bail!(
+                    "conditions for #{} must be named (at {})",
+                    cmd.function,
+                    cmd.span
+                ),
+            };
+            let Value::String(allowed) = &param.value else {
+                bail!(
+                    "condition values for #{} must be strings (at {})",
+                    cmd.function,
+                    cmd.span
+                );
+            };
+            holds &= allowed.split(',').any(|v| v.trim() == actual);
+        }
+
+        let body = cmd
+            .body
+            .clone()
+            .ok_or_else(|| anyhow!("#{} requires a body (at {})", cmd.function, cmd.span))?;
+        Ok(Some(if holds != invert { body } else { vec![] }))
+    }
+}
+
+impl Fold for Conditionals<'_> {
+    fn fold_block(&mut self, block: Block) -> anyhow::Result<Vec<Block>> {
+        if let Block::Command(cmd) = &block {
+            if let Some(body) = self.evaluate(cmd)? {
+                return self.fold_vec_block(body);
+            }
+        }
+        Ok(vec![self.walk_block(block)?])
+    }
+
+    fn fold_inline(&mut self, inline: Inline) -> anyhow::Result<Vec<Inline>> {
+        if let Inline::Command(cmd) = &inline {
+            if let Some(body) = self.evaluate(cmd)? {
+                let mut inlines = Vec::new();
+                for block in body {
+                    match block {
+                        Block::Plain(inner, _) | Block::Paragraph(inner, _) => {
+                            inlines.append(&mut self.fold_vec_inline(inner)?)
+                        }
+                        _ => bail!(
+                            "#{} within a paragraph may only contain text (at {})",
+                            cmd.function,
+                            cmd.span
+                        ),
+                    }
+                }
+                return Ok(inlines);
+            }
+        }
+        Ok(vec![self.walk_inline(inline)?])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Conditionals;
+    use cdoc_parser::ast::visitor::Fold;
+    use cdoc_parser::ast::{Ast, Block};
+    use cdoc_parser::document::Document;
+
+    fn evaluate(input: &str, format: &str, profile: &str) -> Vec<Block> {
+        let mut doc: Document<Ast> = Document::try_from(input).unwrap();
+        let mut conditionals = Conditionals {
+            format,
+            profile,
+            mode: "draft",
+        };
+        conditionals.fold_ast(&mut doc.content.blocks).unwrap();
+        doc.content.blocks
+    }
+
+    #[test]
+    fn conditional_blocks() {
+        let input = "A\n\n#only(format=\"html, latex\"){\nweb\n}\n\n#except(format=notebook){\nnot notebook\n}\n\n#only(profile=instructor, mode=draft){\nnotes\n}\n";
+
+        assert_eq!(4, evaluate(input, "html", "instructor").len());
+        assert_eq!(3, evaluate(input, "html", "release").len());
+        assert_eq!(1, evaluate(input, "notebook", "release").len());
+    }
+
+    #[test]
+    fn conditional_inlines() {
+        let input =
+            "Run the cell #only(format=notebook){below}#except(format=notebook){in the notebook}.";
+
+        let Block::Paragraph(inner, _) = &evaluate(input, "html", "release")[0] else {
+            panic!("expected a paragraph");
+        };
+        assert_eq!(3, inner.len());
+    }
+}
@@ -0,0 +1,494 @@
+use crate::preprocessors::conditionals::Conditionals;
+use anyhow::{anyhow, bail, Context};
+use cdoc_parser::ast::visitor::{AstVisitor, Fold};
+use cdoc_parser::ast::{for_each_span_mut, Ast, Block, CodeBlock, Command, Inline, Value};
+use cdoc_parser::code_ast::parse_code_string;
+use cdoc_parser::document::Document;
+use cdoc_parser::raw::Attributes;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Resolves the shortcodes that pull in content from other files. Paths are relative to the file
+/// that contains the shortcode.
+///
+/// - `#include(path="../shared/setup.md", shift_headings=1)` is replaced with the content of the
+///   markdown file. Included files may include other files, but not themselves.
+/// - `#code(file="solver.py", region="solve")` (or `lines="10-30"`) is replaced with a code block
+///   containing the region of the file marked with `# region: solve` and `# endregion` comments
+///   (or the given line range).
+///
+/// With conditions, `#only` and `#except` are evaluated in each file before its shortcodes are
+/// resolved, so files are never read for content that is left out.
+pub struct Includes<'a> {
+    /// The chain of files currently being included, starting with the document itself.
+    stack: Vec<PathBuf>,
+    /// Every file that was included, directly or through other includes.
+    pub included: Vec<PathBuf>,
+    conditions: Option<Conditionals<'a>>,
+}
+
+impl<'a> Includes<'a> {
+    /// Creates the resolver for the document at the given path.
+    pub fn new(doc_path: impl AsRef<Path>) -> Self {
+        let doc_path = doc_path.as_ref();
+        Includes {
+            stack: vec![fs::canonicalize(doc_path).unwrap_or_else(|_| doc_path.to_path_buf())],
+            included: Vec::new(),
+            conditions: None,
+        }
+    }
+
+    /// Evaluates the conditional shortcodes before resolving includes.
+    pub fn with_conditions(mut self, conditions: Conditionals<'a>) -> Self {
+        self.conditions = Some(conditions);
+        self
+    }
+
+    pub fn process(&mut self, doc: &mut Document<Ast>) -> anyhow::Result<()> {
+        self.resolve_blocks(&mut doc.content.blocks)
+    }
+
+    fn resolve_blocks(&mut self, blocks: &mut Vec<Block>) -> anyhow::Result<()> {
+        if let Some(conditions) = self.conditions.as_mut() {
+            conditions.fold_ast(blocks)?;
+        }
+        self.fold_ast(blocks)
+    }
+
+    /// Resolves a path relative to the current file and records it as included.
+    fn resolve(&mut self, path: &str, cmd: &Command) -> anyhow::Result<PathBuf> {
+        let current = self
+            .stack
+            .last()
+            .expect("the document is always on the stack");
+        let target = current.parent().unwrap_or(Path::new("")).join(path);
+        let target = fs::canonicalize(&target).with_context(|| {
+            format!(
+                "could not include {} in #{} at {}",
+                target.display(),
+                cmd.function,
+                cmd.span
+            )
+        })?;
+
+        if !self.included.contains(&target) {
+            self.included.push(target.clone());
+        }
+        Ok(target)
+    }
+
+    fn include(&mut self, cmd: &Command) -> anyhow::Result<Vec<Block>> {
+        let path = string_param(cmd, "path", Some(0))?
+            .ok_or_else(|| anyhow!("missing argument 'path' for #include at {}", cmd.span))?;
+        let shift = match string_param(cmd, "shift_headings", Some(1))? {
+            Some(shift) => shift.parse::<u8>().with_context(|| {
+                format!(
+                    "invalid value '{shift}' for 'shift_headings' at {}",
+                    cmd.span
+                )
+            })?,
+            None => 0,
+        };
+
+        let target = self.resolve(path, cmd)?;
+        if self.stack.contains(&target) {
+            let chain = self
+                .stack
+                .iter()
+                .skip_while(|p| **p != target)
+                .chain(std::iter::once(&target))
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            bail!("include cycle: {chain}");
+        }
+
+        let input = fs::read_to_string(&target)
+            .with_context(|| format!("could not include {}", target.display()))?;
+        let mut included: Document<Ast> = Document::try_from(input.as_str())
+            .with_context(|| format!("could not parse included file {}", target.display()))?;
+
+        self.stack.push(target.clone());
+        self.resolve_blocks(&mut included.content.blocks)
+            .with_context(|| format!("in included file {}", target.display()))?;
+        self.stack.pop();
+
+        let mut blocks = included.content.blocks;
+        if shift > 0 {
+            HeadingShift(shift).walk_ast(&mut blocks)?;
+        }
+        // Spans of the included blocks refer to another source, so they are replaced by the span
+        // of the shortcode. Messages then point to the #include and never read the wrong source.
+        for_each_span_mut(&mut blocks, &mut |span| *span = cmd.span.clone());
+        Ok(blocks)
+    }
+
+    fn import_code(&mut self, cmd: &Command) -> anyhow::Result<CodeBlock> {
+        let file = string_param(cmd, "file", Some(0))?
+            .ok_or_else(|| anyhow!("missing argument 'file' for #code at {}", cmd.span))?;
+        let target = self.resolve(file, cmd)?;
+        let input = fs::read_to_string(&target)
+            .with_context(|| format!("could not read {}", target.display()))?;
+
+        let selected = match (
+            string_param(cmd, "region", None)?,
+            string_param(cmd, "lines", None)?,
+        ) {
+            (Some(region), _) => select_region(&input, region),
+            (None, Some(lines)) => select_lines(&input, lines),
+            (None, None) => Ok(input
+                .lines()
+                .filter(|l| region_start(l).is_none() && !is_region_end(l))
+                .collect()),
+        }
+        .with_context(|| {
+            format!(
+                "could not import code from {} at {}",
+                target.display(),
+                cmd.span
+            )
+        })?;
+
+        let source = parse_code_string(dedent(&selected).into())
+            .with_context(|| format!("could not parse code from {}", target.display()))?;
+
+        let lang = string_param(cmd, "lang", None)?
+            .or_else(|| target.extension().and_then(|e| e.to_str()).map(language));
+        Ok(CodeBlock {
+            label: cmd.label.clone(),
+            source,
+            attributes: Attributes {
+                flags: lang.into_iter().map(Into::into).collect(),
+                ..Default::default()
+            },
+            display_cell: false,
+            global_idx: cmd.global_idx,
+            span: cmd.span.clone(),
+        })
+    }
+}
+
+impl Fold for Includes<'_> {
+    fn fold_block(&mut self, block: Block) -> anyhow::Result<Vec<Block>> {
+        match standalone_commands(&block) {
+            Some(cmds) if cmds.iter().all(|c| c.function == "include") => {
+                let mut blocks = Vec::new();
+                for cmd in cmds {
+                    blocks.append(&mut self.include(cmd)?);
+                }
+                Ok(blocks)
+            }
+            Some(cmds) if cmds.iter().all(|c| c.function == "code") => cmds
+                .into_iter()
+                .map(|cmd| Ok(Block::CodeBlock(self.import_code(cmd)?)))
+                .collect(),
+            _ => Ok(vec![self.walk_block(block)?]),
+        }
+    }
+
+    fn fold_inline(&mut self, inline: Inline) -> anyhow::Result<Vec<Inline>> {
+        match inline {
+            Inline::Command(cmd) if cmd.function == "include" => Err(anyhow!(
+                "#include must stand on its own line (at {})",
+                cmd.span
+            )),
+            Inline::Command(cmd) if cmd.function == "code" => {
+                Ok(vec![Inline::CodeBlock(self.import_code(&cmd)?)])
+            }
+            inline => Ok(vec![self.walk_inline(inline)?]),
+        }
+    }
+}
+
+/// Returns the shortcodes of a block that consists of nothing else, e.g. a paragraph with one
+/// shortcode per line.
+fn standalone_commands(block: &Block) -> Option<Vec<&Command>> {
+    let cmds = match block {
+        Block::Command(cmd) => vec![cmd],
+        Block::Plain(inner, _) | Block::Paragraph(inner, _) => inner
+            .iter()
+            .filter_map(|i| match i {
+                Inline::Text(s, _) if s.trim().is_empty() => None,
+                Inline::SoftBreak(_) | Inline::HardBreak(_) => None,
+                Inline::Command(cmd) => Some(Some(cmd)),
+                _ => Some(None),
+            })
+            .collect::<Option<Vec<_>>>()?,
+        _ => return None,
+    };
+    (!cmds.is_empty()).then_some(cmds)
+}
+
+/// Finds a string argument by its name or, if given, its position.
+fn string_param<'a>(
+    cmd: &'a Command,
+    name: &str,
+    pos: Option<usize>,
+) -> anyhow::Result<Option<&'a str>> {
+    let param = cmd
+        .parameters
+        .iter()
+        .find(|p| p.key.as_deref() == Some(name))
+        .or_else(|| {
+            let pos = pos?;
+            cmd.parameters.iter().filter(|p| p.key.is_none()).nth(pos)
+        });
+
+    match param.map(|p| &p.value) {
+        None => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s.as_str())),
+        Some(_) => Err(anyhow!(
+            "argument '{name}' for #{} must be a string (at {})",
+            cmd.function,
+            cmd.span
+        )),
+    }
+}
+
+/// Returns the text of a line comment, e.g. `region: solve` for `# region: solve`.
+fn comment_text(line: &str) -> Option<&str> {
+    let line = line.trim();
+    ["#", "//", "--", "%", ";"]
+        .iter()
+        .find_map(|c| line.strip_prefix(c))
+        .map(str::trim)
+}
+
+fn region_start(line: &str) -> Option<&str> {
+    comment_text(line)?.strip_prefix("region:").map(str::trim)
+}
+
+fn is_region_end(line: &str) -> bool {
+    comment_text(line).is_some_and(|t| t == "endregion" || t.starts_with("endregion:"))
+}
+
+/// Selects the lines of a named region. Markers of nested regions are left out.
+fn select_region<'a>(input: &'a str, name: &str) -> anyhow::Result<Vec<&'a str>> {
+    let mut lines = Vec::new();
+    let mut depth = 0;
+    let mut found = false;
+
+    for line in input.lines() {
+        if depth == 0 {
+            if region_start(line) == Some(name) {
+                depth = 1;
+                found = true;
+            }
+        } else if region_start(line).is_some() {
+            depth += 1;
+        } else if is_region_end(line) {
+            depth -= 1;
+            if depth == 0 {
+                break;
+            }
+        } else {
+            lines.push(line);
+        }
+    }
+
+    if !found {
+        bail!("region '{name}' not found");
+    }
+    if depth > 0 {
+        bail!("region '{name}' is not closed");
+    }
+    Ok(lines)
+}
+
+/// Selects a line range such as `10-30`, `10-` or `10`. Line numbers start at 1.
+fn select_lines<'a>(input: &'a str, range: &str) -> anyhow::Result<Vec<&'a str>> {
+    let lines: Vec<&str> = input.lines().collect();
+    let parse = |s: &str| {
+        s.trim()
+            .parse::<usize>()
+            .with_context(|| format!("invalid line range '{range}'"))
+    };
+
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) if end.trim().is_empty() => (parse(start)?, lines.len()),
+        Some((start, end)) => (parse(start)?, parse(end)?),
+        None => (parse(range)?, parse(range)?),
+    };
+
+    if start == 0 || start > end || end > lines.len() {
+        bail!(
+            "invalid line range '{range}' for a file with {} lines",
+            lines.len()
+        );
+    }
+    Ok(lines[start - 1..end].to_vec())
+}
+
+/// Removes the indentation shared by all non-empty lines.
+fn dedent(lines: &[&str]) -> String {
+    let indent = lines
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or_default();
+
+    lines
+        .iter()
+        .map(|l| l.get(indent..).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The language name used for code blocks given a file extension.
+fn language(ext: &str) -> &str {
+    match ext {
+        "py" => "python",
+        "rs" => "rust",
+        "js" => "javascript",
+        "ts" => "typescript",
+        "rb" => "ruby",
+        "sh" => "bash",
+        "jl" => "julia",
+        other => other,
+    }
+}
+
+/// Increases the level of all headings (up to the maximum of 6).
+struct HeadingShift(u8);
+
+impl AstVisitor for HeadingShift {
+    fn visit_block(&mut self, block: &mut Block) -> anyhow::Result<()> {
+        if let Block::Heading { lvl, .. } = block {
+            *lvl = lvl.saturating_add(self.0).min(6);
+        }
+        self.walk_block(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{select_lines, select_region, Includes};
+    use crate::preprocessors::conditionals::Conditionals;
+    use cdoc_parser::ast::query::find_headings;
+    use cdoc_parser::ast::{Ast, Block};
+    use cdoc_parser::document::Document;
+    use std::fs;
+
+    fn resolve(
+        dir: &std::path::Path,
+        input: &str,
+    ) -> anyhow::Result<(Document<Ast>, Includes<'static>)> {
+        let doc_path = dir.join("doc.md");
+        fs::write(&doc_path, input)?;
+        let mut doc: Document<Ast> = Document::try_from(input)?;
+        let mut includes = Includes::new(&doc_path);
+        includes.process(&mut doc)?;
+        Ok((doc, includes))
+    }
+
+    #[test]
+    fn includes_fragments() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("shared")).unwrap();
+        fs::write(
+            dir.path().join("shared/setup.md"),
+            "# Setup\n\nInstall it.\n\n#include(path=\"env.md\")\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("shared/env.md"), "## Environment\n").unwrap();
+
+        let (doc, includes) = resolve(
+            dir.path(),
+            "# Doc\n\n#include(path=\"shared/setup.md\", shift_headings=1)\n\nAfter\n",
+        )
+        .unwrap();
+
+        let levels: Vec<u8> = find_headings(&doc.content.blocks, 6)
+            .into_iter()
+            .map(|b| match b {
+                Block::Heading { lvl, .. } => *lvl,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(vec![1, 2, 3], levels);
+        assert_eq!(5, doc.content.blocks.len());
+        // Included blocks point to the #include in the document.
+        assert!(doc.content.blocks[1..4]
+            .iter()
+            .all(|b| b.span().range.start == 7 && b.span().start.line == 3));
+        assert_eq!(2, includes.included.len());
+    }
+
+    #[test]
+    fn detects_cycles() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.md"), "#include(b.md)\n").unwrap();
+        fs::write(dir.path().join("b.md"), "#include(a.md)\n").unwrap();
+
+        let err = resolve(dir.path(), "#include(a.md)\n").err().unwrap();
+        let cause = err.root_cause().to_string();
+        assert!(cause.starts_with("include cycle: "));
+        assert!(cause.ends_with("a.md"));
+        assert!(err.to_string().starts_with("in included file "));
+    }
+
+    #[test]
+    fn skips_excluded_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("shared.md"),
+            "Shared\n\n#only(format=notebook){\n#include(missing.md)\n}\n",
+        )
+        .unwrap();
+        let input = "#except(format=html){\n#include(missing.md)\n}\n\n#include(shared.md)\n";
+        let doc_path = dir.path().join("doc.md");
+        let mut doc: Document<Ast> = Document::try_from(input).unwrap();
+
+        let mut includes = Includes::new(&doc_path).with_conditions(Conditionals {
+            format: "html",
+            profile: "default",
+            mode: "draft",
+        });
+        includes.process(&mut doc).unwrap();
+
+        assert_eq!(1, doc.content.blocks.len());
+        assert_eq!(1, includes.included.len());
+        // Without conditions, the missing file is an error.
+        assert!(resolve(dir.path(), input).is_err());
+    }
+
+    #[test]
+    fn selects_code() {
+        let input = "import x\n\nclass A:\n    # region: solve\n    def solve(self):\n        # region: inner\n        return 1\n        # endregion\n    # endregion\n";
+
+        assert_eq!(
+            vec!["    def solve(self):", "        return 1"],
+            select_region(input, "solve").unwrap()
+        );
+        assert!(select_region(input, "missing").is_err());
+        assert_eq!(vec!["", "class A:"], select_lines(input, "2-3").unwrap());
+        assert_eq!(vec!["    # endregion"], select_lines(input, "9-").unwrap());
+        assert!(select_lines(input, "5-20").is_err());
+    }
+
+    #[test]
+    fn imports_code() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("solver.py"),
+            "def solve():\n    # region: body\n    #| solution <<\n    return 42\n    #| placeholder\n    # pass\n    #| >>\n    # endregion\n",
+        )
+        .unwrap();
+
+        let (doc, includes) = resolve(
+            dir.path(),
+            "Text\n\n#code|lst(file=solver.py, region=body)\n",
+        )
+        .unwrap();
+
+        let Block::CodeBlock(block) = &doc.content.blocks[1] else {
+            panic!("expected a code block");
+        };
+        assert_eq!(Some("lst"), block.label.as_deref());
+        assert!(block.attributes.has_flag("python"));
+        assert_eq!("return 42\n", block.source.to_string(true).unwrap());
+        assert_eq!("pass\n", block.source.to_string(false).unwrap());
+        assert_eq!(1, includes.included.len());
+    }
+}
@@ -4,14 +4,20 @@ use cdoc_parser::ast::visitor::AstVisitor;
 use cdoc_parser::ast::{Ast, Block, Inline};
 use cdoc_parser::document::Document;
 use cowstr::CowStr;
-use nanoid::nanoid;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::fmt::{Display, Formatter};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MdLabelsConfig;
 
-pub struct MdLabels;
+/// Gives every heading an id. Ids set with a `{#id}` attribute or a `#label` shortcode are kept
+/// and the remaining headings get a GitHub-style slug of their text. Slugs that are already used in
+/// the document get a `-1`, `-2`, ... suffix so that the ids are stable between builds.
+#[derive(Default)]
+pub struct MdLabels {
+    used: HashSet<String>,
+}
 
 #[typetag::serde(name = "md_labels")]
 impl AstPreprocessorConfig for MdLabelsConfig {
@@ -20,16 +26,21 @@ impl AstPreprocessorConfig for MdLabelsConfig {
         _ctx: &PreprocessorContext,
         _settings: &ParserSettings,
     ) -> anyhow::Result<Box<dyn AstPreprocessor>> {
-        Ok(Box::new(MdLabels))
+        Ok(Box::new(MdLabels::default()))
     }
 }
 
 impl AstPreprocessor for MdLabels {
     fn name(&self) -> String {
-        todo!()
+        "Heading labels".to_string()
     }
 
     fn process(&mut self, mut input: Document<Ast>) -> Result<Document<Ast>, Error> {
+        // Explicit ids are collected first so that generated ids never collide with them.
+        let mut explicit = ExplicitIds::default();
+        explicit.walk_ast(&mut input.content.blocks)?;
+        self.used = explicit.0;
+
         self.walk_ast(&mut input.content.blocks)?;
         Ok(input)
     }
@@ -41,7 +52,37 @@ impl Display for MdLabels {
     }
 }
 
+impl MdLabels {
+    fn unique_id(&mut self, slug: String) -> String {
+        let mut id = slug.clone();
+        let mut n = 0;
+        while self.used.contains(&id) {
+            n += 1;
+            id = format!("{slug}-{n}");
+        }
+        self.used.insert(id.clone());
+        id
+    }
+}
+
 impl AstVisitor for MdLabels {
+    fn visit_block(&mut self, block: &mut Block) -> anyhow::Result<()> {
+        if let Block::Heading { id, inner, .. } = block {
+            if id.is_none() {
+                let slug = heading_slug(inner);
+                *id = Some(CowStr::from(self.unique_id(slug)));
+            }
+        }
+
+        self.walk_block(block)
+    }
+}
+
+/// Moves `#label` shortcodes in headings to the heading id and collects all explicit ids.
+#[derive(Default)]
+struct ExplicitIds(HashSet<String>);
+
+impl AstVisitor for ExplicitIds {
     fn visit_block(&mut self, block: &mut Block) -> anyhow::Result<()> {
         if let Block::Heading { id, inner, .. } = block {
             if let Some(cmd) = inner.iter_mut().find(|i| match i {
@@ -51,12 +92,90 @@ impl AstVisitor for MdLabels {
                 if let Inline::Command(label) = cmd {
                     *id = label.label.clone();
                 }
-                *cmd = Inline::Text(CowStr::new());
-            } else {
-                *id = Some(CowStr::from(nanoid!()));
+                *cmd = Inline::Text(CowStr::new(), cmd.span().clone());
+            }
+            if let Some(id) = id {
+                self.0.insert(id.to_string());
             }
         }
 
         self.walk_block(block)
     }
 }
+
+/// Slug of the heading text in the style of GitHub: lowercase, spaces replaced by dashes and
+/// punctuation other than dashes and underscores removed.
+fn heading_slug(inner: &[Inline]) -> String {
+    let mut text = String::new();
+    heading_text(inner, &mut text);
+
+    let slug: String = text
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect();
+
+    if slug.is_empty() {
+        "section".to_string()
+    } else {
+        slug
+    }
+}
+
+fn heading_text(inner: &[Inline], text: &mut String) {
+    for inline in inner {
+        match inline {
+            Inline::Text(s, _) | Inline::Code(s, _) => text.push_str(s),
+            Inline::Math(math) => text.push_str(&math.source),
+            Inline::SoftBreak(_) | Inline::HardBreak(_) => text.push(' '),
+            Inline::Styled(inner, _, _)
+            | Inline::Link(_, _, _, inner, _)
+            | Inline::Span(inner, _, _) => heading_text(inner, text),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MdLabels;
+    use crate::preprocessors::AstPreprocessor;
+    use cdoc_parser::ast::{Ast, Block};
+    use cdoc_parser::document::Document;
+
+    #[test]
+    fn heading_ids() {
+        let doc: Document<Ast> = Document::try_from(
+            "# Getting started\n\n## What's *new* in `v2`?\n\n## Getting started\n\n## Setup {#getting-started-1}\n\n## Getting started\n\n## Notes #label|notes\n\n## Notes",
+        )
+        .unwrap();
+        let doc = MdLabels::default().process(doc).unwrap();
+
+        let ids: Vec<String> = doc
+            .content
+            .blocks
+            .iter()
+            .filter_map(|b| match b {
+                Block::Heading { id, .. } => id.as_ref().map(|id| id.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                "getting-started",
+                "whats-new-in-v2",
+                "getting-started-2",
+                "getting-started-1",
+                "getting-started-3",
+                "notes",
+                "notes-1"
+            ],
+            ids
+        );
+    }
+}
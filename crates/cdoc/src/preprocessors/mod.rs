@@ -1,4 +1,5 @@
 use dyn_clone::DynClone;
+use std::collections::HashMap;
 use std::fmt::{Debug, Display};
 use std::path::PathBuf;
 
@@ -11,8 +12,11 @@ use crate::config::Format;
 use crate::parser::ParserSettings;
 use crate::templates::TemplateManager;
 
+pub mod conditionals;
+pub mod include;
 pub mod md_labels;
 pub mod script;
+pub mod variables;
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -30,6 +34,12 @@ pub struct PreprocessorContext<'a> {
     pub project_root: PathBuf,
     pub templates: &'a TemplateManager,
     pub output_format: &'a dyn Format,
+    /// Name of the build profile.
+    pub profile: &'a str,
+    /// Build mode (`draft` or `release`).
+    pub mode: &'a str,
+    /// Variables available to `#var`, i.e. the `custom` configuration with profile overrides.
+    pub variables: &'a HashMap<String, serde_json::Value>,
 }
 
 pub trait AstPreprocessor: Display {
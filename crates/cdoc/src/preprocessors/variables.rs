@@ -0,0 +1,123 @@
+use anyhow::{anyhow, bail};
+use cdoc_parser::ast::visitor::Fold;
+use cdoc_parser::ast::{Command, Inline, Value as ParamValue};
+use cdoc_parser::document::Metadata;
+use linked_hash_map::LinkedHashMap;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Replaces `#var(name)` shortcodes with the value of a variable. Variables are looked up in the
+/// `variables` of the document's front matter first and then in the project variables (the
+/// `custom` section of the configuration with the overrides of the profile applied). Nested values
+/// are accessed with dots, e.g. `#var(course.code)`.
+pub struct Variables<'a> {
+    document: &'a LinkedHashMap<String, Value>,
+    project: &'a HashMap<String, Value>,
+}
+
+impl<'a> Variables<'a> {
+    pub fn new(meta: &'a Metadata, project: &'a HashMap<String, Value>) -> Self {
+        Variables {
+            document: &meta.variables,
+            project,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Value> {
+        let mut parts = name.split('.');
+        let first = parts.next()?;
+        let root = self
+            .document
+            .get(first)
+            .or_else(|| self.project.get(first))?;
+        parts.try_fold(root, |value, part| match value {
+            Value::Array(items) => items.get(part.parse::<usize>().ok()?),
+            value => value.get(part),
+        })
+    }
+
+    fn resolve(&self, cmd: &Command) -> anyhow::Result<String> {
+        let name = cmd
+            .parameters
+            .iter()
+            .find(|p| p.key.is_none() || p.key.as_deref() == Some("name"))
+            .and_then(|p| match &p.value {
+                ParamValue::String(s) => Some(s.trim()),
+                _ => None,
+            })
+            .ok_or_else(|| anyhow!("#var requires a variable name (at {})", cmd.span))?;
+
+        match self.lookup(name) {
+            None | Some(Value::Null) => bail!("undefined variable '{name}' at {}", cmd.span),
+            Some(Value::String(s)) => Ok(s.clone()),
+            Some(value @ (Value::Number(_) | Value::Bool(_))) => Ok(value.to_string()),
+            Some(_) => bail!(
+                "variable '{name}' at {} is not a string, number or boolean",
+                cmd.span
+            ),
+        }
+    }
+}
+
+impl Fold for Variables<'_> {
+    fn fold_inline(&mut self, inline: Inline) -> anyhow::Result<Vec<Inline>> {
+        match inline {
+            Inline::Command(cmd) if cmd.function == "var" => {
+                Ok(vec![Inline::Text(self.resolve(&cmd)?.into(), cmd.span)])
+            }
+            inline => Ok(vec![self.walk_inline(inline)?]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Variables;
+    use cdoc_parser::ast::visitor::Fold;
+    use cdoc_parser::ast::{Ast, Block, Inline};
+    use cdoc_parser::document::Document;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn substitute(input: &str) -> anyhow::Result<Vec<Block>> {
+        let project = HashMap::from([
+            ("semester".to_string(), json!("Spring 2024")),
+            ("course".to_string(), json!({"code": "CS101", "credits": 5})),
+        ]);
+
+        let mut doc: Document<Ast> = Document::try_from(input)?;
+        Variables::new(&doc.meta, &project).fold_ast(&mut doc.content.blocks)?;
+        Ok(doc.content.blocks)
+    }
+
+    #[test]
+    fn substitutes_variables() {
+        let blocks = substitute(
+            "---\ntitle: Intro\nvariables:\n  semester: Fall 2024\n---\n#var(semester), #var(course.code) (#var(course.credits) ECTS)",
+        )
+        .unwrap();
+
+        let Block::Paragraph(inner, _) = &blocks[0] else {
+            panic!("expected a paragraph");
+        };
+        let text: String = inner
+            .iter()
+            .map(|i| match i {
+                Inline::Text(s, _) => s.to_string(),
+                _ => String::new(),
+            })
+            .collect();
+        assert_eq!("Fall 2024, CS101 (5 ECTS)", text);
+    }
+
+    #[test]
+    fn undefined_variables() {
+        let err = substitute("---\ntitle: Intro\n---\nText\n\nSome #var(instructor)")
+            .err()
+            .unwrap();
+        assert_eq!(
+            "undefined variable 'instructor' at line 6, column 6",
+            err.to_string()
+        );
+    }
+}
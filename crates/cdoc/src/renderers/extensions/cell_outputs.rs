@@ -1,8 +1,9 @@
 use crate::renderers::extensions::{RenderExtension, RenderExtensionConfig};
 use crate::renderers::generic::GenericRenderer;
 use crate::renderers::RenderContext;
-use cdoc_parser::ast::visitor::AstVisitor;
-use cdoc_parser::ast::{CodeBlock, Command, Inline, Parameter, Value};
+use cdoc_parser::ast::visitor::Fold;
+use cdoc_parser::ast::{Block, CodeBlock, Command, Inline, Parameter, Value};
+use cdoc_parser::code_ast::types::CodeContent;
 use cdoc_parser::document::{CodeOutput, Image, OutputValue};
 use cdoc_parser::Span;
 use serde::{Deserialize, Serialize};
@@ -26,79 +27,90 @@ pub struct CellVisitor<'a> {
     outputs: &'a HashMap<u64, CodeOutput>,
 }
 
-impl AstVisitor for CellVisitor<'_> {
-    fn visit_vec_inline(&mut self, inlines: &mut Vec<Inline>) -> anyhow::Result<()> {
-        let mut offset = 0;
-        for (i, inline) in inlines.clone().into_iter().enumerate() {
-            if let Inline::CodeBlock(CodeBlock { source, .. }) = inline {
-                if let Some(outputs) = self.outputs.get(&source.hash) {
-                    // println!("got output");
-                    for output in &outputs.values {
-                        match output {
-                            OutputValue::Text(s) => {
-                                let command = Command {
-                                    function: "output_text".into(),
-                                    label: None,
-                                    parameters: vec![Parameter {
-                                        key: Some("value".into()),
-                                        value: Value::String(s.into()),
-                                        span: Default::default(),
-                                    }],
-                                    body: None,
-                                    span: Default::default(),
-                                    global_idx: 0,
-                                };
-
-                                inlines.insert(i + offset + 1, Inline::Command(command));
-                                offset += 1;
-                            }
-                            OutputValue::Image(img) => {
-                                let mut params = Vec::new();
-                                for (key, val) in source.meta.clone() {
-                                    params.push(Parameter {
-                                        key: Some(key),
-                                        value: Value::String(val),
-                                        span: Span::new(0, 0),
-                                    });
-                                }
-
-                                match img {
-                                    Image::Png(png) => params.push(Parameter {
-                                        key: Some("base64".into()),
-                                        value: Value::String(png.into()),
-                                        span: Span::new(0, 0),
-                                    }),
-                                    Image::Svg(svg) => params.push(Parameter {
-                                        key: Some("svg".into()),
-                                        value: Value::String(svg.into()),
-                                        span: Span::new(0, 0),
-                                    }),
-                                }
-
-                                let command = Command {
-                                    function: "figure".into(),
-                                    label: source.meta.get("id").cloned(),
-                                    parameters: params,
-                                    body: None,
-                                    span: Default::default(),
-                                    global_idx: 0,
-                                };
+impl CellVisitor<'_> {
+    /// Creates the shortcodes that display the outputs of a code block.
+    fn output_commands(&self, source: &CodeContent) -> Vec<Command> {
+        let mut commands = Vec::new();
+        if let Some(outputs) = self.outputs.get(&source.hash) {
+            for output in &outputs.values {
+                match output {
+                    OutputValue::Text(s) => {
+                        commands.push(Command {
+                            function: "output_text".into(),
+                            label: None,
+                            parameters: vec![Parameter {
+                                key: Some("value".into()),
+                                value: Value::String(s.into()),
+                                span: Default::default(),
+                            }],
+                            body: None,
+                            span: Default::default(),
+                            global_idx: 0,
+                        });
+                    }
+                    OutputValue::Image(img) => {
+                        let mut params = Vec::new();
+                        for (key, val) in source.meta.clone() {
+                            params.push(Parameter {
+                                key: Some(key),
+                                value: Value::String(val),
+                                span: Span::new(0, 0),
+                            });
+                        }
 
-                                inlines.insert(i + offset + 1, Inline::Command(command));
-                                offset += 1;
-                            }
-                            OutputValue::Json(_) => {}
-                            OutputValue::Html(_) => {}
-                            OutputValue::Javascript(_) => {}
-                            OutputValue::Error(_) => {}
-                            OutputValue::Plain(_) => {}
+                        match img {
+                            Image::Png(png) => params.push(Parameter {
+                                key: Some("base64".into()),
+                                value: Value::String(png.into()),
+                                span: Span::new(0, 0),
+                            }),
+                            Image::Svg(svg) => params.push(Parameter {
+                                key: Some("svg".into()),
+                                value: Value::String(svg.into()),
+                                span: Span::new(0, 0),
+                            }),
                         }
+
+                        commands.push(Command {
+                            function: "figure".into(),
+                            label: source.meta.get("id").cloned(),
+                            parameters: params,
+                            body: None,
+                            span: Default::default(),
+                            global_idx: 0,
+                        });
                     }
+                    OutputValue::Json(_) => {}
+                    OutputValue::Html(_) => {}
+                    OutputValue::Javascript(_) => {}
+                    OutputValue::Error(_) => {}
+                    OutputValue::Plain(_) => {}
                 }
             }
         }
+        commands
+    }
+}
+
+impl Fold for CellVisitor<'_> {
+    fn fold_block(&mut self, block: Block) -> anyhow::Result<Vec<Block>> {
+        let outputs = match &block {
+            Block::CodeBlock(CodeBlock { source, .. }) => self.output_commands(source),
+            _ => vec![],
+        };
+        let mut blocks = vec![self.walk_block(block)?];
+        blocks.extend(outputs.into_iter().map(Block::Command));
+        Ok(blocks)
+    }
 
-        self.walk_vec_inline(inlines)
+    fn fold_inline(&mut self, inline: Inline) -> anyhow::Result<Vec<Inline>> {
+        let outputs = match &inline {
+            Inline::CodeBlock(CodeBlock { source, .. }) => self.output_commands(source),
+            _ => vec![],
+        };
+        let mut inlines = vec![self.walk_inline(inline)?];
+        inlines.extend(outputs.into_iter().map(Inline::Command));
+        Ok(inlines)
     }
 }
 
@@ -117,7 +129,7 @@ impl RenderExtension for CellProcessor {
             let mut visitor = CellVisitor {
                 outputs: &ctx.doc.code_outputs,
             };
-            visitor.walk_ast(&mut ctx.doc.content.blocks)?;
+            visitor.fold_ast(&mut ctx.doc.content.blocks)?;
         }
         Ok(())
     }
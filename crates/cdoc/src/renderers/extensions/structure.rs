@@ -1,8 +1,8 @@
 use crate::renderers::extensions::{RenderExtension, RenderExtensionConfig};
 use crate::renderers::generic::GenericRenderer;
 use crate::renderers::{RenderContext, RenderElement};
-use cdoc_parser::ast::visitor::AstVisitor;
-use cdoc_parser::ast::{Block, Command};
+use cdoc_parser::ast::visitor::{AstVisit, AstVisitor};
+use cdoc_parser::ast::{Block, Command, Inline, Value};
 use cowstr::CowStr;
 use std::cmp::Ordering;
 use std::collections::HashMap;
@@ -13,8 +13,14 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DocStructureConfig {
+    /// Headings below this level are left out of the structure and are not numbered (0 includes
+    /// all headings).
     pub max_heading_level: usize,
     pub included_commands: Vec<String>,
+    /// Number the headings (1, 1.1, 1.1.2, ...). The numbers are added to the rendered headings
+    /// and therefore also appear in the structure tree and the table of contents.
+    #[serde(default)]
+    pub number_headings: bool,
 }
 
 #[typetag::serde(name = "doc_structure")]
@@ -61,6 +67,8 @@ pub struct Elem {
     #[serde(flatten)]
     val: ElemVal,
     lvl: u8,
+    /// Id of the element in the rendered output, i.e. the heading id or the label of a command.
+    /// Headings always have one (see [crate::preprocessors::md_labels]).
     label: Option<CowStr>,
     num: usize,
     chrono_num: usize,
@@ -95,6 +103,147 @@ pub struct Tree {
     children: Vec<Tree>,
 }
 
+impl Tree {
+    fn flatten(&self, depth: usize, entries: &mut Vec<TocEntry>) {
+        if let ElemVal::Heading { value } = &self.elem.val {
+            entries.push(TocEntry {
+                value: value.trim().into(),
+                label: self.elem.label.clone(),
+                depth,
+            });
+        }
+        self.children
+            .iter()
+            .for_each(|child| child.flatten(depth + 1, entries));
+    }
+}
+
+/// A heading in a table of contents.
+#[derive(Debug, Serialize, Clone)]
+pub struct TocEntry {
+    value: CowStr,
+    label: Option<CowStr>,
+    /// Nesting depth in the table of contents (starting at 1).
+    depth: usize,
+}
+
+/// Headings of the document up to the given level, both as a tree and as a flat list in document
+/// order (used by `#toc`).
+pub fn heading_tree(
+    ctx: &RenderContext,
+    renderer: GenericRenderer,
+    max_heading_level: usize,
+) -> anyhow::Result<(Vec<Tree>, Vec<TocEntry>)> {
+    let base = DocStructure {
+        config: DocStructureConfig {
+            max_heading_level,
+            included_commands: vec![],
+            number_headings: false,
+        },
+    };
+    let mut visitor = DocStructureVisitor::new(&base, ctx, renderer);
+    visitor.walk_ast(&ctx.doc.content.blocks)?;
+    let tree = visitor.construct_element_tree()?;
+
+    let mut entries = vec![];
+    tree.iter().for_each(|t| t.flatten(1, &mut entries));
+    Ok((tree, entries))
+}
+
+/// Whether a heading is numbered, i.e. it is within the level limit (0 for no limit) and does not
+/// have the `unnumbered` class.
+fn is_numbered(max_heading_level: usize, lvl: u8, classes: &[CowStr]) -> bool {
+    (max_heading_level == 0 || lvl as usize <= max_heading_level)
+        && !classes.iter().any(|c| c == "unnumbered")
+}
+
+/// Finds the highest level (i.e. the smallest `lvl`) of the numbered headings.
+struct TopLevel {
+    max_heading_level: usize,
+    lvl: Option<u8>,
+}
+
+impl<'a> AstVisit<'a> for TopLevel {
+    fn visit_block(&mut self, block: &'a Block) -> anyhow::Result<()> {
+        if let Block::Heading { lvl, classes, .. } = block {
+            if is_numbered(self.max_heading_level, *lvl, classes) {
+                self.lvl = Some(self.lvl.map_or(*lvl, |top| top.min(*lvl)));
+            }
+        }
+        self.walk_block(block)
+    }
+}
+
+/// Numbers the headings of a document and prepends the numbers to them. Like the structure tree,
+/// this includes the headings in the content parameters of shortcodes.
+struct HeadingNumbers {
+    max_heading_level: usize,
+    /// Level of the highest numbered heading. Levels above it are left out of the numbers, so
+    /// documents that start at level 2 are numbered 1, 2, ...
+    top_level: usize,
+    /// Number of headings seen so far at each level.
+    counters: Vec<usize>,
+}
+
+impl HeadingNumbers {
+    fn new(max_heading_level: usize, blocks: &[Block]) -> anyhow::Result<Self> {
+        let mut top = TopLevel {
+            max_heading_level,
+            lvl: None,
+        };
+        top.walk_ast(blocks)?;
+
+        Ok(HeadingNumbers {
+            max_heading_level,
+            top_level: top.lvl.unwrap_or(1) as usize,
+            counters: vec![],
+        })
+    }
+
+    /// Hierarchical number of the next heading at the given level.
+    fn next_number(&mut self, lvl: u8) -> String {
+        let lvl = lvl as usize;
+        self.counters.resize(lvl.max(self.counters.len()), 0);
+        self.counters[lvl - 1] += 1;
+        self.counters.truncate(lvl);
+
+        self.counters[self.top_level - 1..]
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+}
+
+impl AstVisitor for HeadingNumbers {
+    fn visit_block(&mut self, block: &mut Block) -> anyhow::Result<()> {
+        if let Block::Heading {
+            lvl,
+            classes,
+            inner,
+            span,
+            ..
+        } = block
+        {
+            if is_numbered(self.max_heading_level, *lvl, classes) {
+                let number = self.next_number(*lvl);
+                inner.insert(0, Inline::Text(format!("{number} ").into(), span.clone()));
+            }
+            return Ok(());
+        }
+        self.walk_block(block)
+    }
+
+    fn visit_command(&mut self, cmd: &mut Command) -> anyhow::Result<()> {
+        for param in &mut cmd.parameters {
+            if let Value::Content(blocks) = &mut param.value {
+                self.walk_vec_block(blocks)?;
+            }
+        }
+        self.walk_command(&mut cmd.body)
+    }
+}
+
 impl RenderExtension for DocStructure {
     fn name(&self) -> String {
         "Document structure".to_string()
@@ -105,15 +254,21 @@ impl RenderExtension for DocStructure {
         ctx: &mut RenderContext,
         renderer: GenericRenderer,
     ) -> anyhow::Result<()> {
+        if self.config.number_headings {
+            HeadingNumbers::new(self.config.max_heading_level, &ctx.doc.content.blocks)?
+                .walk_ast(&mut ctx.doc.content.blocks)?;
+        }
+
         let mut visitor = DocStructureVisitor::new(self, ctx, renderer);
-        visitor.walk_ast(&mut ctx.doc.content.blocks.clone())?;
+        visitor.walk_ast(&ctx.doc.content.blocks)?;
 
         let tree = visitor.construct_element_tree()?;
+        let elems = visitor.elems;
 
-        ctx.doc.meta.user_defined.insert(
-            "tree_raw".to_string(),
-            serde_json::to_value(visitor.elems.clone())?,
-        );
+        ctx.doc
+            .meta
+            .user_defined
+            .insert("tree_raw".to_string(), serde_json::to_value(elems)?);
 
         ctx.doc
             .meta
@@ -142,8 +297,11 @@ fn construct_element_tree_inner2(
                 let (children, new_idx) =
                     construct_element_tree_inner2(elems, current_idx, current.lvl);
 
-                let t = tree.last_mut().unwrap();
-                t.children = children;
+                match tree.last_mut() {
+                    Some(t) => t.children = children,
+                    // Deeper headings before the first heading of this level stay at this level.
+                    None => tree.extend(children),
+                }
 
                 current_idx = new_idx;
             }
@@ -170,21 +328,20 @@ fn construct_element_tree_inner2(
 
 impl DocStructureVisitor<'_> {
     pub fn construct_element_tree(&self) -> anyhow::Result<Vec<Tree>> {
-        let lvl = &self.elems.get(0).map(|e| e.lvl).unwrap_or(1);
-        let (tree, _) = construct_element_tree_inner2(&self.elems, 0, *lvl);
+        let lvl = self.elems.iter().map(|e| e.lvl).min().unwrap_or(1);
+        let (tree, _) = construct_element_tree_inner2(&self.elems, 0, lvl);
         Ok(tree)
     }
 }
 
-impl AstVisitor for DocStructureVisitor<'_> {
-    fn visit_block(&mut self, block: &mut Block) -> anyhow::Result<()> {
-        if let Block::Heading {
-            lvl,
-            id,
-            classes: _,
-            inner,
-        } = block
-        {
+impl<'a> AstVisit<'a> for DocStructureVisitor<'_> {
+    fn visit_block(&mut self, block: &'a Block) -> anyhow::Result<()> {
+        if let Block::Heading { lvl, id, inner, .. } = block {
+            let max_level = self.base.config.max_heading_level;
+            if max_level > 0 && *lvl as usize > max_level {
+                return Ok(());
+            }
+
             let inner = self.renderer.render_inner(inner, self.ctx)?;
             let cnum = self.num_counters.entry("heading".to_string()).or_insert(1);
 
@@ -198,18 +355,20 @@ impl AstVisitor for DocStructureVisitor<'_> {
 
             *cnum += 1;
             self.current_level = *lvl + 1;
+            return Ok(());
         }
 
         self.walk_block(block)
     }
 
-    fn visit_command(&mut self, cmd: &mut Command) -> anyhow::Result<()> {
+    fn visit_command(&mut self, cmd: &'a Command) -> anyhow::Result<()> {
         let params = self
             .renderer
             .render_params(cmd.parameters.clone(), self.ctx)?;
+        // Arguments without a parameter name cannot be looked up by templates.
         let params = params
             .into_iter()
-            .map(|p| (p.key.unwrap(), p.value))
+            .filter_map(|p| Some((p.key?, p.value)))
             .collect();
 
         if self
@@ -236,12 +395,83 @@ impl AstVisitor for DocStructureVisitor<'_> {
             *cnum += 1;
 
             self.current_level += 1;
-            self.walk_command(&mut cmd.body)?;
+            self.walk_command(cmd)?;
             self.current_level -= 1;
         } else {
-            self.walk_command(&mut cmd.body)?;
+            self.walk_command(cmd)?;
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::HeadingNumbers;
+    use cdoc_parser::ast::visitor::{AstVisit, AstVisitor};
+    use cdoc_parser::ast::{Ast, Block, Inline};
+    use cdoc_parser::document::Document;
+
+    /// Heading texts after numbering, including the headings in shortcode parameters.
+    fn numbered(input: &str, max_heading_level: usize) -> Vec<String> {
+        struct Headings(Vec<String>);
+
+        impl<'a> AstVisit<'a> for Headings {
+            fn visit_block(&mut self, block: &'a Block) -> anyhow::Result<()> {
+                if let Block::Heading { inner, .. } = block {
+                    self.0.push(
+                        inner
+                            .iter()
+                            .filter_map(|i| match i {
+                                Inline::Text(s, _) => Some(s.to_string()),
+                                _ => None,
+                            })
+                            .collect(),
+                    );
+                }
+                self.walk_block(block)
+            }
+        }
+
+        let mut doc: Document<Ast> = Document::try_from(input).unwrap();
+        let blocks = &mut doc.content.blocks;
+        let mut numbers = HeadingNumbers::new(max_heading_level, blocks).unwrap();
+        numbers.walk_ast(blocks).unwrap();
+
+        let mut headings = Headings(vec![]);
+        headings.walk_ast(blocks).unwrap();
+        headings.0
+    }
+
+    #[test]
+    fn heading_numbers() {
+        assert_eq!(
+            vec!["1 A", "1.1 B", "1.1.1 C", "1.2 D", "2 E", "2.0.1 F"],
+            numbered("# A\n\n## B\n\n### C\n\n## D\n\n# E\n\n### F", 0)
+        );
+        assert_eq!(
+            vec!["1 A", "2 B", "2.1 C"],
+            numbered("## A\n\n## B\n\n### C", 0)
+        );
+        assert_eq!(
+            vec!["0.1 A", "1 B", "1.1 C"],
+            numbered("## A\n\n# B\n\n## C", 0)
+        );
+    }
+
+    #[test]
+    fn heading_number_limits() {
+        assert_eq!(
+            vec!["1 A", "1.1 B", "C", "D", "1.2 E"],
+            numbered("# A\n\n## B\n\n### C\n\n## D {.unnumbered}\n\n## E", 2)
+        );
+    }
+
+    #[test]
+    fn headings_in_parameters() {
+        assert_eq!(
+            vec!["1 A", "2 B", "3 C"],
+            numbered("# A\n\n#message(title={\n# B\n})\n\n# C", 0)
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use cdoc_parser::ast::visitor::AstVisitor;
+use cdoc_parser::ast::{Block, Inline};
+
+use linked_hash_map::LinkedHashMap;
+use std::collections::HashMap;
+
+/// A footnote collected from a document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Footnote {
+    /// Document-wide number, assigned in order of first reference.
+    pub num: usize,
+    /// Contents of the footnote definition (empty if the footnote is never defined).
+    pub content: Vec<Block>,
+}
+
+/// Collects footnote references and definitions. Footnotes are numbered by the order in which
+/// they are first referenced. Definitions that are never referenced are numbered last.
+#[derive(Default)]
+pub struct FootnoteVisitor {
+    order: Vec<String>,
+    definitions: HashMap<String, Vec<Block>>,
+}
+
+impl FootnoteVisitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_footnotes(self) -> LinkedHashMap<String, Footnote> {
+        let mut definitions = self.definitions;
+        let mut footnotes = LinkedHashMap::new();
+
+        for label in self.order {
+            let content = definitions.remove(&label).unwrap_or_default();
+            let num = footnotes.len() + 1;
+            footnotes.insert(label, Footnote { num, content });
+        }
+
+        let mut unreferenced: Vec<(String, Vec<Block>)> = definitions.into_iter().collect();
+        unreferenced.sort_by(|a, b| a.0.cmp(&b.0));
+        for (label, content) in unreferenced {
+            let num = footnotes.len() + 1;
+            footnotes.insert(label, Footnote { num, content });
+        }
+
+        footnotes
+    }
+}
+
+impl AstVisitor for FootnoteVisitor {
+    fn visit_block(&mut self, block: &mut Block) -> anyhow::Result<()> {
+        if let Block::FootnoteDefinition(label, content, _) = block {
+            self.walk_vec_block(content)?;
+            self.definitions.insert(label.to_string(), content.clone());
+            Ok(())
+        } else {
+            self.walk_block(block)
+        }
+    }
+
+    fn visit_inline(&mut self, inline: &mut Inline) -> anyhow::Result<()> {
+        if let Inline::FootnoteReference(label, _) = inline {
+            if !self.order.iter().any(|l| l == label.as_str()) {
+                self.order.push(label.to_string());
+            }
+        }
+        self.walk_inline(inline)
+    }
+}
@@ -3,16 +3,22 @@ use std::collections::HashMap;
 use anyhow::{anyhow, Context as AhContext, Result};
 use serde::{Deserialize, Serialize};
 
-use cdoc_parser::ast::{Block, CodeBlock, Command, Inline, Math, Parameter, Style, Value};
+use cdoc_parser::ast::{
+    Block, CodeBlock, Command, Inline, Math, Parameter, RawContent, Style, TableRow, Value,
+};
 use cdoc_parser::document::{CodeOutput, Document, Image, OutputValue};
 use cowstr::CowStr;
+use pulldown_cmark::Alignment;
 use std::io::{Cursor, Write};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use tera::Context;
 
+use crate::bibliography::BibEntry;
+use crate::glossary::{term_anchor, term_params, term_slug};
+use crate::renderers::extensions::structure::heading_tree;
 use crate::renderers::extensions::RenderExtension;
 use crate::renderers::{
-    DocumentRenderer, RenderContext, RenderElement, RenderResult, RenderedParam,
+    DocumentRenderer, ProjectReference, RenderContext, RenderElement, RenderResult, RenderedParam,
 };
 use crate::templates::{TemplateDefinition, TemplateType};
 
@@ -24,11 +30,33 @@ fn write_bytes(source: &str, mut buf: impl Write) -> Result<()> {
         .ok_or(anyhow!("did not write correct number of bytes"))
 }
 
+#[derive(Serialize)]
+struct RenderedFootnote {
+    label: String,
+    num: usize,
+    value: CowStr,
+}
+
 #[derive(Default, Serialize, Deserialize, Debug, Clone)]
 pub struct GenericRenderer {
     list_level: usize,
     current_list_idx: Vec<Option<u64>>,
-    counters: HashMap<CowStr, usize>,
+    /// Number of uses of each glossary term so far (by term slug), used for the anchors of the
+    /// uses.
+    term_uses: HashMap<String, usize>,
+    /// Citation keys in the order they are first cited (which determines their numbers).
+    #[serde(skip)]
+    cited: Vec<String>,
+    /// Number of cited entries that have already been included in a reference list.
+    #[serde(skip)]
+    listed: usize,
+}
+
+#[derive(Serialize)]
+struct CitedEntry<'a> {
+    num: usize,
+    #[serde(flatten)]
+    entry: &'a BibEntry,
 }
 
 #[typetag::serde(name = "generic")]
@@ -45,6 +73,8 @@ impl DocumentRenderer for GenericRenderer {
         let buf = Vec::new();
         let mut cursor = Cursor::new(buf);
         self.render(&ctx.doc.content.blocks, ctx, &mut cursor)?;
+        self.render_bibliography(ctx, &mut cursor)?;
+        self.render_footnotes(ctx, &mut cursor)?;
 
         let content = String::from_utf8(cursor.get_ref().clone())?.into();
         Ok(Document {
@@ -91,8 +121,8 @@ impl GenericRenderer {
         args.insert("label", label);
         args.insert("display_mode", &display_mode);
         if display_mode && label.is_some() {
-            let num = self.fetch_and_inc_num("equation".into(), label);
-            args.insert("num", &num);
+            let num = label_num(ctx, label);
+            insert_num(&mut args, "equation", num, ctx);
         }
         args.insert("value", inner);
         ctx.templates.render(
@@ -109,19 +139,29 @@ impl GenericRenderer {
         command: &Command,
         buf: impl Write,
     ) -> Result<()> {
+        match command.function.as_str() {
+            "ref" => return self.render_reference(ctx, command, buf),
+            "cite" => return self.render_citation(ctx, command, buf),
+            "bibliography" => return self.render_bibliography(ctx, buf),
+            "term" => return self.render_term(ctx, command, buf),
+            "toc" => return self.render_toc(ctx, command, buf),
+            _ => {}
+        }
+
         let mut args = ctx.extra_args.clone();
         // args.insert("defs", &ctx.templates.definitions);
         // args.insert("refs", &ctx.references);
         // println!("{:?}", &ctx.references);
         // args.insert("refs_by_type", &ctx.references_by_type);
 
-        let num = self.fetch_and_inc_num(command.function.clone(), &command.label);
+        let num = label_num(ctx, &command.label);
+        insert_num(&mut args, &command.function, num, ctx);
         let rendered = self
             .render_params(command.parameters.clone(), ctx)
             .with_context(|| {
                 format!(
-                    "error rendering shortcode {} at position {} and global index {}",
-                    command.function, command.span.range.start, command.global_idx
+                    "error rendering shortcode {} at {} (global index {})",
+                    command.function, command.span, command.global_idx
                 )
             })?;
         let tdef = ctx
@@ -135,20 +175,19 @@ impl GenericRenderer {
                         .get_with_margin(ctx.doc.content.source.as_str(), 100)
                 )
             })?;
-        let r: Result<Vec<()>> = ctx
+        let values: Result<Vec<serde_json::Value>> = ctx
             .templates
             .validate_args_for_template(&command.function, &rendered)
             .with_context(|| {
                 format!(
-                    "validation error at position {} {}",
-                    command.span.range.start, command.global_idx
+                    "validation error for shortcode {} at {}",
+                    command.function, command.span
                 )
             })?
             .into_iter()
             .collect();
-        r?;
 
-        add_args(&tdef, &mut args, &command.label, num, rendered)?;
+        add_args(&tdef, &mut args, &command.label, rendered, values?)?;
         let body = command
             .body
             .as_ref()
@@ -165,26 +204,328 @@ impl GenericRenderer {
         )
     }
 
-    fn fetch_and_inc_num(&mut self, typ: CowStr, label: &Option<CowStr>) -> usize {
-        let num = if label.is_some() {
-            let num = self.counters.entry(typ).or_insert(0);
-            *num += 1;
-            *num
-        } else {
-            0
+    /// Renders `#ref(lbl)` or `#ref(doc=..., lbl=...)` as the number of the referenced element
+    /// with a link to it. References to other documents are resolved with the project-wide
+    /// reference index.
+    fn render_reference(
+        &mut self,
+        ctx: &RenderContext,
+        command: &Command,
+        buf: impl Write,
+    ) -> Result<()> {
+        // `id` is the parameter name of the former `ref` shortcode templates.
+        let label = string_param(command, "lbl", true)
+            .or_else(|| string_param(command, "id", false))
+            .ok_or_else(|| anyhow!("#ref requires a label (at {})", command.span))?;
+        let doc = string_param(command, "doc", false);
+
+        let (reference, href) = match ctx.project.references {
+            Some(refs) => refs
+                .index
+                .resolve(refs.current, doc, label)
+                .map(|r| (r.clone(), refs.index.href(refs.current, r))),
+            None if doc.is_some() => {
+                Err(anyhow!("references to other documents are not available"))
+            }
+            None => ctx
+                .references
+                .get(label)
+                .map(|r| {
+                    let reference = ProjectReference {
+                        doc: String::new(),
+                        path: String::new(),
+                        obj_type: r.obj_type.clone(),
+                        num: r.num,
+                        number: ctx.project.format_num(&r.obj_type, r.num),
+                        anchor: label.to_string(),
+                    };
+                    (reference, format!("#{label}"))
+                })
+                .ok_or_else(|| anyhow!("unknown label '{label}'")),
+        }
+        .with_context(|| format!("invalid reference at {}", command.span))?;
+
+        let mut args = Context::default();
+        args.insert("label", label);
+        args.insert("num", &reference.num);
+        args.insert("number", &reference.number);
+        args.insert("obj_type", &reference.obj_type);
+        args.insert("href", &href);
+        args.insert("local", &href.starts_with('#'));
+        ctx.templates.render(
+            "reference",
+            ctx.format.template_prefix(),
+            TemplateType::Builtin,
+            &args,
+            buf,
+        )
+    }
+
+    fn render_code_block(
+        &mut self,
+        block: &CodeBlock,
+        ctx: &RenderContext,
+        buf: impl Write,
+    ) -> Result<()> {
+        let CodeBlock {
+            label,
+            source,
+            attributes,
+            ..
+        } = block;
+
+        let id = get_id();
+
+        let code_rendered = source.to_string(
+            ctx.doc
+                .meta
+                .code_solutions
+                .unwrap_or(ctx.parser_settings.solutions),
+        )?;
+
+        // let highlighted = syntect::html::highlighted_html_for_string(
+        //     &code_rendered,
+        //     ctx.syntax_set,
+        //     ctx.syntax_set.find_syntax_by_extension("py").unwrap(),
+        //     ctx.theme,
+        // )?;
+        let highlighted = &code_rendered.trim();
+
+        let num = label_num(ctx, label);
+
+        let mut args = Context::default();
+        args.insert("label", label);
+        args.insert("interactive", &ctx.doc.meta.interactive);
+        args.insert("cell_outputs", &ctx.doc.meta.cell_outputs);
+        args.insert("editable", &ctx.doc.meta.editable);
+        args.insert("source", &code_rendered);
+        args.insert("highlighted", &highlighted);
+        args.insert("id", &id);
+        args.insert("attr", &attributes);
+        args.insert("meta", &source.meta);
+        insert_num(&mut args, "code", num, ctx);
+        // args.insert("outputs", &self.render_inner(outputs, ctx)?);
+        // args.insert("outputs", outputs);
+
+        ctx.templates.render(
+            "cell",
+            ctx.format.template_prefix(),
+            TemplateType::Builtin,
+            &args,
+            buf,
+        )
+    }
+
+    fn render_table_row(
+        &mut self,
+        row: &TableRow,
+        header: bool,
+        alignments: &[&str],
+        ctx: &RenderContext,
+        buf: impl Write,
+    ) -> Result<()> {
+        let mut cells = Vec::new();
+        for (idx, cell) in row.iter().enumerate() {
+            let mut args = Context::default();
+            args.insert("idx", &idx);
+            args.insert("header", &header);
+            args.insert("alignment", alignments.get(idx).unwrap_or(&"none"));
+            args.insert("value", &self.render_inner(cell, ctx)?);
+            ctx.templates.render(
+                "table_cell",
+                ctx.format.template_prefix(),
+                TemplateType::Builtin,
+                &args,
+                &mut cells,
+            )?;
+        }
+
+        let mut args = Context::default();
+        args.insert("header", &header);
+        args.insert("value", &String::from_utf8(cells)?);
+        ctx.templates.render(
+            "table_row",
+            ctx.format.template_prefix(),
+            TemplateType::Builtin,
+            &args,
+            buf,
+        )
+    }
+
+    /// Renders the list of footnotes collected from the document. Nothing is written if the
+    /// document has no footnotes.
+    pub(crate) fn render_footnotes(&mut self, ctx: &RenderContext, buf: impl Write) -> Result<()> {
+        if ctx.footnotes.is_empty() {
+            return Ok(());
+        }
+
+        let footnotes = ctx
+            .footnotes
+            .iter()
+            .map(|(label, footnote)| {
+                Ok(RenderedFootnote {
+                    label: label.clone(),
+                    num: footnote.num,
+                    value: self.render_inner(&footnote.content, ctx)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut args = Context::default();
+        args.insert("footnotes", &footnotes);
+        ctx.templates.render(
+            "footnotes",
+            ctx.format.template_prefix(),
+            TemplateType::Builtin,
+            &args,
+            buf,
+        )
+    }
+
+    /// Renders the entries cited since the last reference list. A list is added at the end of
+    /// each document and `#bibliography` can be used to add lists in between, e.g. at the end of
+    /// each section.
+    pub(crate) fn render_bibliography(
+        &mut self,
+        ctx: &RenderContext,
+        buf: impl Write,
+    ) -> Result<()> {
+        if self.listed == self.cited.len() {
+            return Ok(());
+        }
+
+        let entries = self.cited[self.listed..]
+            .iter()
+            .zip(self.listed + 1..)
+            .filter_map(|(key, num)| {
+                let entry = ctx.project.bibliography?.get(key)?;
+                Some(CitedEntry { num, entry })
+            })
+            .collect::<Vec<_>>();
+        self.listed = self.cited.len();
+
+        let mut args = Context::default();
+        args.insert("entries", &entries);
+        ctx.templates.render(
+            "bibliography",
+            ctx.format.template_prefix(),
+            TemplateType::Builtin,
+            &args,
+            buf,
+        )
+    }
+
+    /// Renders `#cite(key)` or `#cite(key, page=12)`. Entries are numbered in the order they are
+    /// first cited in the document.
+    fn render_citation(
+        &mut self,
+        ctx: &RenderContext,
+        command: &Command,
+        buf: impl Write,
+    ) -> Result<()> {
+        let key = string_param(command, "key", true)
+            .ok_or_else(|| anyhow!("#cite requires a citation key (at {})", command.span))?;
+        let entry = ctx
+            .project
+            .bibliography
+            .and_then(|b| b.get(key))
+            .ok_or_else(|| anyhow!("unknown citation key '{key}' at {}", command.span))?;
+
+        let num = match self.cited.iter().position(|k| k == key) {
+            Some(idx) => idx + 1,
+            None => {
+                self.cited.push(key.to_string());
+                self.cited.len()
+            }
+        };
+
+        let mut args = Context::default();
+        args.insert("key", key);
+        args.insert("num", &num);
+        args.insert("page", &string_param(command, "page", false));
+        args.insert("entry", entry);
+        ctx.templates.render(
+            "citation",
+            ctx.format.template_prefix(),
+            TemplateType::Builtin,
+            &args,
+            buf,
+        )
+    }
+
+    /// Renders `#toc` or `#toc(depth=2)` as a table of contents with the document's headings up
+    /// to the given level (3 by default).
+    fn render_toc(
+        &mut self,
+        ctx: &RenderContext,
+        command: &Command,
+        buf: impl Write,
+    ) -> Result<()> {
+        let depth = match string_param(command, "depth", true) {
+            Some(depth) => depth
+                .parse()
+                .map_err(|_| anyhow!("invalid depth '{depth}' at {}", command.span))?,
+            None => 3,
         };
-        num
+        let (tree, entries) = heading_tree(ctx, self.clone(), depth)?;
+
+        let mut args = Context::default();
+        args.insert("tree", &tree);
+        args.insert("entries", &entries);
+        ctx.templates.render(
+            "toc",
+            ctx.format.template_prefix(),
+            TemplateType::Builtin,
+            &args,
+            buf,
+        )
+    }
+
+    /// Renders `#term(name)` or `#term(name, text=...)`. Each use gets an anchor so that the term
+    /// index can link to it, and defined terms link to their entry on the glossary page.
+    fn render_term(
+        &mut self,
+        ctx: &RenderContext,
+        command: &Command,
+        buf: impl Write,
+    ) -> Result<()> {
+        let (term, text) = term_params(command)
+            .ok_or_else(|| anyhow!("#term requires a term (at {})", command.span))?;
+        let defined = ctx.project.glossary.and_then(|g| g.get(term));
+
+        let num = self.term_uses.entry(term_slug(term)).or_insert(0);
+        *num += 1;
+        let anchor = term_anchor(term, *num);
+
+        let glossary_page = format!("glossary.{}#{}", ctx.format.extension(), term_slug(term));
+        let href = defined
+            .and(ctx.project.references)
+            .and_then(|refs| refs.index.link(refs.current, &glossary_page));
+
+        let mut args = Context::default();
+        args.insert("term", defined.map_or(term, |(name, _)| name));
+        args.insert("text", text);
+        args.insert("definition", &defined.map(|(_, definition)| definition));
+        args.insert("anchor", &anchor);
+        args.insert("href", &href);
+        ctx.templates.render(
+            "term",
+            ctx.format.template_prefix(),
+            TemplateType::Builtin,
+            &args,
+            buf,
+        )
     }
 }
 
 impl RenderElement<Inline> for GenericRenderer {
     fn render(&mut self, elem: &Inline, ctx: &RenderContext, mut buf: impl Write) -> Result<()> {
         match elem {
-            Inline::Text(s) => {
+            Inline::Text(s, _) => {
                 let _ = buf.write(s.as_bytes())?;
                 Ok(())
             }
-            Inline::Styled(inner, style) => match style {
+            Inline::Styled(inner, style, _) => match style {
                 Style::Emphasis => render_value_template(
                     "emphasis",
                     TemplateType::Builtin,
@@ -214,74 +555,46 @@ impl RenderElement<Inline> for GenericRenderer {
                     buf,
                 ),
             },
-            Inline::CodeBlock(CodeBlock {
-                label,
-                source,
-                attributes,
-                ..
-            }) => {
-                let id = get_id();
-
-                let code_rendered = source.to_string(
-                    ctx.doc
-                        .meta
-                        .code_solutions
-                        .unwrap_or(ctx.parser_settings.solutions),
-                )?;
-
-                // let highlighted = syntect::html::highlighted_html_for_string(
-                //     &code_rendered,
-                //     ctx.syntax_set,
-                //     ctx.syntax_set.find_syntax_by_extension("py").unwrap(),
-                //     ctx.theme,
-                // )?;
-                let highlighted = &code_rendered.trim();
-
-                let num = self.fetch_and_inc_num("code".into(), label);
-
-                let mut args = Context::default();
-                args.insert("label", label);
-                args.insert("interactive", &ctx.doc.meta.interactive);
-                args.insert("cell_outputs", &ctx.doc.meta.cell_outputs);
-                args.insert("editable", &ctx.doc.meta.editable);
-                args.insert("source", &code_rendered);
-                args.insert("highlighted", &highlighted);
-                args.insert("id", &id);
-                args.insert("attr", &attributes);
-                args.insert("meta", &source.meta);
-                args.insert("num", &num);
-                // args.insert("outputs", &self.render_inner(outputs, ctx)?);
-                // args.insert("outputs", outputs);
-
-                Ok(ctx.templates.render(
-                    "cell",
-                    ctx.format.template_prefix(),
-                    TemplateType::Builtin,
-                    &args,
-                    buf,
-                )?)
-            }
-            Inline::Code(s) => {
+            Inline::CodeBlock(block) => self.render_code_block(block, ctx, buf),
+            Inline::Code(s, _) => {
                 render_value_template("inline_code", TemplateType::Builtin, s, ctx, buf)
             }
-            Inline::SoftBreak => {
+            Inline::SoftBreak(_) => {
                 render_basic_template("soft_break", TemplateType::Builtin, ctx, buf)
             }
-            Inline::HardBreak => {
+            Inline::HardBreak(_) => {
                 render_basic_template("hard_break", TemplateType::Builtin, ctx, buf)
             }
-            Inline::Rule => {
+            Inline::Rule(_) => {
                 render_basic_template("horizontal_rule", TemplateType::Builtin, ctx, buf)
             }
-            Inline::Image(_tp, url, alt, inner) => {
+            Inline::Image(_tp, url, alt, inner, _) => {
                 let inner = self.render_inner(inner, ctx)?;
                 render_image(url, alt, &inner, ctx, buf)
             }
-            Inline::Link(_tp, url, alt, inner) => {
+            Inline::Link(_tp, url, alt, inner, _) => {
                 let inner = self.render_inner(inner, ctx)?;
                 render_link(url, alt, &inner, ctx, buf)
             }
-            Inline::Html(s) => write_bytes(s, buf),
+            Inline::Html(s, _) => write_bytes(s, buf),
+            Inline::Raw(raw) => render_raw(raw, ctx, buf),
+            Inline::Span(inner, attributes, _) => {
+                let mut values = attributes.values.clone();
+                let id = values.remove("id");
+
+                let mut args = Context::default();
+                args.insert("value", &self.render_inner(inner, ctx)?);
+                args.insert("id", &id);
+                args.insert("classes", &attributes.classes);
+                args.insert("attributes", &values);
+                ctx.templates.render(
+                    "span",
+                    ctx.format.template_prefix(),
+                    TemplateType::Builtin,
+                    &args,
+                    buf,
+                )
+            }
             Inline::Math(Math {
                 source,
                 display_block,
@@ -289,6 +602,24 @@ impl RenderElement<Inline> for GenericRenderer {
                 ..
             }) => self.render_math(*display_block, source, label, ctx, buf),
             Inline::Command(command) => self.render_command_template(ctx, command, buf),
+            Inline::FootnoteReference(label, span) => {
+                let footnote = ctx
+                    .footnotes
+                    .get(label.as_str())
+                    .ok_or_else(|| anyhow!("undefined footnote '{}' at {}", label, span))?;
+
+                let mut args = Context::default();
+                args.insert("label", label);
+                args.insert("num", &footnote.num);
+                args.insert("value", &self.render_inner(&footnote.content, ctx)?);
+                ctx.templates.render(
+                    "footnote_reference",
+                    ctx.format.template_prefix(),
+                    TemplateType::Builtin,
+                    &args,
+                    buf,
+                )
+            }
         }
     }
 }
@@ -377,12 +708,15 @@ impl RenderElement<Block> for GenericRenderer {
                 lvl,
                 inner,
                 id: label,
+                classes,
+                attributes,
                 ..
             } => {
                 let mut args = Context::default();
-                // println!("{}", );
                 args.insert("level", &lvl);
                 args.insert("label", &label);
+                args.insert("classes", &classes);
+                args.insert("attributes", &attributes);
                 args.insert("inner", &self.render_inner(inner, ctx)?);
                 Ok(ctx.templates.render(
                     "header",
@@ -392,16 +726,35 @@ impl RenderElement<Block> for GenericRenderer {
                     buf,
                 )?)
             }
-            Block::Plain(inner) => self.render(inner, ctx, buf),
-            Block::Paragraph(inner) | Block::BlockQuote(inner) => render_value_template(
+            Block::Plain(inner, _) => self.render(inner, ctx, buf),
+            Block::Paragraph(inner, _) => render_value_template(
                 "paragraph",
                 TemplateType::Builtin,
                 &self.render_inner(inner, ctx)?,
                 ctx,
                 buf,
             ),
+            Block::BlockQuote(inner, _) => render_value_template(
+                "blockquote",
+                TemplateType::Builtin,
+                &self.render_inner(inner, ctx)?,
+                ctx,
+                buf,
+            ),
+            Block::Admonition(kind, inner, _) => {
+                let mut args = Context::default();
+                args.insert("kind", kind.as_str());
+                args.insert("value", &self.render_inner(inner, ctx)?);
+                ctx.templates.render(
+                    "admonition",
+                    ctx.format.template_prefix(),
+                    TemplateType::Builtin,
+                    &args,
+                    buf,
+                )
+            }
 
-            Block::List(idx, items) => {
+            Block::List(idx, items, _) => {
                 self.list_level += 1;
                 self.current_list_idx.push(*idx);
                 let inner = self.render_inner(items, ctx)?;
@@ -434,7 +787,42 @@ impl RenderElement<Block> for GenericRenderer {
                 self.current_list_idx.pop();
                 Ok(())
             }
-            Block::ListItem(inner) => {
+            Block::Table {
+                alignments,
+                header,
+                rows,
+                ..
+            } => {
+                let alignments: Vec<&str> = alignments.iter().map(alignment_name).collect();
+
+                let mut header_buf = Vec::new();
+                self.render_table_row(header, true, &alignments, ctx, &mut header_buf)?;
+                let mut body_buf = Vec::new();
+                for row in rows {
+                    self.render_table_row(row, false, &alignments, ctx, &mut body_buf)?;
+                }
+
+                let mut args = Context::default();
+                args.insert("alignments", &alignments);
+                args.insert("header", &String::from_utf8(header_buf)?);
+                args.insert("body", &String::from_utf8(body_buf)?);
+                ctx.templates.render(
+                    "table",
+                    ctx.format.template_prefix(),
+                    TemplateType::Builtin,
+                    &args,
+                    buf,
+                )
+            }
+            Block::CodeBlock(block) => self.render_code_block(block, ctx, buf),
+            Block::Math(Math { source, label, .. }) => {
+                self.render_math(true, source, label, ctx, buf)
+            }
+            Block::Raw(raw) => render_raw(raw, ctx, buf),
+            Block::Command(command) => self.render_command_template(ctx, command, buf),
+            // Definitions are rendered together at the end of the document.
+            Block::FootnoteDefinition(..) => Ok(()),
+            Block::ListItem(inner, _) => {
                 let mut args = Context::default();
                 args.insert("lvl", &self.list_level);
                 args.insert("idx", &self.current_list_idx.last().unwrap());
@@ -454,6 +842,17 @@ impl RenderElement<Block> for GenericRenderer {
     }
 }
 
+/// Writes raw content as is if it targets the current format and drops it otherwise.
+fn render_raw(raw: &RawContent, ctx: &RenderContext, mut buf: impl Write) -> Result<()> {
+    if ctx.format.accepts_raw(&raw.format) {
+        write_bytes(&raw.source, &mut buf)?;
+        if raw.display_block {
+            write_bytes("\n", buf)?;
+        }
+    }
+    Ok(())
+}
+
 fn render_basic_template(
     name: &str,
     type_: TemplateType,
@@ -469,6 +868,35 @@ fn render_basic_template(
     )
 }
 
+/// Parameter names of the shortcodes that are rendered without a template (see
+/// [GenericRenderer::render_command_template]), in the order of their positional arguments.
+pub(crate) fn builtin_parameters(function: &str) -> Option<&'static [&'static str]> {
+    match function {
+        "ref" => Some(&["lbl", "doc"]),
+        "cite" => Some(&["key", "page"]),
+        "bibliography" => Some(&[]),
+        "term" => Some(&["term", "text"]),
+        "toc" => Some(&["depth"]),
+        _ => None,
+    }
+}
+
+/// Finds a string parameter by name (or the first positional parameter if `positional` is set).
+pub(crate) fn string_param<'c>(
+    command: &'c Command,
+    name: &str,
+    positional: bool,
+) -> Option<&'c str> {
+    command
+        .parameters
+        .iter()
+        .find_map(|p| match (&p.key, &p.value) {
+            (Some(k), Value::String(s)) if k == name => Some(s.trim()),
+            (None, Value::String(s)) if positional => Some(s.trim()),
+            _ => None,
+        })
+}
+
 fn render_value_template(
     name: &str,
     type_: TemplateType,
@@ -482,6 +910,15 @@ fn render_value_template(
         .render(name, ctx.format.template_prefix(), type_, &args, buf)
 }
 
+fn alignment_name(alignment: &Alignment) -> &'static str {
+    match alignment {
+        Alignment::None => "none",
+        Alignment::Left => "left",
+        Alignment::Center => "center",
+        Alignment::Right => "right",
+    }
+}
+
 static COUNTER: AtomicUsize = AtomicUsize::new(1);
 
 fn get_id() -> usize {
@@ -492,26 +929,45 @@ fn add_args(
     def: &TemplateDefinition,
     args: &mut Context,
     id: &Option<CowStr>,
-    num: usize,
     arguments: Vec<RenderedParam>,
+    values: Vec<serde_json::Value>,
 ) -> Result<()> {
     if let Some(id) = id {
         args.insert("id", &id);
     }
-    args.insert("num", &num);
 
-    for (i, p) in arguments.into_iter().enumerate() {
+    for (i, (p, value)) in arguments.into_iter().zip(values).enumerate() {
         let key = if let Some(key) = p.key {
             key
         } else {
             def.shortcode.as_ref().unwrap().parameters[i].name.clone()
         };
 
-        args.insert(key.as_str(), &p.value);
+        args.insert(key.as_str(), &value);
     }
     Ok(())
 }
 
+/// Number of a labelled element within the document (`0` for elements without a label). The
+/// numbers are assigned when the references of the document are collected, so that they match the
+/// numbers used for references and for the numbering across the project.
+fn label_num(ctx: &RenderContext, label: &Option<CowStr>) -> usize {
+    label
+        .as_ref()
+        .and_then(|label| ctx.references.get(label.as_str()))
+        .map_or(0, |reference| reference.num)
+}
+
+/// Adds the number of an element: `num` is its number within the document (`0` without a label)
+/// and `number` the number as it is rendered (see the `numbering` configuration), which is only
+/// set for labelled elements.
+fn insert_num(args: &mut Context, obj_type: &str, num: usize, ctx: &RenderContext) {
+    args.insert("num", &num);
+    if num > 0 {
+        args.insert("number", &ctx.project.format_num(obj_type, num));
+    }
+}
+
 fn render_image(
     url: &str,
     alt: &str,
@@ -551,3 +1007,200 @@ fn render_link(
         buf,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::bibliography::{parse_bibtex, Bibliography};
+    use crate::config::{Format, HtmlFormat};
+    use crate::glossary::Glossary;
+    use crate::numbering::{Numbering, NumberingScope, ProjectNumbering};
+    use crate::parser::ParserSettings;
+    use crate::renderers::extensions::structure::DocStructureConfig;
+    use crate::renderers::extensions::{RenderExtension, RenderExtensionConfig};
+    use crate::renderers::{collect_references, label_counts, ProjectContext, RenderContext};
+    use crate::templates::TemplateManager;
+    use cdoc_parser::ast::Ast;
+    use cdoc_parser::document::Document;
+    use linked_hash_map::LinkedHashMap;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use tera::Context;
+
+    fn global_numbering() -> Numbering {
+        Numbering {
+            scope: NumberingScope::Global,
+            reset_level: 1,
+            prefix: false,
+        }
+    }
+
+    fn render_html(input: &str) -> String {
+        try_render_html(input).unwrap()
+    }
+
+    fn try_render_html(input: &str) -> anyhow::Result<String> {
+        render_html_with(input, Default::default(), vec![])
+    }
+
+    /// Renders with the document structure extension of the default html profile.
+    fn render_html_with_structure(input: &str, project: ProjectContext) -> String {
+        let structure = DocStructureConfig {
+            max_heading_level: 0,
+            included_commands: vec![],
+            number_headings: false,
+        };
+        render_html_with(input, project, vec![structure.build().unwrap()]).unwrap()
+    }
+
+    fn render_html_with(
+        input: &str,
+        project: ProjectContext,
+        extensions: Vec<Box<dyn RenderExtension>>,
+    ) -> anyhow::Result<String> {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/config/templates");
+        let templates = TemplateManager::from_path(root.clone(), root, false)?;
+        let format = HtmlFormat {};
+        let meta = Default::default();
+        let mut args = Context::default();
+        args.insert("config", &serde_json::json!({ "url_prefix": "" }));
+
+        let mut doc: Document<Ast> = Document::try_from(input)?;
+        let mut ctx = RenderContext::new(
+            &mut doc,
+            &templates,
+            args,
+            &meta,
+            &format,
+            ParserSettings::default(),
+            project,
+        )?;
+        Ok(format
+            .renderer()
+            .render_doc(&mut ctx, extensions)?
+            .content
+            .to_string())
+    }
+
+    /// Built-in shortcodes with positional arguments, rendered with the document structure
+    /// extension (which looks up their arguments by name).
+    #[test]
+    fn positional_arguments_with_structure() {
+        let mut bibliography = Bibliography::default();
+        bibliography.extend(
+            parse_bibtex("@book{knuth1984, author={Knuth, Donald}, title={Literate Programming}}")
+                .unwrap(),
+        );
+        let glossary = Glossary::new(LinkedHashMap::from_iter([(
+            "Gradient descent".to_string(),
+            "An iterative algorithm.".to_string(),
+        )]));
+        let project = ProjectContext {
+            bibliography: Some(&bibliography),
+            glossary: Some(&glossary),
+            ..Default::default()
+        };
+
+        let cases = [
+            (
+                "$$x$$|eq-a\n\nSee #ref(eq-a).",
+                r##"<a href="#eq-a" class="reference">1</a>"##,
+            ),
+            (
+                "See #cite(knuth1984, 12).",
+                r##"<a href="#ref-knuth1984" class="citation">[1, p. 12]</a>"##,
+            ),
+            (
+                "We use #term(gradient descent).",
+                r##"<span class="term" id="term-gradient-descent-1" title="An iterative algorithm.">gradient descent</span>"##,
+            ),
+            (
+                "#toc(2)\n\n# Intro {#intro}\n\n## Setup {#install}\n\n### Details {#details}",
+                r##"<li class="toc-level-2" style="margin-left: 1em"><a href="#install">Setup</a></li>"##,
+            ),
+        ];
+        for (input, expected) in cases {
+            let output = render_html_with_structure(input, project);
+            assert!(output.contains(expected), "{input}: {output}");
+        }
+    }
+
+    #[test]
+    fn references() {
+        let output = render_html("$$x$$|eq-a\n\nSee #ref(eq-a), #ref(lbl=eq-a) and #ref(id=eq-a).");
+        assert_eq!(
+            3,
+            output
+                .matches(r##"<a href="#eq-a" class="reference">1</a>"##)
+                .count(),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn numbering_across_documents() {
+        // Inline math is not numbered and labelled shortcodes in parameters are.
+        let first = "Inline $x$|eq-inline math.\n\n#figure|fig-a(url=a.png, caption={Nested #figure|fig-b(url=b.png, caption=Inner)})\n";
+        let second = "$$y$$|eq-a\n\n#figure|fig-c(url=c.png, caption=Later)\n\nSee #ref(eq-a).\n";
+
+        let mut numbering = ProjectNumbering::new(HashMap::from([
+            ("figure".to_string(), global_numbering()),
+            ("equation".to_string(), global_numbering()),
+        ]));
+        let mut doc: Document<Ast> = Document::try_from(first).unwrap();
+        let counts = label_counts(&collect_references(&mut doc).unwrap());
+        let first_numbering = numbering.next_document(&[1], &counts);
+        let second_numbering = numbering.next_document(&[2], &Default::default());
+
+        let project = ProjectContext {
+            numbering: Some(&first_numbering),
+            ..Default::default()
+        };
+        let output = render_html_with(first, project, vec![]).unwrap();
+        assert!(output.contains("Figure 2: Inner"), "{output}");
+
+        let project = ProjectContext {
+            numbering: Some(&second_numbering),
+            ..Default::default()
+        };
+        let output = render_html_with(second, project, vec![]).unwrap();
+        assert!(output.contains("Figure 3: Later"), "{output}");
+        assert!(
+            output.contains(r##"<a href="#eq-a" class="reference">1</a>"##),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn toc() {
+        let output = render_html(
+            "#toc(depth=2)\n\n# Intro {#intro}\n\n## Setup {#install}\n\n### Details {#details}",
+        );
+        assert!(
+            output.contains(r##"<li class="toc-level-1" style="margin-left: 0em"><a href="#intro">Intro</a></li>"##),
+            "{output}"
+        );
+        assert!(
+            output.contains(r##"<li class="toc-level-2" style="margin-left: 1em"><a href="#install">Setup</a></li>"##),
+            "{output}"
+        );
+        assert!(!output.contains(r##"href="#details""##), "{output}");
+    }
+
+    #[test]
+    fn toc_with_deeper_first_heading() {
+        let output =
+            render_html("#toc\n\n## Setup {#setup}\n\n# Intro {#intro}\n\n## Details {#details}");
+        for id in ["setup", "intro", "details"] {
+            assert!(output.contains(&format!(r##"href="#{id}""##)), "{output}");
+        }
+    }
+
+    #[test]
+    fn unknown_shortcodes() {
+        let err = try_render_html("Text #nonexistent(x) text").unwrap_err();
+        assert!(format!("{err:#}").contains("'nonexistent'"), "{err:#}");
+
+        let err = try_render_html("#toc(2, 3)").unwrap_err();
+        assert!(format!("{err:#}").contains("Too many arguments"), "{err:#}");
+    }
+}
@@ -1,4 +1,7 @@
+use crate::bibliography::Bibliography;
 use crate::config::Format;
+use crate::glossary::Glossary;
+use crate::numbering::DocumentNumbering;
 use anyhow::Result;
 use std::collections::HashMap;
 
@@ -11,6 +14,7 @@ use std::io::Write;
 
 use crate::parser::ParserSettings;
 use crate::renderers::extensions::RenderExtension;
+use crate::renderers::footnotes::FootnoteVisitor;
 use crate::renderers::parameter_resolution::ParameterResolution;
 use crate::renderers::references::ReferenceVisitor;
 use cdoc_parser::ast::visitor::AstVisitor;
@@ -22,12 +26,16 @@ use tera::Context;
 use crate::templates::TemplateManager;
 
 pub mod extensions;
+mod footnotes;
 pub mod generic;
 pub mod json;
 pub mod notebook;
 mod parameter_resolution;
 mod references;
 
+pub use footnotes::Footnote;
+pub use references::{ProjectReference, ProjectReferences, ReferenceIndex};
+
 /// Type alias used to specify that the string is a renderer output.
 pub type RenderResult = CowStr;
 
@@ -46,6 +54,32 @@ pub struct RenderContext<'a> {
     pub parser_settings: ParserSettings,
     pub references: LinkedHashMap<String, Reference>,
     pub references_by_type: HashMap<String, Vec<(String, Reference)>>,
+    /// Footnotes of the document, ordered by their number.
+    pub footnotes: LinkedHashMap<String, Footnote>,
+    /// Data of the whole project that documents can refer to.
+    pub project: ProjectContext<'a>,
+}
+
+/// Project-wide data used for rendering a document. Everything is optional so that documents can
+/// also be rendered on their own.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProjectContext<'a> {
+    /// Labels of the whole project, used to resolve references to other documents.
+    pub references: Option<ProjectReferences<'a>>,
+    /// Entries that can be cited with `#cite`.
+    pub bibliography: Option<&'a Bibliography>,
+    /// Terms that can be used with `#term`.
+    pub glossary: Option<&'a Glossary>,
+    /// Numbers of the labelled elements of the document within the project.
+    pub numbering: Option<&'a DocumentNumbering>,
+}
+
+impl ProjectContext<'_> {
+    /// Number of the `num`th labelled element of the given type in the document.
+    pub fn format_num(&self, obj_type: &str, num: usize) -> String {
+        self.numbering
+            .map_or_else(|| num.to_string(), |n| n.format(obj_type, num))
+    }
 }
 
 impl<'a> RenderContext<'a> {
@@ -58,15 +92,18 @@ impl<'a> RenderContext<'a> {
         notebook_output_meta: &'a NotebookMeta,
         format: &'a dyn Format,
         parser_settings: ParserSettings,
+        project: ProjectContext<'a>,
     ) -> Result<Self> {
         let mut parameter_resolution = ParameterResolution { templates };
         parameter_resolution.walk_ast(&mut doc.content.blocks)?;
 
-        let mut ref_visit = ReferenceVisitor::new();
-        ref_visit.walk_ast(&mut doc.content.blocks)?;
-        let rbt = references_by_type(&mut ref_visit.references);
+        let mut references = collect_references(doc)?;
+        let rbt = references_by_type(&mut references);
 
-        extra_args.insert("refs", &ref_visit.references);
+        let mut footnote_visit = FootnoteVisitor::new();
+        footnote_visit.walk_ast(&mut doc.content.blocks)?;
+
+        extra_args.insert("refs", &references);
         extra_args.insert("refs_by_type", &rbt);
         extra_args.insert("defs", &templates.definitions);
         // args.insert("refs", &ctx.references);
@@ -82,12 +119,31 @@ impl<'a> RenderContext<'a> {
             notebook_output_meta,
             format,
             parser_settings,
-            references: ref_visit.references,
+            references,
             references_by_type: rbt,
+            footnotes: footnote_visit.into_footnotes(),
+            project,
         })
     }
 }
 
+/// Collects the labelled elements of a document. Labels must be unique within the document.
+pub fn collect_references(doc: &mut Document<Ast>) -> Result<LinkedHashMap<String, Reference>> {
+    let mut ref_visit = ReferenceVisitor::new();
+    ref_visit.walk_ast(&mut doc.content.blocks)?;
+    Ok(ref_visit.references)
+}
+
+/// Number of labelled elements of each type. These are the elements that are numbered when the
+/// document is rendered, so the counts can be used to number the elements of later documents.
+pub fn label_counts(refs: &LinkedHashMap<String, Reference>) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    for reference in refs.values() {
+        *counts.entry(reference.obj_type.clone()).or_default() += 1;
+    }
+    counts
+}
+
 pub fn references_by_type(
     refs: &mut LinkedHashMap<String, Reference>,
 ) -> HashMap<String, Vec<(String, Reference)>> {
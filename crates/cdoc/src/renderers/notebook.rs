@@ -1,13 +1,13 @@
 use anyhow::Result;
-use cdoc_parser::ast::visitor::AstVisitor;
-use cdoc_parser::ast::{Ast, CodeBlock, Inline};
+use cdoc_parser::ast::{Ast, Block, CodeBlock};
 use cdoc_parser::document::{CodeOutput, Document};
 use cdoc_parser::notebook::{Cell, CellCommon, CellMeta, JupyterLabMeta, Notebook, NotebookMeta};
+use cdoc_parser::raw::Attributes;
 
 use nanoid::nanoid;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::io::BufWriter;
+use std::mem;
 
 use crate::renderers::extensions::RenderExtension;
 use crate::renderers::generic::GenericRenderer;
@@ -34,7 +34,7 @@ impl DocumentRenderer for NotebookRenderer {
         let writer = NotebookWriter {
             notebook_meta: ctx.notebook_output_meta.clone(),
             outputs: ctx.doc.code_outputs.clone(),
-            code_cells: vec![],
+            cells: vec![],
             ctx,
             renderer,
         };
@@ -160,13 +160,13 @@ impl DocumentRenderer for NotebookRenderer {
 pub struct NotebookWriter<'a> {
     pub notebook_meta: NotebookMeta,
     pub outputs: HashMap<u64, CodeOutput>,
-    pub code_cells: Vec<Cell>,
+    pub cells: Vec<Cell>,
     pub ctx: &'a RenderContext<'a>,
     pub renderer: GenericRenderer,
 }
 
 impl NotebookWriter<'_> {
-    fn convert(mut self, mut ast: Ast) -> Result<Notebook> {
+    fn convert(mut self, ast: Ast) -> Result<Notebook> {
         let cell_meta = CellMeta {
             jupyter: Some(JupyterLabMeta {
                 outputs_hidden: None,
@@ -191,72 +191,165 @@ HTML(f"""
             execution_count: Some(0),
             outputs: vec![],
         };
+        self.cells.push(import);
 
-        self.walk_ast(&mut ast.blocks)?;
-
-        let mut buf = BufWriter::new(Vec::new());
-
-        self.renderer.render(&ast.blocks, self.ctx, &mut buf)?;
+        // Each run of blocks between two code cells becomes one markdown cell.
+        let mut markdown = Vec::new();
+        for block in &ast.blocks {
+            match block {
+                Block::CodeBlock(code) if code.attributes.has_flag("cell") => {
+                    self.push_markdown_cell(&String::from_utf8(mem::take(&mut markdown))?);
+                    self.push_code_cell(code)?;
+                }
+                _ => self.renderer.render(block, self.ctx, &mut markdown)?,
+            }
+        }
+        self.renderer.render_bibliography(self.ctx, &mut markdown)?;
+        self.renderer.render_footnotes(self.ctx, &mut markdown)?;
+        self.push_markdown_cell(&String::from_utf8(markdown)?);
 
-        let out_str = String::from_utf8(buf.into_inner()?)?;
+        Ok(Notebook {
+            metadata: self.notebook_meta,
+            nbformat: 4,
+            nbformat_minor: 5,
+            cells: self.cells,
+        })
+    }
 
-        let md_cells = out_str.split(CODE_SPLIT);
+    fn push_code_cell(&mut self, block: &CodeBlock) -> Result<()> {
+        let rendered = block.source.to_string(
+            self.ctx
+                .doc
+                .meta
+                .code_solutions
+                .unwrap_or(self.ctx.parser_settings.solutions),
+        )?;
 
-        let mut cells = vec![import];
+        self.cells.push(Cell::Code {
+            common: CellCommon {
+                id: block
+                    .attributes
+                    .id()
+                    .map_or_else(|| nanoid!(), |id| id.to_string()),
+                metadata: code_cell_meta(&block.attributes),
+                source: rendered.trim().to_string(),
+            },
+            execution_count: Some(0),
+            outputs: vec![], // TODO: fix outputs
+        });
+        Ok(())
+    }
 
-        for (idx, md) in md_cells.enumerate() {
-            cells.push(Cell::Markdown {
+    /// Adds a markdown cell unless the source is only whitespace.
+    fn push_markdown_cell(&mut self, source: &str) {
+        if !source.trim().is_empty() {
+            self.cells.push(Cell::Markdown {
                 common: CellCommon {
                     id: nanoid!(),
                     metadata: Default::default(),
-                    source: md.to_string(),
+                    source: source.to_string(),
                 },
             });
-            if let Some(code) = self.code_cells.get(idx) {
-                cells.push(code.clone());
-            }
         }
+    }
+}
 
-        Ok(Notebook {
-            metadata: self.notebook_meta,
-            nbformat: 4,
-            nbformat_minor: 5,
-            cells,
-        })
+/// Classes become cell tags and the remaining key-value attributes are added to the metadata.
+fn code_cell_meta(attributes: &Attributes) -> CellMeta {
+    CellMeta {
+        tags: (!attributes.classes.is_empty())
+            .then(|| attributes.classes.iter().map(|c| c.to_string()).collect()),
+        additional: attributes
+            .values
+            .iter()
+            .filter(|(key, _)| key.as_str() != "id")
+            .map(|(key, value)| (key.to_string(), value.as_str().into()))
+            .collect(),
+        ..Default::default()
     }
 }
 
-const CODE_SPLIT: &str = "--+code+--";
+#[cfg(test)]
+mod tests {
+    use crate::config::{Format, NotebookFormat};
+    use crate::parser::ParserSettings;
+    use crate::renderers::RenderContext;
+    use crate::templates::TemplateManager;
+    use cdoc_parser::ast::Ast;
+    use cdoc_parser::document::Document;
+    use cdoc_parser::notebook::{Cell, CellCommon, Notebook};
+    use std::path::PathBuf;
 
-impl AstVisitor for NotebookWriter<'_> {
-    fn visit_inline(&mut self, inline: &mut Inline) -> Result<()> {
-        if let Inline::CodeBlock(CodeBlock {
-            source, attributes, ..
-        }) = inline
-        {
-            if attributes.contains(&"cell".into()) {
-                let rendered = source.to_string(
-                    self.ctx
-                        .doc
-                        .meta
-                        .code_solutions
-                        .unwrap_or(self.ctx.parser_settings.solutions),
-                )?;
+    /// Kind and trimmed source of the cells after the setup cell.
+    fn render_cells(input: &str) -> Vec<(&'static str, String)> {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/config/templates");
+        let templates = TemplateManager::from_path(root.clone(), root, false).unwrap();
+        let format = NotebookFormat {};
+        let meta = Default::default();
 
-                self.code_cells.push(Cell::Code {
-                    common: CellCommon {
-                        id: nanoid!(),
-                        metadata: Default::default(),
-                        source: rendered.trim().to_string(),
-                    },
-                    execution_count: Some(0),
-                    outputs: vec![], // TODO: fix outputs
-                });
+        let mut doc: Document<Ast> = Document::try_from(input).unwrap();
+        let mut ctx = RenderContext::new(
+            &mut doc,
+            &templates,
+            Default::default(),
+            &meta,
+            &format,
+            ParserSettings::default(),
+            Default::default(),
+        )
+        .unwrap();
+        let output = format.renderer().render_doc(&mut ctx, vec![]).unwrap();
+        let notebook: Notebook = serde_json::from_str(&output.content).unwrap();
 
-                *inline = Inline::Text(CODE_SPLIT.into());
-            }
-        }
+        notebook.cells[1..]
+            .iter()
+            .map(|c| match c {
+                Cell::Markdown {
+                    common: CellCommon { source, .. },
+                } => ("markdown", source.trim().to_string()),
+                Cell::Code {
+                    common: CellCommon { source, .. },
+                    ..
+                } => ("code", source.trim().to_string()),
+                _ => ("raw", String::new()),
+            })
+            .collect()
+    }
 
-        Ok(())
+    #[test]
+    fn cells() {
+        let cells = render_cells(
+            "Intro with --+cell+-- and --+code+--\n\n```python, cell\nprint(\"--+cell+--\")\n```\n\n```python\nprint(2)\n```\n\nEnd",
+        );
+
+        assert_eq!(3, cells.len(), "{cells:?}");
+        assert_eq!("markdown", cells[0].0);
+        assert!(
+            cells[0].1.contains("Intro with --+cell+-- and --+code+--"),
+            "{cells:?}"
+        );
+        assert_eq!(("code", "print(\"--+cell+--\")".to_string()), cells[1]);
+        assert_eq!("markdown", cells[2].0);
+        assert!(
+            cells[2].1.contains("print(2)") && cells[2].1.contains("End"),
+            "{cells:?}"
+        );
+    }
+
+    #[test]
+    fn nested_code_blocks() {
+        // Only top-level code blocks become cells.
+        let cells = render_cells(
+            "Intro\n\n- First step\n\n  ```python, cell\n  print(1)\n  ```\n\n- Second step\n\nEnd",
+        );
+
+        assert_eq!(1, cells.len(), "{cells:?}");
+        assert_eq!("markdown", cells[0].0);
+        assert!(
+            cells[0].1.contains("First step")
+                && cells[0].1.contains("print(1)")
+                && cells[0].1.contains("End"),
+            "{cells:?}"
+        );
     }
 }
@@ -1,21 +1,39 @@
+use crate::renderers::generic::builtin_parameters;
 use crate::templates::{TemplateManager, TemplateType};
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
 use cdoc_parser::ast::visitor::AstVisitor;
-use cdoc_parser::ast::Command;
+use cdoc_parser::ast::{Command, Parameter, Value};
 
+/// Gives positional arguments the name of their parameter and adds default values for omitted
+/// arguments. Shortcodes that are neither built in nor defined by a template are an error.
 pub struct ParameterResolution<'a> {
     pub templates: &'a TemplateManager,
 }
 
 impl AstVisitor for ParameterResolution<'_> {
     fn visit_command(&mut self, cmd: &mut Command) -> anyhow::Result<()> {
+        if let Some(names) = builtin_parameters(&cmd.function) {
+            for (i, param) in cmd.parameters.iter_mut().enumerate() {
+                if param.key.is_none() {
+                    param.key = Some(
+                        (*names.get(i).ok_or_else(|| {
+                            anyhow!("Too many arguments for #{} at {}", cmd.function, cmd.span)
+                        })?)
+                        .into(),
+                    );
+                }
+            }
+            return self.walk_command(&mut cmd.body);
+        }
+
+        let def = self
+            .templates
+            .get_template(&cmd.function, TemplateType::Shortcode)
+            .with_context(|| format!("at {}", cmd.span))?
+            .shortcode
+            .unwrap();
         for (i, param) in cmd.parameters.iter_mut().enumerate() {
             if param.key.is_none() {
-                let def = self
-                    .templates
-                    .get_template(&cmd.function, TemplateType::Shortcode)?
-                    .shortcode
-                    .unwrap();
                 param.key = Some(
                     def.parameters
                         .get(i)
@@ -25,6 +43,20 @@ impl AstVisitor for ParameterResolution<'_> {
                 );
             }
         }
+
+        for sp in &def.parameters {
+            let given = cmd
+                .parameters
+                .iter()
+                .any(|p| p.key.as_ref() == Some(&sp.name));
+            if let (false, Some(default)) = (given, sp.default_source()) {
+                cmd.parameters.push(Parameter {
+                    key: Some(sp.name.clone()),
+                    value: Value::String(default),
+                    span: cmd.span.clone(),
+                });
+            }
+        }
         self.walk_command(&mut cmd.body)
     }
 }
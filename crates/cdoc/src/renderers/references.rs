@@ -1,5 +1,10 @@
+use crate::numbering::DocumentNumbering;
+use anyhow::bail;
 use cdoc_parser::ast::visitor::AstVisitor;
 use cdoc_parser::ast::{CodeBlock, Command, Math, Reference, Value};
+use cdoc_parser::Span;
+use serde::Serialize;
+use std::collections::HashMap;
 
 use linked_hash_map::LinkedHashMap;
 
@@ -13,33 +18,44 @@ impl ReferenceVisitor {
             references: Default::default(),
         }
     }
+
+    fn insert(&mut self, label: &str, reference: Reference, span: &Span) -> anyhow::Result<()> {
+        if self.references.contains_key(label) {
+            bail!("duplicate label '{label}' at {span}");
+        }
+        self.references.insert(label.to_string(), reference);
+        Ok(())
+    }
 }
 
 impl AstVisitor for ReferenceVisitor {
     fn visit_code_block(&mut self, block: &mut CodeBlock) -> anyhow::Result<()> {
         if let Some(label) = &block.label {
-            self.references.insert(
-                label.to_string(),
+            self.insert(
+                label,
                 Reference {
                     obj_type: "code".to_string(),
                     attr: Default::default(), // TODO: Attrs
                     num: 0,
                 },
-            );
+                &block.span,
+            )?;
         }
         Ok(())
     }
 
     fn visit_math(&mut self, math: &mut Math) -> anyhow::Result<()> {
-        if let Some(label) = &math.label {
-            self.references.insert(
-                label.to_string(),
+        // Only display math is numbered.
+        if let Some(label) = math.label.as_ref().filter(|_| math.display_block) {
+            self.insert(
+                label,
                 Reference {
                     obj_type: "equation".to_string(),
                     attr: Default::default(),
                     num: 0,
                 },
-            );
+                &math.span,
+            )?;
         }
         Ok(())
     }
@@ -56,20 +72,331 @@ impl AstVisitor for ReferenceVisitor {
             })
             .collect();
         if let Some(id) = &cmd.label {
-            self.references.insert(
-                id.to_string(),
+            self.insert(
+                id,
                 Reference {
                     obj_type: cmd.function.to_string(),
                     attr: params,
                     num: 0,
                 },
-            );
+                &cmd.span,
+            )?;
+        }
+        for param in &mut cmd.parameters {
+            if let Value::Content(blocks) = &mut param.value {
+                self.walk_vec_block(blocks)?;
+            }
         }
         if let Some(body) = &mut cmd.body {
             self.walk_vec_block(body)?;
         }
         Ok(())
     }
+}
+
+/// A labelled element anywhere in the project.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProjectReference {
+    /// The document that defines the label (its path in the content folder without extension).
+    pub doc: String,
+    /// Output file of the document, relative to the build folder of the output format.
+    pub path: String,
+    pub obj_type: String,
+    /// Number of the element among the elements of the same type in its document.
+    pub num: usize,
+    /// Number of the element as it is rendered (see the `numbering` configuration).
+    pub number: String,
+    /// Id of the element in the rendered output.
+    pub anchor: String,
+}
+
+/// Labels of all documents in a project (for a single output format). It is built before
+/// documents are rendered so that `#ref` can link to elements in other documents.
+#[derive(Debug, Clone, Default)]
+pub struct ReferenceIndex {
+    /// Output file of each document.
+    documents: HashMap<String, String>,
+    labels: HashMap<String, Vec<ProjectReference>>,
+}
+
+/// The project-wide labels together with the document that is being rendered.
+#[derive(Debug, Clone, Copy)]
+pub struct ProjectReferences<'a> {
+    pub index: &'a ReferenceIndex,
+    pub current: &'a str,
+}
+
+impl ReferenceIndex {
+    /// Adds (or replaces) the labels defined by a document. The document key is its path in the
+    /// content folder without extension and `path` is the output file relative to the build
+    /// folder. Elements are numbered with the document's numbering.
+    pub fn insert_document(
+        &mut self,
+        doc: &str,
+        path: &str,
+        references: &LinkedHashMap<String, Reference>,
+        numbering: &DocumentNumbering,
+    ) {
+        self.labels
+            .values_mut()
+            .for_each(|defs| defs.retain(|r| r.doc != doc));
+        self.documents.insert(doc.to_string(), path.to_string());
+
+        for (label, reference) in references {
+            self.labels
+                .entry(label.clone())
+                .or_default()
+                .push(ProjectReference {
+                    doc: doc.to_string(),
+                    path: path.to_string(),
+                    obj_type: reference.obj_type.clone(),
+                    num: reference.num,
+                    number: numbering.format(&reference.obj_type, reference.num),
+                    anchor: label.clone(),
+                });
+        }
+    }
+
+    /// Labels that are defined in more than one document, with the documents that define them.
+    /// Both are sorted so that the result is the same for every build.
+    pub fn duplicate_labels(&self) -> Vec<(&str, Vec<&str>)> {
+        let mut duplicates: Vec<(&str, Vec<&str>)> = self
+            .labels
+            .iter()
+            .filter(|(_, defs)| defs.len() > 1)
+            .map(|(label, defs)| {
+                let mut docs: Vec<&str> = defs.iter().map(|r| r.doc.as_str()).collect();
+                docs.sort();
+                (label.as_str(), docs)
+            })
+            .collect();
+        duplicates.sort();
+        duplicates
+    }
+
+    /// Fails if a label is defined in more than one document.
+    pub fn check_duplicates(&self) -> anyhow::Result<()> {
+        let duplicates = self.duplicate_labels();
+        if !duplicates.is_empty() {
+            let labels: Vec<String> = duplicates
+                .iter()
+                .map(|(label, docs)| format!("'{label}' ({})", docs.join(", ")))
+                .collect();
+            bail!("labels defined in several documents: {}", labels.join(", "));
+        }
+        Ok(())
+    }
+
+    fn matches(key: &str, doc: &str) -> bool {
+        key == doc || key.ends_with(&format!("/{doc}"))
+    }
+
+    /// Finds the element with the given label. Without an explicit document, labels of the
+    /// current document take precedence and labels of other documents must be unique.
+    pub fn resolve(
+        &self,
+        current: &str,
+        doc: Option<&str>,
+        label: &str,
+    ) -> anyhow::Result<&ProjectReference> {
+        let defs = self
+            .labels
+            .get(label)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        match doc {
+            Some(doc) => {
+                let found: Vec<&ProjectReference> =
+                    defs.iter().filter(|r| Self::matches(&r.doc, doc)).collect();
+                match found.as_slice() {
+                    [reference] => Ok(reference),
+                    [] if !self.documents.keys().any(|k| Self::matches(k, doc)) => {
+                        bail!("unknown document '{doc}'")
+                    }
+                    [] => bail!("unknown label '{label}' in document '{doc}'"),
+                    _ => bail!("document name '{doc}' is ambiguous (use its full path)"),
+                }
+            }
+            None => {
+                if let Some(reference) = defs.iter().find(|r| r.doc == current) {
+                    return Ok(reference);
+                }
+                match defs {
+                    [reference] => Ok(reference),
+                    [] => bail!("unknown label '{label}'"),
+                    _ => {
+                        let mut docs: Vec<&str> = defs.iter().map(|r| r.doc.as_str()).collect();
+                        docs.sort();
+                        bail!(
+                            "label '{label}' is defined in several documents ({}), use #ref(doc=..., lbl={label})",
+                            docs.join(", ")
+                        )
+                    }
+                }
+            }
+        }
+    }
 
-    // TODO: Math block
+    /// Link from the current document to a referenced element.
+    pub fn href(&self, current: &str, reference: &ProjectReference) -> String {
+        match self.link(current, &reference.path) {
+            Some(link) if reference.doc != current => format!("{link}#{}", reference.anchor),
+            _ => format!("#{}", reference.anchor),
+        }
+    }
+
+    /// Relative link from the current document to a file in the build folder.
+    pub fn link(&self, current: &str, path: &str) -> Option<String> {
+        self.documents
+            .get(current)
+            .map(|from| relative_link(from, path))
+    }
+}
+
+/// Relative link between two files given by `/`-separated paths relative to the same folder.
+fn relative_link(from: &str, to: &str) -> String {
+    let mut from_dir: Vec<&str> = from.split('/').collect();
+    from_dir.pop();
+    let to: Vec<&str> = to.split('/').collect();
+
+    let common = from_dir.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut parts = vec![".."; from_dir.len() - common];
+    parts.extend(&to[common..]);
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{relative_link, ReferenceIndex, ReferenceVisitor};
+    use crate::numbering::DocumentNumbering;
+    use cdoc_parser::ast::visitor::AstVisitor;
+    use cdoc_parser::ast::{Ast, Reference};
+    use cdoc_parser::document::Document;
+    use linked_hash_map::LinkedHashMap;
+
+    fn refs(labels: &[(&str, &str, usize)]) -> LinkedHashMap<String, Reference> {
+        labels
+            .iter()
+            .map(|(label, obj_type, num)| {
+                (
+                    label.to_string(),
+                    Reference {
+                        obj_type: obj_type.to_string(),
+                        attr: Default::default(),
+                        num: *num,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn relative_links() {
+        assert_eq!("b.html", relative_link("a.html", "b.html"));
+        assert_eq!("part/b.html", relative_link("a.html", "part/b.html"));
+        assert_eq!("../b.html", relative_link("part/a.html", "b.html"));
+        assert_eq!("../two/b.html", relative_link("one/a.html", "two/b.html"));
+    }
+
+    #[test]
+    fn resolve_references() {
+        let mut index = ReferenceIndex::default();
+        index.insert_document(
+            "part/chapter1",
+            "part/chapter1.html",
+            &refs(&[("fig-data", "figure", 1), ("eq-loss", "equation", 1)]),
+            &DocumentNumbering::default(),
+        );
+        index.insert_document(
+            "part/chapter2",
+            "part/chapter2.html",
+            &refs(&[("fig-data", "figure", 1), ("fig-loss", "figure", 2)]),
+            &DocumentNumbering::default(),
+        );
+        index.insert_document(
+            "intro",
+            "intro.html",
+            &refs(&[]),
+            &DocumentNumbering::default(),
+        );
+
+        let r = index.resolve("intro", None, "fig-loss").unwrap();
+        assert_eq!(("figure", 2), (r.obj_type.as_str(), r.num));
+        assert_eq!("2", r.number);
+        assert_eq!("part/chapter2.html#fig-loss", index.href("intro", r));
+
+        let r = index.resolve("part/chapter2", None, "fig-data").unwrap();
+        assert_eq!("#fig-data", index.href("part/chapter2", r));
+
+        let r = index
+            .resolve("intro", Some("chapter1"), "fig-data")
+            .unwrap();
+        assert_eq!("part/chapter1.html#fig-data", index.href("intro", r));
+
+        let err = |doc, label| {
+            index
+                .resolve("intro", doc, label)
+                .err()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!("unknown label 'fig-nope'", err(None, "fig-nope"));
+        assert_eq!(
+            "unknown document 'chapter3'",
+            err(Some("chapter3"), "fig-data")
+        );
+        assert_eq!(
+            "label 'fig-data' is defined in several documents (part/chapter1, part/chapter2), use #ref(doc=..., lbl=fig-data)",
+            err(None, "fig-data")
+        );
+    }
+
+    #[test]
+    fn duplicate_labels_in_project() {
+        let mut index = ReferenceIndex::default();
+        for doc in ["b", "a"] {
+            index.insert_document(
+                doc,
+                &format!("{doc}.html"),
+                &refs(&[("fig-data", "figure", 1), (doc, "figure", 2)]),
+                &DocumentNumbering::default(),
+            );
+        }
+        assert_eq!(vec![("fig-data", vec!["a", "b"])], index.duplicate_labels());
+
+        index.insert_document("b", "b.html", &refs(&[]), &DocumentNumbering::default());
+        assert!(index.duplicate_labels().is_empty());
+    }
+
+    #[test]
+    fn duplicate_labels_are_errors() {
+        let mut index = ReferenceIndex::default();
+        for doc in ["chapter1", "chapter2"] {
+            index.insert_document(
+                doc,
+                &format!("{doc}.html"),
+                &refs(&[("fig-data", "figure", 1)]),
+                &DocumentNumbering::default(),
+            );
+        }
+        let err = index.check_duplicates().unwrap_err();
+        assert_eq!(
+            "labels defined in several documents: 'fig-data' (chapter1, chapter2)",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn duplicate_labels() {
+        let mut doc: Document<Ast> = Document::try_from("$$a$$|eq-a\n\n$$b$$|eq-a\n").unwrap();
+        let err = ReferenceVisitor::new()
+            .walk_ast(&mut doc.content.blocks)
+            .err()
+            .unwrap();
+        assert!(err
+            .to_string()
+            .starts_with("duplicate label 'eq-a' at line 3"));
+    }
 }
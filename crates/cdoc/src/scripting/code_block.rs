@@ -1,16 +1,16 @@
 use cdoc_parser::code_ast::types::CodeContent;
 use cdoc_parser::document::CodeOutput;
-use cowstr::CowStr;
+use cdoc_parser::raw::Attributes;
 
 use cdoc_parser::Span;
 
 use rhai::serde::{from_dynamic, to_dynamic};
-use rhai::{CustomType, Dynamic, TypeBuilder};
+use rhai::{CustomType, Dynamic, Map, TypeBuilder};
 
 #[derive(Clone)]
 pub(crate) struct ScriptCodeBlock {
     source: CodeContent,
-    attributes: Vec<CowStr>,
+    attributes: Dynamic,
     outputs: Dynamic,
     display_cell: bool,
     global_idx: usize,
@@ -21,7 +21,7 @@ pub(crate) struct ScriptCodeBlock {
 impl ScriptCodeBlock {
     pub fn new(
         source: &CodeContent,
-        attributes: &[CowStr],
+        attributes: &Attributes,
         outputs: &Option<&mut CodeOutput>,
         display_cell: bool,
         global_idx: usize,
@@ -29,7 +29,7 @@ impl ScriptCodeBlock {
     ) -> Self {
         ScriptCodeBlock {
             source: source.clone(),
-            attributes: attributes.to_vec(),
+            attributes: to_dynamic(attributes).unwrap(),
             outputs: to_dynamic(outputs).unwrap(),
             display_cell,
             global_idx,
@@ -40,14 +40,14 @@ impl ScriptCodeBlock {
     pub fn apply_changes(
         self,
         source: &mut CodeContent,
-        tags: &mut Vec<CowStr>,
+        attributes: &mut Attributes,
         outputs: Option<&mut CodeOutput>,
         display_cell: &mut bool,
         global_idx: &mut usize,
     ) -> anyhow::Result<()> {
         *source = self.source;
 
-        *tags = self.attributes;
+        *attributes = from_dynamic(&self.attributes)?;
         *display_cell = self.display_cell;
         *global_idx = self.global_idx;
 
@@ -69,9 +69,24 @@ impl CustomType for ScriptCodeBlock {
                 |s: &mut Self, v: CodeContent| s.source = v,
             )
             .with_get_set(
-                "tags",
+                "attributes",
                 |s: &mut Self| s.attributes.clone(),
-                |s: &mut Self, v: Vec<CowStr>| s.attributes = v,
+                |s: &mut Self, v: Dynamic| s.attributes = v,
+            )
+            // The flags used to be the only attributes and were exposed as `tags`.
+            .with_get_set(
+                "tags",
+                |s: &mut Self| {
+                    s.attributes
+                        .read_lock::<Map>()
+                        .and_then(|m| m.get("flags").cloned())
+                        .unwrap_or_default()
+                },
+                |s: &mut Self, v: Dynamic| {
+                    if let Some(mut m) = s.attributes.write_lock::<Map>() {
+                        m.insert("flags".into(), v);
+                    }
+                },
             )
             .with_get_set(
                 "outputs",
@@ -7,7 +7,7 @@ use std::path::Path;
 use anyhow::{anyhow, Result};
 
 use cdoc_parser::ast::visitor::AstVisitor;
-use cdoc_parser::ast::{CodeBlock, Inline};
+use cdoc_parser::ast::{Block, CodeBlock, Inline};
 
 use cdoc_parser::document::{CodeOutput, Metadata};
 
@@ -47,6 +47,9 @@ fn get_url(url: &str) -> Result<Response, Box<EvalAltResult>> {
 pub struct ScriptEngine {
     engine: Engine,
     ast: rhai::AST,
+    /// Whether the script defines `visit_block`. Blocks are only cloned and passed to the script
+    /// if it does.
+    has_visit_block: bool,
 }
 
 impl ScriptEngine {
@@ -60,6 +63,8 @@ impl ScriptEngine {
 
         let module = exported_module!(types::rhai_inline_type);
         engine.register_global_module(module.into());
+        let module = exported_module!(types::rhai_block_type);
+        engine.register_global_module(module.into());
 
         engine
             .definitions()
@@ -74,7 +79,13 @@ impl ScriptEngine {
         //     .call_fn(&mut state, &ast, "init", ())
         //     .map_err(|e| anyhow!(e.to_string()))?;
 
-        Ok(ScriptEngine { ast, engine })
+        let has_visit_block = ast.iter_functions().any(|f| f.name == "visit_block");
+
+        Ok(ScriptEngine {
+            ast,
+            engine,
+            has_visit_block,
+        })
     }
 }
 
@@ -113,6 +124,33 @@ impl<'a> ScriptVisitor<'a> {
 }
 
 impl AstVisitor for ScriptVisitor<'_> {
+    fn visit_block(&mut self, block: &mut Block) -> Result<()> {
+        if !self.base.has_visit_block {
+            return self.walk_block(block);
+        }
+
+        match self.base.engine.call_fn::<Block>(
+            &mut self.state,
+            &self.base.ast,
+            "visit_block",
+            (block.clone(),),
+        ) {
+            Ok(v) => {
+                *block = v;
+                Ok(())
+            }
+            Err(e) => match *e {
+                EvalAltResult::ErrorFunctionNotFound(_, _) => Ok(()),
+                EvalAltResult::ErrorRuntime(value, _) => {
+                    Err(anyhow!(format!("script error: {}", value)))
+                }
+                _ => Err(anyhow!(format!("{}", e))),
+            },
+        }?;
+
+        self.walk_block(block)
+    }
+
     fn visit_inline(&mut self, inline: &mut Inline) -> Result<()> {
         match self.base.engine.call_fn::<Inline>(
             &mut self.state,
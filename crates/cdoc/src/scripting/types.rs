@@ -3,39 +3,49 @@ use rhai::plugin::*;
 #[allow(non_snake_case, non_upper_case_globals)]
 #[export_module]
 pub(crate) mod rhai_inline_type {
-    use cdoc_parser::ast::{CodeBlock, Command, Math, Style};
+    use cdoc_parser::ast::{CodeBlock, Command, Math, RawContent, Style};
     use cdoc_parser::Span;
     use cowstr::CowStr;
     use pulldown_cmark::LinkType;
+    use rhai::serde::{from_dynamic, to_dynamic};
     use rhai::{Array, Dynamic};
 
     pub type Inline = cdoc_parser::ast::Inline;
 
     pub fn Text(value: CowStr) -> Inline {
-        Inline::Text(value)
+        Inline::Text(value, Span::default())
     }
     pub fn Styled(value: Vec<Inline>, style: Style) -> Inline {
-        Inline::Styled(value, style)
+        Inline::Styled(value, style, Span::default())
     }
 
     pub fn Code(value: CowStr) -> Inline {
-        Inline::Code(value)
+        Inline::Code(value, Span::default())
     }
 
-    pub const SoftBreak: Inline = Inline::SoftBreak;
-    pub const HardBreak: Inline = Inline::HardBreak;
-    pub const Rule: Inline = Inline::Rule;
+    pub const SoftBreak: Inline = Inline::SoftBreak(Span::new(0, 0));
+    pub const HardBreak: Inline = Inline::HardBreak(Span::new(0, 0));
+    pub const Rule: Inline = Inline::Rule(Span::new(0, 0));
 
     pub fn Image(link_type: LinkType, url: CowStr, alt: CowStr, inner: Vec<Inline>) -> Inline {
-        Inline::Image(link_type, url, alt, inner)
+        Inline::Image(link_type, url, alt, inner, Span::default())
     }
 
     pub fn Link(link_type: LinkType, url: CowStr, alt: CowStr, inner: Vec<Inline>) -> Inline {
-        Inline::Link(link_type, url, alt, inner)
+        Inline::Link(link_type, url, alt, inner, Span::default())
     }
 
     pub fn Html(value: CowStr) -> Inline {
-        Inline::Html(value)
+        Inline::Html(value, Span::default())
+    }
+
+    pub fn Raw(format: CowStr, source: CowStr) -> Inline {
+        Inline::Raw(RawContent {
+            format,
+            source,
+            display_block: false,
+            span: Span::default(),
+        })
     }
 
     pub fn Math(label: Option<CowStr>, source: CowStr, display_block: bool, pos: Span) -> Inline {
@@ -51,50 +61,66 @@ pub(crate) mod rhai_inline_type {
         Inline::Command(value)
     }
 
+    pub fn Span(value: Vec<Inline>, attributes: Dynamic) -> Inline {
+        Inline::Span(value, from_dynamic(&attributes).unwrap(), Span::default())
+    }
+
+    pub fn FootnoteReference(label: CowStr) -> Inline {
+        Inline::FootnoteReference(label, Span::default())
+    }
+
     #[rhai_fn(global, get = "value", pure)]
     #[allow(clippy::needless_pass_by_ref_mut)]
     pub fn get_value(value: &mut Inline) -> Array {
         match value {
-            Inline::Text(v) => vec![v.clone().as_str().into()] as Array,
-            Inline::Styled(i, s) => vec![i.clone().into(), Dynamic::from(s.clone())] as Array,
-            Inline::Code(v) => vec![v.clone().as_str().into()] as Array,
+            Inline::Text(v, _) => vec![v.clone().as_str().into()] as Array,
+            Inline::Styled(i, s, _) => vec![i.clone().into(), Dynamic::from(s.clone())] as Array,
+            Inline::Code(v, _) => vec![v.clone().as_str().into()] as Array,
             Inline::CodeBlock(CodeBlock {
                 label,
                 source,
-                attributes: tags,
+                attributes,
                 display_cell,
                 global_idx,
                 span: pos,
             }) => vec![
                 Dynamic::from(label.clone()),
                 Dynamic::from(source.clone()),
-                Dynamic::from(tags.clone()),
+                to_dynamic(attributes).unwrap(),
                 Dynamic::from(*display_cell),
                 Dynamic::from(*global_idx),
                 Dynamic::from(pos.clone()),
             ] as Array,
-            Inline::SoftBreak => vec![] as Array,
-            Inline::HardBreak => vec![] as Array,
-            Inline::Rule => vec![] as Array,
-            Inline::Image(t, u, a, i) => vec![
+            Inline::SoftBreak(_) => vec![] as Array,
+            Inline::HardBreak(_) => vec![] as Array,
+            Inline::Rule(_) => vec![] as Array,
+            Inline::Image(t, u, a, i, _) => vec![
                 Dynamic::from(*t),
                 u.clone().as_str().into(),
                 a.clone().as_str().into(),
                 i.clone().into(),
             ] as Array,
-            Inline::Link(t, u, a, i) => vec![
+            Inline::Link(t, u, a, i, _) => vec![
                 Dynamic::from(*t),
                 u.clone().as_str().into(),
                 a.clone().as_str().into(),
                 i.clone().into(),
             ] as Array,
-            Inline::Html(v) => vec![v.clone().as_str().into()] as Array,
+            Inline::Html(v, _) => vec![v.clone().as_str().into()] as Array,
+            Inline::Raw(RawContent { format, source, .. }) => vec![
+                format.clone().as_str().into(),
+                source.clone().as_str().into(),
+            ] as Array,
+            Inline::Span(i, attributes, _) => {
+                vec![i.clone().into(), to_dynamic(attributes).unwrap()] as Array
+            }
             Inline::Math(Math {
                 source,
                 display_block,
                 ..
             }) => vec![source.clone().as_str().into(), (*display_block).into()] as Array,
             Inline::Command(c) => vec![Dynamic::from(c.clone())] as Array,
+            Inline::FootnoteReference(l, _) => vec![l.clone().as_str().into()] as Array,
         }
     }
 
@@ -102,18 +128,202 @@ pub(crate) mod rhai_inline_type {
     #[allow(clippy::needless_pass_by_ref_mut)]
     pub fn get_type(value: &mut Inline) -> String {
         match value {
-            Inline::Text(_) => "Text".to_string(),
-            Inline::Styled(_, _) => "Styled".to_string(),
-            Inline::Code(_) => "Code".to_string(),
+            Inline::Text(..) => "Text".to_string(),
+            Inline::Styled(..) => "Styled".to_string(),
+            Inline::Code(..) => "Code".to_string(),
             Inline::CodeBlock(CodeBlock { .. }) => "CodeBlock".to_string(),
-            Inline::SoftBreak => "SoftBreak".to_string(),
-            Inline::HardBreak => "HardBreak".to_string(),
-            Inline::Rule => "Rule".to_string(),
-            Inline::Image(_, _, _, _) => "Image".to_string(),
-            Inline::Link(_, _, _, _) => "Link".to_string(),
-            Inline::Html(_) => "Html".to_string(),
+            Inline::SoftBreak(_) => "SoftBreak".to_string(),
+            Inline::HardBreak(_) => "HardBreak".to_string(),
+            Inline::Rule(_) => "Rule".to_string(),
+            Inline::Image(..) => "Image".to_string(),
+            Inline::Link(..) => "Link".to_string(),
+            Inline::Html(..) => "Html".to_string(),
+            Inline::Raw(_) => "Raw".to_string(),
+            Inline::Span(..) => "Span".to_string(),
             Inline::Math(Math { .. }) => "Math".to_string(),
             Inline::Command(_) => "Command".to_string(),
+            Inline::FootnoteReference(..) => "FootnoteReference".to_string(),
         }
     }
+
+    #[rhai_fn(global, get = "span", pure)]
+    #[allow(clippy::needless_pass_by_ref_mut)]
+    pub fn get_span(value: &mut Inline) -> Span {
+        value.span().clone()
+    }
+}
+
+#[allow(non_snake_case, non_upper_case_globals)]
+#[export_module]
+pub(crate) mod rhai_block_type {
+    use cdoc_parser::ast::{AdmonitionKind, CodeBlock, Command, Math, RawContent, TableRow};
+    use cdoc_parser::Span;
+    use cowstr::CowStr;
+    use pulldown_cmark::Alignment;
+    use rhai::serde::to_dynamic;
+    use rhai::{Array, Dynamic};
+
+    pub type Block = cdoc_parser::ast::Block;
+    pub type Inline = cdoc_parser::ast::Inline;
+
+    pub fn Heading(lvl: u8, id: Option<CowStr>, classes: Vec<CowStr>, inner: Vec<Inline>) -> Block {
+        Block::Heading {
+            lvl,
+            id,
+            classes,
+            attributes: Default::default(),
+            inner,
+            span: Span::default(),
+        }
+    }
+
+    pub fn Plain(value: Vec<Inline>) -> Block {
+        Block::Plain(value, Span::default())
+    }
+
+    pub fn Paragraph(value: Vec<Inline>) -> Block {
+        Block::Paragraph(value, Span::default())
+    }
+
+    pub fn BlockQuote(value: Vec<Block>) -> Block {
+        Block::BlockQuote(value, Span::default())
+    }
+
+    pub fn Admonition(kind: AdmonitionKind, value: Vec<Block>) -> Block {
+        Block::Admonition(kind, value, Span::default())
+    }
+
+    pub fn List(start: Option<u64>, items: Vec<Block>) -> Block {
+        Block::List(start, items, Span::default())
+    }
+
+    pub fn ListItem(value: Vec<Block>) -> Block {
+        Block::ListItem(value, Span::default())
+    }
+
+    pub fn Table(alignments: Vec<Alignment>, header: TableRow, rows: Vec<TableRow>) -> Block {
+        Block::Table {
+            alignments,
+            header,
+            rows,
+            span: Span::default(),
+        }
+    }
+
+    pub fn FootnoteDefinition(label: CowStr, value: Vec<Block>) -> Block {
+        Block::FootnoteDefinition(label, value, Span::default())
+    }
+
+    pub fn Math(label: Option<CowStr>, source: CowStr, pos: Span) -> Block {
+        Block::Math(Math {
+            label,
+            source,
+            display_block: true,
+            span: pos,
+        })
+    }
+
+    pub fn Raw(format: CowStr, source: CowStr) -> Block {
+        Block::Raw(RawContent {
+            format,
+            source,
+            display_block: true,
+            span: Span::default(),
+        })
+    }
+
+    pub fn Shortcode(value: Command) -> Block {
+        Block::Command(value)
+    }
+
+    #[rhai_fn(global, get = "value", pure)]
+    #[allow(clippy::needless_pass_by_ref_mut)]
+    pub fn get_value(value: &mut Block) -> Array {
+        match value {
+            Block::Heading {
+                lvl,
+                id,
+                classes,
+                attributes,
+                inner,
+                ..
+            } => vec![
+                Dynamic::from(*lvl),
+                Dynamic::from(id.clone()),
+                Dynamic::from(classes.clone()),
+                Dynamic::from(inner.clone()),
+                to_dynamic(attributes).unwrap(),
+            ] as Array,
+            Block::Plain(i, _) => vec![Dynamic::from(i.clone())] as Array,
+            Block::Paragraph(i, _) => vec![Dynamic::from(i.clone())] as Array,
+            Block::BlockQuote(b, _) => vec![Dynamic::from(b.clone())] as Array,
+            Block::Admonition(k, b, _) => {
+                vec![Dynamic::from(*k), Dynamic::from(b.clone())] as Array
+            }
+            Block::List(start, items, _) => {
+                vec![Dynamic::from(*start), Dynamic::from(items.clone())] as Array
+            }
+            Block::ListItem(b, _) => vec![Dynamic::from(b.clone())] as Array,
+            Block::Table {
+                alignments,
+                header,
+                rows,
+                ..
+            } => vec![
+                Dynamic::from(alignments.clone()),
+                Dynamic::from(header.clone()),
+                Dynamic::from(rows.clone()),
+            ] as Array,
+            Block::FootnoteDefinition(l, b, _) => {
+                vec![l.clone().as_str().into(), Dynamic::from(b.clone())] as Array
+            }
+            Block::CodeBlock(CodeBlock {
+                label,
+                source,
+                attributes,
+                display_cell,
+                global_idx,
+                span: pos,
+            }) => vec![
+                Dynamic::from(label.clone()),
+                Dynamic::from(source.clone()),
+                to_dynamic(attributes).unwrap(),
+                Dynamic::from(*display_cell),
+                Dynamic::from(*global_idx),
+                Dynamic::from(pos.clone()),
+            ] as Array,
+            Block::Math(Math { source, .. }) => vec![source.clone().as_str().into()] as Array,
+            Block::Raw(RawContent { format, source, .. }) => vec![
+                format.clone().as_str().into(),
+                source.clone().as_str().into(),
+            ] as Array,
+            Block::Command(c) => vec![Dynamic::from(c.clone())] as Array,
+        }
+    }
+
+    #[rhai_fn(global, get = "type", pure)]
+    #[allow(clippy::needless_pass_by_ref_mut)]
+    pub fn get_type(value: &mut Block) -> String {
+        match value {
+            Block::Heading { .. } => "Heading".to_string(),
+            Block::Plain(..) => "Plain".to_string(),
+            Block::Paragraph(..) => "Paragraph".to_string(),
+            Block::BlockQuote(..) => "BlockQuote".to_string(),
+            Block::Admonition(..) => "Admonition".to_string(),
+            Block::List(..) => "List".to_string(),
+            Block::ListItem(..) => "ListItem".to_string(),
+            Block::Table { .. } => "Table".to_string(),
+            Block::FootnoteDefinition(..) => "FootnoteDefinition".to_string(),
+            Block::CodeBlock(_) => "CodeBlock".to_string(),
+            Block::Math(_) => "Math".to_string(),
+            Block::Raw(_) => "Raw".to_string(),
+            Block::Command(_) => "Command".to_string(),
+        }
+    }
+
+    #[rhai_fn(global, get = "span", pure)]
+    #[allow(clippy::needless_pass_by_ref_mut)]
+    pub fn get_span(value: &mut Block) -> Span {
+        value.span().clone()
+    }
 }
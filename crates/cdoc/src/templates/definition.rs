@@ -5,7 +5,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 
 use crate::renderers::RenderedParam;
 use crate::templates::precompiled::{PrecompiledFormat, PrecompiledTemplate};
@@ -86,17 +86,55 @@ pub struct ShortcodeParameter {
     /// Whether the argument can be omitted
     #[serde(default)]
     pub optional: bool,
+    /// Value used when the argument is omitted. It is converted like a value given in a document.
+    #[serde(default)]
+    pub default: Option<serde_json::Value>,
     #[serde(rename = "type")]
     pub type_: ParameterType,
 }
 
-/// Parameter types. This must currently be either an arbitrary string or a predefined set of valid
-/// values.
+impl ShortcodeParameter {
+    /// The default value written as it would be in a shortcode call.
+    pub fn default_source(&self) -> Option<CowStr> {
+        self.default.as_ref().map(|value| match value {
+            serde_json::Value::Array(items) => items
+                .iter()
+                .map(value_source)
+                .collect::<Vec<_>>()
+                .join(", ")
+                .into(),
+            value => value_source(value).into(),
+        })
+    }
+}
+
+fn value_source(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Parameter types. Values are always written as strings in the document and are converted to the
+/// parameter type before they are passed to the template.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum ParameterType {
+    /// Any string.
     Regular,
+    /// One of a predefined set of values.
     Choice(Vec<CowStr>),
+    #[serde(alias = "int")]
+    Integer,
+    Float,
+    #[serde(alias = "bool")]
+    Boolean,
+    /// An absolute URL, e.g. `https://example.com`.
+    Url,
+    /// A path relative to the project root. It may not leave the project directory.
+    Path,
+    /// A comma-separated list of strings.
+    List,
 }
 
 impl Display for ParameterType {
@@ -104,6 +142,12 @@ impl Display for ParameterType {
         match self {
             ParameterType::Regular => write!(f, "regular"),
             ParameterType::Choice(cs) => write!(f, "{:?}", cs),
+            ParameterType::Integer => write!(f, "integer"),
+            ParameterType::Float => write!(f, "float"),
+            ParameterType::Boolean => write!(f, "boolean"),
+            ParameterType::Url => write!(f, "url"),
+            ParameterType::Path => write!(f, "path"),
+            ParameterType::List => write!(f, "list"),
         }
     }
 }
@@ -228,22 +272,83 @@ pub enum ValidationError {
 }
 
 impl ParameterType {
-    pub fn validate(&self, value: &RenderedParam) -> Result<(), ValidationError> {
+    /// Validates the value and converts it to the type of the parameter.
+    pub fn parse(&self, value: &RenderedParam) -> Result<serde_json::Value, ValidationError> {
+        let v = value.value.as_str();
+        let invalid = |expected: &str| {
+            ValidationError::InvalidValue(format!("expected {}, got '{}'", expected, v))
+        };
+
         match self {
-            ParameterType::Regular => Ok(()),
-            ParameterType::Choice(choices) => {
-                let v = &value.value;
-                choices.contains(v).then_some(()).ok_or_else(|| {
+            ParameterType::Regular => Ok(v.into()),
+            ParameterType::Choice(choices) => choices
+                .contains(&value.value)
+                .then(|| v.into())
+                .ok_or_else(|| {
                     ValidationError::InvalidValue(format!(
                         "The provided value {} must be one of {:?}",
                         v, &choices
                     ))
-                })
+                }),
+            ParameterType::Integer => v
+                .trim()
+                .parse::<i64>()
+                .map(serde_json::Value::from)
+                .map_err(|_| invalid("an integer")),
+            ParameterType::Float => v
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .and_then(serde_json::Number::from_f64)
+                .map(serde_json::Value::Number)
+                .ok_or_else(|| invalid("a number")),
+            ParameterType::Boolean => match v.trim() {
+                "true" => Ok(true.into()),
+                "false" => Ok(false.into()),
+                _ => Err(invalid("'true' or 'false'")),
+            },
+            ParameterType::Url => {
+                let v = v.trim();
+                is_absolute_url(v)
+                    .then(|| v.into())
+                    .ok_or_else(|| invalid("an absolute URL"))
             }
+            ParameterType::Path => {
+                let v = v.trim();
+                let inside_project = !v.is_empty()
+                    && Path::new(v)
+                        .components()
+                        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+                inside_project
+                    .then(|| v.into())
+                    .ok_or_else(|| invalid("a relative path inside the project"))
+            }
+            ParameterType::List => Ok(v
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(serde_json::Value::from)
+                .collect()),
         }
     }
 }
 
+/// Checks for a scheme followed by a non-empty remainder, e.g. `https://example.com` or
+/// `mailto:someone@example.com`.
+fn is_absolute_url(value: &str) -> bool {
+    match value.split_once(':') {
+        Some((scheme, rest)) => {
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+                && !rest.is_empty()
+                && !value.contains(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
 impl TemplateDefinition {
     pub fn template_for_format(&self, base_path: PathBuf, format: &str) -> anyhow::Result<String> {
         let tp = self
@@ -276,30 +381,44 @@ impl TemplateDefinition {
             .ok_or(anyhow!("Format not supported by template"))
     }
 
+    /// Validates the arguments of a shortcode call and converts them to the types of the
+    /// parameters. Returns an error if a required parameter is missing.
     pub fn validate_args(
         &self,
         args: &[RenderedParam],
-    ) -> Result<Vec<anyhow::Result<()>>, anyhow::Error> {
+    ) -> Result<Vec<anyhow::Result<serde_json::Value>>, anyhow::Error> {
         if let TemplateType::Shortcode = &self.type_ {
             let s = self.shortcode.as_ref().unwrap();
-            let res: Vec<Result<(), anyhow::Error>> = args
+            let given = |idx: usize, sp: &ShortcodeParameter| {
+                args.iter().enumerate().any(|(i, p)| match &p.key {
+                    Some(key) => key == sp.name,
+                    None => i == idx,
+                })
+            };
+            if let Some((_, missing)) = s
+                .parameters
+                .iter()
+                .enumerate()
+                .find(|(idx, sp)| !sp.optional && sp.default.is_none() && !given(*idx, sp))
+            {
+                return Err(ValidationError::RequiredParameter(missing.name.to_string()))
+                    .context(format!("when parsing shortcode '{}'", self.name));
+            }
+
+            let res: Vec<anyhow::Result<serde_json::Value>> = args
                 .iter()
                 .enumerate()
                 .map(|(i, p)| {
                     if let Some(key) = &p.key {
-                        if s.accept_arbitrary_params {
-                            Ok(())
-                        } else {
-                            s.parameters
-                                .iter()
-                                .find(|sp| sp.name == key)
-                                .map(|sp| sp.type_.validate(p))
-                                .ok_or(ValidationError::InvalidName(key.to_string()))?
+                        match s.parameters.iter().find(|sp| sp.name == key) {
+                            Some(sp) => sp.type_.parse(p),
+                            None if s.accept_arbitrary_params => Ok(p.value.as_str().into()),
+                            None => Err(ValidationError::InvalidName(key.to_string())),
                         }
                     } else {
                         s.parameters
                             .get(i)
-                            .map(|sp| sp.type_.validate(p))
+                            .map(|sp| sp.type_.parse(p))
                             .ok_or(ValidationError::InvalidValue(p.value.to_string()))?
                     }
                 })
@@ -314,3 +433,85 @@ impl TemplateDefinition {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const DEFINITION: &str = r#"
+name: test
+description: Test shortcode.
+type: shortcode
+shortcode:
+  kind: inline
+  parameters:
+    - name: title
+      description: Required.
+      type: regular
+    - name: count
+      description: Integer with default.
+      type: integer
+      default: 2
+    - name: show
+      description: Optional boolean.
+      optional: true
+      type: boolean
+    - name: tags
+      description: Optional list.
+      optional: true
+      type: list
+templates: {}
+"#;
+
+    fn param(key: &str, value: &str) -> RenderedParam {
+        RenderedParam {
+            key: Some(key.into()),
+            value: value.into(),
+        }
+    }
+
+    #[test]
+    fn typed_arguments() {
+        let def: TemplateDefinition = serde_yaml::from_str(DEFINITION).unwrap();
+
+        let values: Vec<serde_json::Value> = def
+            .validate_args(&[
+                param("title", "Æbler"),
+                param("count", " 3"),
+                param("show", "true"),
+                param("tags", "a, b ,c"),
+            ])
+            .unwrap()
+            .into_iter()
+            .collect::<anyhow::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            vec![
+                json!("Æbler"),
+                json!(3),
+                json!(true),
+                json!(["a", "b", "c"])
+            ],
+            values
+        );
+
+        let invalid = def.validate_args(&[param("title", "x"), param("count", "many")]);
+        assert!(invalid.unwrap()[1].is_err());
+    }
+
+    #[test]
+    fn required_arguments() {
+        let def: TemplateDefinition = serde_yaml::from_str(DEFINITION).unwrap();
+        assert_eq!(
+            Some("2".into()),
+            def.shortcode.as_ref().unwrap().parameters[1].default_source()
+        );
+
+        let err = def.validate_args(&[param("show", "false")]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ValidationError>(),
+            Some(ValidationError::RequiredParameter(name)) if name == "title"
+        ));
+    }
+}
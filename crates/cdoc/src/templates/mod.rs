@@ -260,7 +260,7 @@ impl TemplateManager {
         &self,
         id: &str,
         args: &[RenderedParam],
-    ) -> anyhow::Result<Vec<anyhow::Result<()>>> {
+    ) -> anyhow::Result<Vec<anyhow::Result<Value>>> {
         let tp = self
             .get_template(id, TemplateType::Shortcode)
             .context(format!("Invalid shortcode identifier '{}'", id))?;
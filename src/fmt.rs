@@ -0,0 +1,100 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use cdoc_parser::ast::printer::print_document;
+use cdoc_parser::ast::{for_each_span_mut, Ast};
+use cdoc_parser::document::Document;
+use cdoc_parser::Span;
+
+/// Result of formatting the documents in the content directory.
+#[derive(Default)]
+pub(crate) struct FormatReport {
+    /// Documents that were not formatted. They are only rewritten if `check` is false.
+    pub(crate) unformatted: Vec<PathBuf>,
+    /// Documents that could not be formatted, together with the reason.
+    pub(crate) errors: Vec<(PathBuf, anyhow::Error)>,
+}
+
+/// Formats the markdown documents in the content directory. A document that cannot be formatted
+/// is recorded in the report and the remaining documents are still processed.
+pub(crate) fn format_content(content_dir: &Path, check: bool) -> anyhow::Result<FormatReport> {
+    let mut files = Vec::new();
+    collect_markdown(content_dir, &mut files)?;
+
+    let mut report = FormatReport::default();
+    for file in files {
+        let result = fs::read_to_string(&file)
+            .map_err(anyhow::Error::from)
+            .and_then(|input| {
+                let output = format_source(&input)?;
+                if output != input && !check {
+                    fs::write(&file, &output)?;
+                }
+                Ok(output != input)
+            });
+        match result {
+            Ok(true) => report.unformatted.push(file),
+            Ok(false) => {}
+            Err(e) => report.errors.push((file, e)),
+        }
+    }
+
+    Ok(report)
+}
+
+/// Prints a document in the canonical style. An error is returned if the document does not parse
+/// or if the formatted output does not parse to the same document.
+fn format_source(input: &str) -> anyhow::Result<String> {
+    let doc: Document<Ast> = Document::try_from(input).context("Could not parse the document")?;
+    let output = print_document(&doc);
+
+    if output != input {
+        let formatted: Document<Ast> =
+            Document::try_from(output.as_str()).context("Formatting produced invalid syntax")?;
+        if without_spans(doc) != without_spans(formatted) {
+            bail!("Formatting would change the document, it was left unchanged");
+        }
+    }
+    Ok(output)
+}
+
+/// The document without its source and spans, such that documents can be compared by content.
+fn without_spans(mut doc: Document<Ast>) -> Document<Ast> {
+    for_each_span_mut(&mut doc.content.blocks, &mut |span| *span = Span::default());
+    doc.content.source = Default::default();
+    doc
+}
+
+fn collect_markdown(dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            collect_markdown(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "md") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spaced_parameters() {
+        let output = format_source("Text #cmd(a , c = {abc}, d = x y, e=\"{y}\").\n").unwrap();
+        assert_eq!("Text #cmd(a, c={abc}, d=x y, e=\"{y}\").\n", output);
+    }
+
+    #[test]
+    fn invalid_documents() {
+        let err = format_source("---\ntitle: [\n---\nText\n").unwrap_err();
+        assert!(format!("{err:#}").contains("Could not parse"), "{err:#}");
+    }
+}
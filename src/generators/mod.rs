@@ -1,22 +1,67 @@
 use crate::project::config::Mode;
-use anyhow::{anyhow, Context as AContext};
+use anyhow::{anyhow, bail, Context as AContext};
+use cdoc::bibliography::{parse_csl_json, to_bibtex};
 use cdoc::config::Format;
+use cdoc::glossary::GlossaryEntry;
 use cdoc::renderers::RenderResult;
 use cdoc::templates::{TemplateManager, TemplateType};
-use cdoc_parser::document::Document;
+use cdoc_parser::document::{Document, Metadata};
 use indicatif::{ParallelProgressIterator, ProgressBar};
 use rayon::prelude::*;
 use std::fs;
 use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::ops::Deref;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 
 use tera::Context;
 
 use crate::project::config::ProjectConfig;
 use crate::project::{ContentItemDescriptor, ContentResultX, ProjectItemContentVec};
 
+/// Path of the output file of a document relative to the build folder of the format.
+pub fn output_path(
+    root: &Path,
+    doc_path: &Path,
+    doc_id: &str,
+    is_section: bool,
+    format: &dyn Format,
+) -> PathBuf {
+    let relative_doc_path = doc_path
+        .strip_prefix(root.join("content"))
+        .unwrap_or(doc_path);
+    let id = if is_section { "index" } else { doc_id };
+    relative_doc_path.with_file_name(format!("{}.{}", id, format.extension()))
+}
+
+/// Path of a bibliography file in the build folder. Only the normal components of the configured
+/// path are kept, so files outside the project folder (e.g. `../refs.bib`) are still written
+/// inside the build folder. CSL-JSON files are converted to BibTeX (`refs.json` becomes
+/// `refs.json.bib`) since biblatex cannot read them.
+fn bib_resource(bib: &Path) -> anyhow::Result<PathBuf> {
+    let relative: PathBuf = bib
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect();
+    if relative.file_name().is_none() {
+        bail!("invalid bibliography path {}", bib.display());
+    }
+    Ok(match relative.extension() {
+        Some(ext) if ext == "json" => relative.with_extension("json.bib"),
+        _ => relative,
+    })
+}
+
+fn write_bib_resource(source: &Path, target: &Path) -> anyhow::Result<()> {
+    if source.extension().is_some_and(|ext| ext == "json") {
+        let entries = parse_csl_json(&fs::read_to_string(source)?)?;
+        fs::write(target, to_bibtex(&entries))?;
+    } else {
+        fs::copy(source, target)?;
+    }
+    Ok(())
+}
+
 /// This type is responsible for writing the final output for a given format.
 /// For formats that use layouts, this is where the document content is rendered into the layout
 /// template.
@@ -36,6 +81,9 @@ pub struct Generator<'a> {
     pub build_dir: PathBuf,
     /// Output format (used to determine whether to use layout and for the template manager).
     pub format: &'a dyn Format,
+    /// Glossary terms and their uses, included in layout templates and used for the glossary
+    /// pages.
+    pub glossary: &'a [GlossaryEntry],
 }
 
 impl Generator<'_> {
@@ -45,14 +93,17 @@ impl Generator<'_> {
         doc_path: &PathBuf,
         is_section: bool,
     ) -> anyhow::Result<impl Write> {
-        let relative_doc_path = doc_path
-            .strip_prefix(self.root.join("content").as_path())
-            .unwrap_or(doc_path);
-        let mut html_build_dir = self.build_dir.join(relative_doc_path);
-        html_build_dir.pop(); // Pop filename
-
-        let id = if is_section { "index" } else { doc_id };
-        let section_build_path = html_build_dir.join(format!("{}.{}", id, self.format.extension()));
+        let section_build_path = self.build_dir.join(output_path(
+            &self.root,
+            doc_path,
+            doc_id,
+            is_section,
+            self.format,
+        ));
+        let html_build_dir = section_build_path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| self.build_dir.clone());
 
         // println!("sec path: {}", section_build_path.display());
 
@@ -96,6 +147,10 @@ impl Generator<'_> {
         let mut base = Context::new();
         base.insert("project", &self.project);
         base.insert("config", &self.config);
+        base.insert("glossary", &self.glossary);
+
+        self.generate_glossary(&base)?;
+        self.generate_main(&base)?;
 
         let res = project_vec
             // .iter()
@@ -161,6 +216,124 @@ impl Generator<'_> {
         let mut base = Context::new();
         base.insert("project", &self.project);
         base.insert("config", &self.config);
+        base.insert("glossary", &self.glossary);
         self.process(&base, doc, doc_info)
     }
+
+    /// Writes the files that combine the documents into a single output (the `main` and
+    /// `preamble` layouts, e.g. `main.tex` and `preamble.tex` for LaTeX) at the root of the build
+    /// folder. The bibliography files are written along with them so that the main file can be
+    /// compiled in the build folder. Their paths in the build folder are passed to the layouts as
+    /// `bib_resources`.
+    fn generate_main(&self, base: &Context) -> anyhow::Result<()> {
+        let prefix = self.format.template_prefix();
+        let mut generated = false;
+        let resources = self
+            .config
+            .bibliography
+            .iter()
+            .map(|bib| Ok((bib, bib_resource(bib)?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let resource_names: Vec<String> = resources
+            .iter()
+            .map(|(_, target)| target.to_string_lossy().replace('\\', "/"))
+            .collect();
+        for id in ["main", "preamble"] {
+            let supported = self
+                .templates
+                .get_template(id, TemplateType::Layout)
+                .is_ok_and(|tp| tp.get_format(prefix).is_ok());
+            if !supported {
+                continue;
+            }
+
+            let mut args = base.clone();
+            args.insert("mode", &self.mode);
+            args.insert("bib_resources", &resource_names);
+
+            fs::create_dir_all(&self.build_dir)?;
+            let path = self
+                .build_dir
+                .join(format!("{}.{}", id, self.format.extension()));
+            let file = File::create(&path).with_context(|| format!("{}", path.display()))?;
+            self.templates.render(
+                id,
+                prefix,
+                TemplateType::Layout,
+                &args,
+                BufWriter::new(file),
+            )?;
+            generated = true;
+        }
+
+        if generated {
+            for (bib, target) in resources {
+                let target = self.build_dir.join(target);
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                write_bib_resource(&self.root.join(bib), &target)
+                    .with_context(|| format!("Could not copy bibliography {}", bib.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the glossary page (`glossary`) and the index of terms (`term_index`) at the root of
+    /// the build folder. The pages are rendered with the `glossary` and `term_index` builtin
+    /// templates and are only created for formats that use a layout and are supported by the
+    /// templates.
+    fn generate_glossary(&self, base: &Context) -> anyhow::Result<()> {
+        let Some(layout_id) = self.format.layout() else {
+            return Ok(());
+        };
+        if self.glossary.is_empty() {
+            return Ok(());
+        }
+
+        let prefix = self.format.template_prefix();
+        for (id, title) in [("glossary", "Glossary"), ("term_index", "Index")] {
+            let supported = self
+                .templates
+                .get_template(id, TemplateType::Builtin)
+                .is_ok_and(|tp| tp.get_format(prefix).is_ok());
+            if !supported {
+                continue;
+            }
+
+            let mut args = Context::new();
+            args.insert("entries", &self.glossary);
+            let mut content = Vec::new();
+            self.templates
+                .render(id, prefix, TemplateType::Builtin, &args, &mut content)?;
+
+            let doc: Document<RenderResult> = Document {
+                meta: Metadata {
+                    title: title.to_string(),
+                    ..Default::default()
+                },
+                content: String::from_utf8(content)?.into(),
+                code_outputs: Default::default(),
+            };
+
+            let mut args = base.clone();
+            args.insert("current_path", &["root", id]);
+            args.insert("doc", &doc);
+            args.insert("mode", &self.mode);
+
+            fs::create_dir_all(&self.build_dir)?;
+            let path = self
+                .build_dir
+                .join(format!("{}.{}", id, self.format.extension()));
+            let file = File::create(&path).with_context(|| format!("{}", path.display()))?;
+            self.templates.render(
+                &layout_id,
+                prefix,
+                TemplateType::Layout,
+                &args,
+                BufWriter::new(file),
+            )?;
+        }
+        Ok(())
+    }
 }
@@ -20,6 +20,10 @@ use notify_debouncer_mini::{
 };
 #[cfg(feature = "server")]
 use penguin::Server;
+#[cfg(feature = "server")]
+use std::collections::HashSet;
+#[cfg(feature = "server")]
+use std::sync::{Arc, Mutex};
 
 use semver::{Version, VersionReq};
 
@@ -29,6 +33,7 @@ use courses::project::{configure_project, from_vec, ContentItem};
 
 use courses::built_info;
 
+mod fmt;
 mod setup;
 
 #[derive(Parser, Debug)]
@@ -97,6 +102,15 @@ enum Commands {
         #[arg(short, long)]
         version: Option<VersionReq>,
     },
+    /// Format the markdown documents of the project in the canonical style.
+    Fmt {
+        /// Optional path to the project root directory (that contains config.yml).
+        #[arg(short, long)]
+        path: Option<PathBuf>,
+        /// Only list the documents that are not formatted and exit with an error if there are any.
+        #[arg(long)]
+        check: bool,
+    },
 }
 
 fn path_with_default(path: Option<PathBuf>) -> anyhow::Result<PathBuf> {
@@ -192,6 +206,38 @@ async fn cli_run() -> anyhow::Result<()> {
             let _status = res.wait()?;
             Ok(())
         }
+        Commands::Fmt { path, check } => {
+            let path = path_with_default(path)?;
+            let report = fmt::format_content(&path.join("content"), check)?;
+            let unformatted = report.unformatted;
+
+            for file in &unformatted {
+                let file = file.strip_prefix(&path).unwrap_or(file);
+                if check {
+                    println!("{} {}", style("unformatted").yellow(), file.display());
+                } else {
+                    println!("{} {}", style("formatted").green(), file.display());
+                }
+            }
+            for (file, e) in &report.errors {
+                let file = file.strip_prefix(&path).unwrap_or(file);
+                eprintln!("{} {}: {:#}", style("error").red(), file.display(), e);
+            }
+
+            if !report.errors.is_empty() {
+                return Err(anyhow!(
+                    "{} documents could not be formatted.",
+                    report.errors.len()
+                ));
+            }
+            if check && !unformatted.is_empty() {
+                return Err(anyhow!(
+                    "{} documents are not formatted. Run `courses fmt` to format them.",
+                    unformatted.len()
+                ));
+            }
+            Ok(())
+        }
         Commands::Build { path, profile, .. } => {
             let current_time = SystemTime::now();
             let (mut pipeline, _) = init_and_build(path, profile)?;
@@ -248,6 +294,14 @@ async fn cli_run() -> anyhow::Result<()> {
                 pipeline.project_config.url_prefix
             );
 
+            // Included files outside the watched folders are watched individually. The notifier
+            // is shared with its event handler so that files that are included by a rebuild are
+            // also watched.
+            let included_files = unwatched_includes(&pipeline, &absolute_path);
+            let mut watched_includes: HashSet<PathBuf> = included_files.iter().cloned().collect();
+            let shared_debouncer: SharedDebouncer = Arc::new(Mutex::new(None));
+            let handler_debouncer = shared_debouncer.clone();
+
             let notify_config =
                 notify_debouncer_mini::Config::default().with_timeout(Duration::from_millis(100));
 
@@ -259,7 +313,9 @@ async fn cli_run() -> anyhow::Result<()> {
                         // file changes on any operating system.
                         if let DebouncedEventKind::Any = &event.kind {
                             let full_path = &event.path;
-                            let p = full_path.strip_prefix(absolute_path.as_path()).unwrap();
+                            let p = full_path
+                                .strip_prefix(absolute_path.as_path())
+                                .unwrap_or(full_path);
 
                             // Ensure that temporary files (that include ~) created by some editors are skipped
                             if let Some(ext) = p.extension() {
@@ -267,9 +323,12 @@ async fn cli_run() -> anyhow::Result<()> {
                                     let current_time = SystemTime::now();
                                     // Determine whether change was in content or template
                                     if p.starts_with(Path::new("content")) {
-                                        // Content change only results in rebuilding the changed file itself.
+                                        // Content change only results in rebuilding the changed file itself
+                                        // and the documents that include it.
                                         let res = pipeline.build_single(full_path.to_path_buf());
                                         err_print(res);
+                                        let res = pipeline.build_dependents(full_path);
+                                        err_print(res);
                                     } else if p.starts_with(Path::new("templates")) {
                                         // Template change requires reloading templates and rebuilding the whole
                                         // project.
@@ -281,6 +340,26 @@ async fn cli_run() -> anyhow::Result<()> {
                                     } else if p.starts_with(Path::new("scripts")) {
                                         let res = pipeline.build_all(true);
                                         err_print(res);
+                                    } else {
+                                        // Files included from outside the content folder.
+                                        let res = pipeline.build_dependents(full_path);
+                                        err_print(res);
+                                    }
+
+                                    let new_includes: Vec<PathBuf> =
+                                        unwatched_includes(&pipeline, &absolute_path)
+                                            .into_iter()
+                                            .filter(|f| watched_includes.insert(f.clone()))
+                                            .collect();
+                                    if let Some(debouncer) =
+                                        handler_debouncer.lock().unwrap().as_mut()
+                                    {
+                                        for file in new_includes {
+                                            let res = debouncer
+                                                .watcher()
+                                                .watch(file.as_path(), RecursiveMode::NonRecursive);
+                                            err_print(res.map_err(Into::into));
+                                        }
                                     }
 
                                     // Reload the webpage to show the updated content.
@@ -317,6 +396,12 @@ async fn cli_run() -> anyhow::Result<()> {
                 path.as_path().join("scripts").as_path(),
                 RecursiveMode::Recursive,
             )?;
+            for file in included_files {
+                debouncer
+                    .watcher()
+                    .watch(file.as_path(), RecursiveMode::NonRecursive)?;
+            }
+            *shared_debouncer.lock().unwrap() = Some(debouncer);
 
             server.await?;
 
@@ -473,6 +558,23 @@ async fn cli_run() -> anyhow::Result<()> {
     }
 }
 
+#[cfg(feature = "server")]
+type SharedDebouncer = Arc<Mutex<Option<Debouncer<RecommendedWatcher>>>>;
+
+/// Included files that are not inside one of the folders watched by the dev-server.
+#[cfg(feature = "server")]
+fn unwatched_includes(pipeline: &Pipeline, root: &Path) -> Vec<PathBuf> {
+    pipeline
+        .included_files()
+        .into_iter()
+        .filter(|f| {
+            ["content", "templates", "scripts"]
+                .iter()
+                .all(|dir| !f.starts_with(root.join(dir)))
+        })
+        .collect()
+}
+
 // Print errors in red if present.
 fn err_print(res: anyhow::Result<()>) {
     match res {
@@ -495,5 +597,10 @@ fn err_print(res: anyhow::Result<()>) {
 #[cfg_attr(feature = "server", tokio::main)]
 #[cfg_attr(feature = "no-server", pollster::main)]
 async fn main() {
-    err_print(cli_run().await)
+    let res = cli_run().await;
+    let failed = res.is_err();
+    err_print(res);
+    if failed {
+        std::process::exit(1);
+    }
 }
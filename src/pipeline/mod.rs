@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs;
 use std::io::{BufWriter, Cursor};
 use std::path::{Path, PathBuf};
@@ -16,11 +16,19 @@ use syntect::highlighting::ThemeSet;
 use syntect::parsing::SyntaxSet;
 use tera::{Context, Filter, Function};
 
+use cdoc::bibliography::Bibliography;
 use cdoc::config::Format;
+use cdoc::glossary::{collect_terms, Glossary, GlossaryEntry, TermIndex, TermUse};
+use cdoc::numbering::{content_position, DocumentNumbering, ProjectNumbering};
 
+use cdoc::preprocessors::conditionals::Conditionals;
+use cdoc::preprocessors::include::Includes;
 use cdoc::preprocessors::PreprocessorContext;
 
-use cdoc::renderers::{DocumentRenderer, RenderContext, RenderResult};
+use cdoc::renderers::{
+    collect_references, label_counts, references_by_type, DocumentRenderer, ProjectContext,
+    ProjectReferences, ReferenceIndex, RenderContext, RenderResult,
+};
 use cdoc::templates::TemplateManager;
 use image::io::Reader as ImageReader;
 use mover::Mover;
@@ -28,7 +36,7 @@ use mover::Mover;
 use cdoc::renderers::generic::GenericRenderer;
 use rayon::prelude::*;
 
-use crate::generators::Generator;
+use crate::generators::{output_path, Generator};
 use crate::project::config::{Mode, Profile, ProjectConfig};
 use crate::project::{
     from_vec, ContentItem, ContentItemDescriptor, DocumentDescriptor, ProjectItemContentVec,
@@ -37,10 +45,12 @@ use crate::project::{
 
 use crate::project::caching::Cache;
 use cdoc::renderers::extensions::build_extensions;
-use cdoc_parser::ast::Ast;
+use cdoc_parser::ast::{Ast, Reference};
 use cdoc_parser::document::Document;
+use cdoc_parser::Diagnostic;
 use cowstr::CowStr;
 use lazy_static::lazy_static;
+use linked_hash_map::LinkedHashMap;
 use std::borrow::Borrow;
 
 mod mover;
@@ -112,6 +122,67 @@ pub struct Pipeline {
 
     templates: TemplateManager,
     cached_contexts: Arc<Mutex<HashMap<String, ProjectItemVec>>>,
+    /// Variables available in documents (the `custom` configuration with profile overrides).
+    variables: HashMap<String, Value>,
+    /// Maps included files to the documents (and formats) that include them.
+    includes: Arc<Mutex<IncludedBy>>,
+    /// Entries that can be cited in documents.
+    bibliography: Arc<Bibliography>,
+    /// Terms defined in the project's `glossary.yml`.
+    glossary: Arc<Glossary>,
+    /// Labels and term uses of all documents for each format, kept for rebuilding single files.
+    indices: Arc<Mutex<HashMap<String, ProjectIndex>>>,
+    /// Labels and term uses of each document by format and document path, so that documents
+    /// that have not changed are not parsed again on the next build.
+    document_indices: Arc<Mutex<DocumentIndices>>,
+    /// Warnings of the current build (shown after the errors).
+    warnings: Arc<Mutex<BTreeSet<String>>>,
+}
+
+/// Labels and term uses of a document, collected before documents are rendered.
+#[derive(Clone)]
+struct DocumentIndex {
+    title: String,
+    references: LinkedHashMap<String, Reference>,
+    terms: Vec<TermUse>,
+}
+
+impl DocumentIndex {
+    /// Number of labelled elements of each type.
+    fn counts(&self) -> HashMap<String, usize> {
+        label_counts(&self.references)
+    }
+}
+
+/// Labels and term uses of each document by format name and document path (`None` for documents
+/// that are not rendered for the format).
+type DocumentIndices = HashMap<(String, PathBuf), Option<DocumentIndex>>;
+/// Documents (and the formats they were built for) that include a file, by the included file.
+type IncludedBy = HashMap<PathBuf, HashSet<(PathBuf, String)>>;
+
+/// Labels, term uses and numbering of all documents for a single format.
+#[derive(Clone, Default)]
+struct ProjectIndex {
+    references: ReferenceIndex,
+    terms: TermIndex,
+    /// Numbering of the labelled elements of each document (by document key).
+    numbering: HashMap<String, DocumentNumbering>,
+}
+
+/// A parsed document. Documents are rendered once all documents have been parsed so that
+/// references to other documents can be resolved.
+enum ParsedDocument {
+    Ast(Document<Ast>),
+    /// Output of documents that are not rendered for the format (or not at all).
+    Output(Option<Document<RenderResult>>),
+}
+
+/// Path with `/` separators (used for document keys and links).
+fn slash_path(path: &Path) -> String {
+    path.iter()
+        .map(|c| c.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
 }
 
 pub fn print_err<T>(res: anyhow::Result<T>) -> Option<T> {
@@ -168,6 +239,27 @@ impl Pipeline {
             Err(_) => Cache::default(),
         };
 
+        let variables = config
+            .custom
+            .iter()
+            .chain(p.custom.iter())
+            .map(|(k, v)| Ok((k.clone(), serde_json::to_value(v)?)))
+            .collect::<anyhow::Result<HashMap<String, Value>>>()?;
+
+        let bibliography_files: Vec<PathBuf> = config
+            .bibliography
+            .iter()
+            .map(|p| project_path.as_ref().join(p))
+            .collect();
+        let bibliography = Bibliography::load(&bibliography_files)?;
+
+        let glossary_path = project_path.as_ref().join("glossary.yml");
+        let glossary = if glossary_path.exists() {
+            Glossary::load(&glossary_path)?
+        } else {
+            Glossary::default()
+        };
+
         let mut pipeline = Pipeline {
             profile: p,
             profile_name: profile,
@@ -177,6 +269,13 @@ impl Pipeline {
             cache_info,
             templates: template_manager,
             cached_contexts: Arc::new(Mutex::new(HashMap::new())),
+            includes: Arc::new(Mutex::new(HashMap::new())),
+            glossary: Arc::new(glossary),
+            indices: Arc::new(Mutex::new(HashMap::new())),
+            document_indices: Arc::new(Mutex::new(HashMap::new())),
+            warnings: Arc::new(Mutex::new(BTreeSet::new())),
+            bibliography: Arc::new(bibliography),
+            variables,
         };
 
         let p2 = pipeline.clone();
@@ -208,7 +307,9 @@ impl Pipeline {
                     ))
                     .expect("problems!");
 
-                    let mut ctx = self.get_render_context(&mut doc, format.borrow()).unwrap();
+                    let mut ctx = self
+                        .get_render_context(&mut doc, format.borrow(), None, None)
+                        .unwrap();
                     let mut renderer = GenericRenderer::default();
                     let res = renderer
                         .render_doc(
@@ -235,6 +336,8 @@ impl Pipeline {
         &'a self,
         doc: &'a mut Document<Ast>,
         format: &'a dyn Format,
+        references: Option<ProjectReferences<'a>>,
+        numbering: Option<&'a DocumentNumbering>,
     ) -> anyhow::Result<RenderContext<'a>> {
         let mut meta = Context::default();
         meta.insert("config", &self.project_config);
@@ -250,6 +353,12 @@ impl Pipeline {
             self.project_config.notebook_meta.as_ref().unwrap(),
             format,
             self.profile.parser.settings.clone(),
+            ProjectContext {
+                references,
+                bibliography: Some(&self.bibliography),
+                glossary: Some(&self.glossary),
+                numbering,
+            },
         )
     }
 
@@ -284,10 +393,11 @@ impl Pipeline {
         })?;
 
         let mut all_errors = Vec::new();
+        self.warnings.lock().unwrap().clear();
 
         for format in self.get_formats_or_default().clone() {
             print!("format: {}", style(&format).bold());
-            let output = self.process_document(&loaded.doc, format.as_ref());
+            let output = self.process_single(&loaded, format.as_ref());
 
             match output {
                 Err(e) => {
@@ -313,6 +423,7 @@ impl Pipeline {
                         //     project.clone(),
                         // );
 
+                        let glossary = self.glossary_entries(format.as_ref());
                         let mut ctx = Generator {
                             root: self.project_path.clone(),
                             project: &from_vec(&project),
@@ -321,6 +432,7 @@ impl Pipeline {
                             mode: self.profile.mode,
                             build_dir: self.get_build_path(format.as_ref()),
                             format: format.as_ref(),
+                            glossary: &glossary,
                         };
                         ctx.generate_single(output, &item2)?;
                         self.cache_info.update_build_status(
@@ -339,6 +451,7 @@ impl Pipeline {
         }
 
         println!("{}", style("-".repeat(60)).blue());
+        self.print_warnings();
         if all_errors.is_empty() {
             println!("{}", style("Success").green().bold());
         } else {
@@ -385,6 +498,42 @@ impl Pipeline {
     //     project
     // }
 
+    /// Rebuild every document that includes the given file.
+    pub fn build_dependents(&mut self, path: &Path) -> anyhow::Result<()> {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let mut dependents: Vec<PathBuf> = self
+            .includes
+            .lock()
+            .unwrap()
+            .get(&path)
+            .map(|docs| docs.iter().map(|(doc, _)| doc.clone()).collect())
+            .unwrap_or_default();
+        dependents.sort();
+        dependents.dedup();
+
+        for doc in dependents {
+            self.build_single(doc)?;
+        }
+        Ok(())
+    }
+
+    /// All files that are included by a document in the project.
+    pub fn included_files(&self) -> Vec<PathBuf> {
+        self.includes.lock().unwrap().keys().cloned().collect()
+    }
+
+    fn record_includes(&self, doc_path: &Path, format: &str, included: Vec<PathBuf>) {
+        let key = (doc_path.to_path_buf(), format.to_string());
+        let mut includes = self.includes.lock().unwrap();
+        for docs in includes.values_mut() {
+            docs.remove(&key);
+        }
+        includes.retain(|_, docs| !docs.is_empty());
+        for file in included {
+            includes.entry(file).or_default().insert(key.clone());
+        }
+    }
+
     fn doc_from_path(&self, path: PathBuf) -> anyhow::Result<ContentItemDescriptor<()>> {
         let doc_path = path
             .as_path()
@@ -454,6 +603,7 @@ impl Pipeline {
         // }
 
         let loaded = self.load_files(ignore_cache)?;
+        self.warnings.lock().unwrap().clear();
 
         println!("{}", style("=".repeat(60)).blue());
         println!(
@@ -498,7 +648,18 @@ impl Pipeline {
                     style(format.name()).bold(),
                     style("parsing").blue()
                 ));
-                let output = self.process_all(loaded.clone(), format.as_ref(), bar.clone());
+                let output = match self.process_all(loaded.clone(), format.as_ref(), bar.clone()) {
+                    Ok(output) => output,
+                    Err(e) => {
+                        bar.finish_with_message(format!(
+                            "{} {}",
+                            style(format.name()).bold(),
+                            style("failed").red()
+                        ));
+                        all_errs.lock().unwrap().push(e);
+                        return;
+                    }
+                };
 
                 // let mut errs = Vec::new();
                 let output: ProjectItemContentVec = output
@@ -525,6 +686,7 @@ impl Pipeline {
                 // format_errs.append(&mut errs.lock().unwrap());
 
                 let project_full = from_vec(&proj);
+                let glossary = self.glossary_entries(format.as_ref());
                 let context = Generator {
                     root: self.project_path.to_path_buf(),
                     project: &project_full,
@@ -533,6 +695,7 @@ impl Pipeline {
                     config: self.project_config.clone(),
                     format: format.as_ref(),
                     build_dir: self.get_build_path(format.as_ref()),
+                    glossary: &glossary,
                 };
 
                 self.cached_contexts
@@ -601,6 +764,7 @@ impl Pipeline {
         }
 
         println!("{}", style("-".repeat(60)).blue());
+        self.print_warnings();
         if all_errs.is_empty() {
             println!("{}", style("Project built without errors").green().bold());
         } else {
@@ -667,18 +831,45 @@ impl Pipeline {
         project: Vec<ContentItemDescriptor<Option<String>>>,
         format: &dyn Format,
         bar: ProgressBar,
-    ) -> ProjectItemVecErr {
+    ) -> anyhow::Result<ProjectItemVecErr> {
+        let failed = |i: &ContentItemDescriptor<Option<String>>| {
+            format!(
+                "Failed to process document – {}",
+                style(format!("content/{}", i.doc.path.display())).italic()
+            )
+        };
+
+        let parsed = project
+            .par_iter()
+            .map(|i| {
+                self.parse_document(&i.doc, format)
+                    .with_context(|| failed(i))
+            })
+            .collect::<Vec<anyhow::Result<(ParsedDocument, Option<DocumentIndex>)>>>();
+
+        let mut index = ProjectIndex::default();
+        let mut numbering = ProjectNumbering::new(self.project_config.numbering.clone());
+        for (i, res) in project.iter().zip(&parsed) {
+            if let Ok((_, Some(doc_index))) = res {
+                let doc_numbering =
+                    numbering.next_document(content_position(&i.path_idx), &doc_index.counts());
+                self.index_document(&mut index, i, format, doc_index, doc_numbering);
+            }
+        }
+        self.indices
+            .lock()
+            .unwrap()
+            .insert(format.name().to_string(), index.clone());
+        index.references.check_duplicates()?;
+
         let res = project
             .par_iter()
-            // .iter()
+            .zip(parsed)
             .progress_with(bar)
-            .map(|i| {
-                let res = self.process_document(&i.doc, format).with_context(|| {
-                    format!(
-                        "Failed to process document – {}",
-                        style(format!("content/{}", i.doc.path.display())).italic()
-                    )
-                });
+            .map(|(i, parsed)| {
+                let res = parsed
+                    .and_then(|(parsed, _)| self.render_parsed(&i.doc, parsed, format, &index))
+                    .with_context(|| failed(i));
 
                 res.map(|res| ContentItemDescriptor {
                     is_section: i.is_section,
@@ -695,69 +886,261 @@ impl Pipeline {
             .collect::<Vec<anyhow::Result<ContentItemDescriptor<Option<Document<RenderResult>>>>>>(
             );
 
-        res
+        Ok(res)
     }
 
-    fn process_document(
+    /// Processes a single document using the index of the last full build (updated with the
+    /// labels and terms of the document). The document keeps its numbering from the last full
+    /// build.
+    fn process_single(
         &self,
-        item: &DocumentDescriptor<Option<String>>,
+        item: &ContentItemDescriptor<Option<String>>,
         format: &dyn Format,
     ) -> anyhow::Result<Option<Document<RenderResult>>> {
-        if let Some(content) = item.content.as_ref() {
-            let doc = item
-                .format
-                .loader()
-                .load(content, self.profile.mode == Mode::Draft)?;
-
-            match doc {
-                None => Ok(None),
-                Some(doc) => {
-                    if format.no_parse() {
-                        Ok(Some(Document {
-                            meta: doc.meta,
-                            content: "".into(),
-                            code_outputs: doc.code_outputs,
-                        }))
-                    } else if self.profile.mode != Mode::Draft && doc.meta.draft {
-                        Ok(Some(doc.map(|_| CowStr::new())))
-                    } else if !doc
-                        .meta
-                        .exclude_outputs
-                        .as_ref()
-                        .map(|o| o.contains(&format.name().to_string()))
-                        .unwrap_or_default()
-                    {
-                        let processor_ctx = PreprocessorContext {
-                            templates: &self.templates,
-                            output_format: format,
-                            project_root: self.project_path.clone(),
-                        };
+        let (parsed, doc_index) = self.parse_document(&item.doc, format)?;
+
+        let index = {
+            let mut indices = self.indices.lock().unwrap();
+            let index = indices.entry(format.name().to_string()).or_default();
+            if let Some(doc_index) = &doc_index {
+                let numbering = index
+                    .numbering
+                    .get(&self.document_key(&item.doc.path))
+                    .cloned()
+                    .unwrap_or_default();
+                self.index_document(index, item, format, doc_index, numbering);
+            }
+            index.clone()
+        };
+        index.references.check_duplicates()?;
 
-                        let mut res = self.profile.parser.parse(doc, &processor_ctx)?;
+        self.render_parsed(&item.doc, parsed, format, &index)
+    }
 
-                        // let res = print_err(res)?;
+    /// Key of a document in the reference index: its path in the content folder without
+    /// extension.
+    fn document_key(&self, doc_path: &Path) -> String {
+        let relative = doc_path
+            .strip_prefix(self.project_path.join("content"))
+            .unwrap_or(doc_path);
+        slash_path(&relative.with_extension(""))
+    }
 
-                        let mut ctx = self.get_render_context(&mut res, format)?;
-                        let mut renderer = format.renderer();
+    fn index_document<T>(
+        &self,
+        index: &mut ProjectIndex,
+        item: &ContentItemDescriptor<T>,
+        format: &dyn Format,
+        doc_index: &DocumentIndex,
+        numbering: DocumentNumbering,
+    ) {
+        let key = self.document_key(&item.doc.path);
+        let output = slash_path(&output_path(
+            &self.project_path,
+            &item.doc.path,
+            &item.doc.id,
+            item.is_section,
+            format,
+        ));
+        index
+            .references
+            .insert_document(&key, &output, &doc_index.references, &numbering);
+        index.numbering.insert(key.clone(), numbering);
+        index
+            .terms
+            .insert_document(&key, &output, &doc_index.title, &doc_index.terms);
+
+        let relative = item
+            .doc
+            .path
+            .strip_prefix(self.project_path.join("content"))
+            .unwrap_or(&item.doc.path);
+        for term in doc_index
+            .terms
+            .iter()
+            .filter(|t| self.glossary.get(&t.term).is_none())
+        {
+            self.warnings.lock().unwrap().insert(format!(
+                "undefined term '{}' in content/{} at {}",
+                term.term,
+                relative.display(),
+                term.span
+            ));
+        }
+    }
 
-                        Ok(Some(
-                            renderer.render_doc(
-                                &mut ctx,
-                                build_extensions(
-                                    self.profile
-                                        .render_extensions
-                                        .get(format.name())
-                                        .unwrap_or(&vec![]),
-                                )?,
-                            )?,
-                        ))
-                    } else {
-                        Ok(None)
-                    }
+    /// Glossary entries with the term uses of the last build of the given format.
+    fn glossary_entries(&self, format: &dyn Format) -> Vec<GlossaryEntry> {
+        self.indices
+            .lock()
+            .unwrap()
+            .get(format.name())
+            .map(|index| index.terms.entries(&self.glossary))
+            .unwrap_or_default()
+    }
+
+    /// Syntax problems that the parser recovered from are reported as warnings so that the rest
+    /// of the document is still built.
+    fn record_diagnostics(&self, path: &Path, diagnostics: &[Diagnostic]) {
+        let path = path
+            .strip_prefix(self.project_path.join("content"))
+            .unwrap_or(path);
+        let mut warnings = self.warnings.lock().unwrap();
+        for diagnostic in diagnostics {
+            warnings.insert(format!(
+                "syntax error in content/{} at {}",
+                path.display(),
+                diagnostic
+            ));
+        }
+    }
+
+    fn print_warnings(&self) {
+        for warning in self.warnings.lock().unwrap().iter() {
+            eprintln!("{} {}", style("Warning:").yellow().bold(), warning);
+        }
+    }
+
+    /// Parses a document and collects its labels and term uses. Documents that have not changed
+    /// since the last build are only parsed for these, and only if they have not been collected
+    /// by an earlier build of this pipeline.
+    fn parse_document(
+        &self,
+        item: &DocumentDescriptor<Option<String>>,
+        format: &dyn Format,
+    ) -> anyhow::Result<(ParsedDocument, Option<DocumentIndex>)> {
+        let content = match item.content.as_ref() {
+            Some(content) => content.clone(),
+            None => {
+                let cached = self
+                    .document_indices
+                    .lock()
+                    .unwrap()
+                    .get(&(format.name().to_string(), item.path.clone()))
+                    .cloned();
+                if let Some(doc_index) = cached {
+                    return Ok((ParsedDocument::Output(None), doc_index));
                 }
+                let path = self.project_path.join("content").join(&item.path);
+                fs::read_to_string(&path)
+                    .with_context(|| format!("Error loading document {}", path.display()))?
             }
-        } else {
-            Ok(None)
+        };
+
+        let mut parsed = self.parse_source(item, &content, format)?;
+        let doc_index = match &mut parsed {
+            ParsedDocument::Ast(doc) => {
+                let mut references = collect_references(doc)?;
+                references_by_type(&mut references);
+                Some(DocumentIndex {
+                    title: doc.meta.title.clone(),
+                    references,
+                    terms: collect_terms(doc)?,
+                })
+            }
+            ParsedDocument::Output(_) => None,
+        };
+        self.document_indices.lock().unwrap().insert(
+            (format.name().to_string(), item.path.clone()),
+            doc_index.clone(),
+        );
+
+        if item.content.is_none() {
+            parsed = ParsedDocument::Output(None);
         }
+        Ok((parsed, doc_index))
+    }
+
+    fn parse_source(
+        &self,
+        item: &DocumentDescriptor<Option<String>>,
+        content: &str,
+        format: &dyn Format,
+    ) -> anyhow::Result<ParsedDocument> {
+        let loaded = item
+            .format
+            .loader()
+            .load(content, self.profile.mode == Mode::Draft)?;
+        let doc = loaded.map(|loaded| {
+            self.record_diagnostics(&item.path, &loaded.diagnostics);
+            loaded.doc
+        });
+
+        match doc {
+            None => Ok(ParsedDocument::Output(None)),
+            Some(mut doc) => {
+                if format.no_parse() {
+                    Ok(ParsedDocument::Output(Some(Document {
+                        meta: doc.meta,
+                        content: "".into(),
+                        code_outputs: doc.code_outputs,
+                    })))
+                } else if self.profile.mode != Mode::Draft && doc.meta.draft {
+                    Ok(ParsedDocument::Output(Some(doc.map(|_| CowStr::new()))))
+                } else if !doc
+                    .meta
+                    .exclude_outputs
+                    .as_ref()
+                    .map(|o| o.contains(&format.name().to_string()))
+                    .unwrap_or_default()
+                {
+                    let processor_ctx = PreprocessorContext {
+                        templates: &self.templates,
+                        output_format: format,
+                        project_root: self.project_path.clone(),
+                        profile: &self.profile_name,
+                        mode: self.profile.mode.as_str(),
+                        variables: &self.variables,
+                    };
+
+                    // Conditionals are evaluated first so that excluded content is never included.
+                    let doc_path = self.project_path.join("content").join(&item.path);
+                    let mut includes =
+                        Includes::new(&doc_path).with_conditions(Conditionals::new(&processor_ctx));
+                    includes.process(&mut doc)?;
+                    self.record_includes(&doc_path, format.name(), includes.included);
+
+                    Ok(ParsedDocument::Ast(
+                        self.profile.parser.parse(doc, &processor_ctx)?,
+                    ))
+                } else {
+                    Ok(ParsedDocument::Output(None))
+                }
+            }
+        }
+    }
+
+    fn render_parsed(
+        &self,
+        item: &DocumentDescriptor<Option<String>>,
+        parsed: ParsedDocument,
+        format: &dyn Format,
+        index: &ProjectIndex,
+    ) -> anyhow::Result<Option<Document<RenderResult>>> {
+        let mut doc = match parsed {
+            ParsedDocument::Ast(doc) => doc,
+            ParsedDocument::Output(output) => return Ok(output),
+        };
+
+        let current = self.document_key(&item.path);
+        let refs = ProjectReferences {
+            index: &index.references,
+            current: &current,
+        };
+        let numbering = index.numbering.get(&current);
+        let mut ctx = self.get_render_context(&mut doc, format, Some(refs), numbering)?;
+        let mut renderer = format.renderer();
+
+        Ok(Some(
+            renderer.render_doc(
+                &mut ctx,
+                build_extensions(
+                    self.profile
+                        .render_extensions
+                        .get(format.name())
+                        .unwrap_or(&vec![]),
+                )?,
+            )?,
+        ))
     }
 }
@@ -1,9 +1,11 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 
 use cdoc::config::Format;
 
+use cdoc::numbering::Numbering;
 use cdoc::package::Dependency;
 use cdoc::parser::{Parser, ParserSettings};
 
@@ -45,6 +47,14 @@ pub struct ProjectConfig {
 
     #[serde(default)]
     pub scripts: HashMap<String, String>,
+
+    /// BibTeX (`.bib`) or CSL-JSON (`.json`) files with the entries that can be cited. Paths are
+    /// relative to the project root.
+    #[serde(default)]
+    pub bibliography: Vec<PathBuf>,
+    /// Numbering schemes of labelled elements by type (e.g. `figure` or `equation`).
+    #[serde(default)]
+    pub numbering: HashMap<String, Numbering>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -64,6 +74,9 @@ pub struct Profile {
     pub render_extensions: HashMap<String, Vec<Box<dyn RenderExtensionConfig>>>,
     #[serde(default)]
     pub create_filters: bool,
+    /// Overrides values of the project's `custom` configuration when building with this profile.
+    #[serde(default)]
+    pub custom: HashMap<String, serde_yaml::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -98,10 +111,12 @@ fn default_profiles() -> HashMap<String, Profile> {
                 vec![Box::new(DocStructureConfig {
                     max_heading_level: 0,
                     included_commands: vec![],
+                    number_headings: false,
                 })
                     as Box<dyn RenderExtensionConfig + 'static>],
             )]),
             create_filters: true,
+            custom: HashMap::new(),
         },
     );
 
@@ -119,10 +134,12 @@ fn default_profiles() -> HashMap<String, Profile> {
                 vec![Box::new(DocStructureConfig {
                     max_heading_level: 0,
                     included_commands: vec![],
+                    number_headings: false,
                 })
                     as Box<dyn RenderExtensionConfig + 'static>],
             )]),
             create_filters: false,
+            custom: HashMap::new(),
         },
     );
 
@@ -139,6 +156,15 @@ pub enum Mode {
     Draft,
 }
 
+impl Mode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Release => "release",
+            Mode::Draft => "draft",
+        }
+    }
+}
+
 impl Default for Mode {
     fn default() -> Self {
         Self::Draft